@@ -5,14 +5,24 @@ use uuid::Uuid;
 
 use std::sync::{Arc, LazyLock};
 
+use futures::StreamExt;
 use kudrive_client::config_loader::{
-    self, get_nickname as get_nick, get_uuid, init_config as init_conf, set_config,
+    self, get_nickname as get_nick, get_uuid, init_config as init_conf, set_config, RateLimits,
 };
+use kudrive_client::event::{ActiveTransfer, FileResult};
 use kudrive_client::file_server::resolve_path;
 use kudrive_client::init as client_init;
-use kudrive_client::{clients, file_receive, file_send};
-use tracing_subscriber::EnvFilter;
+use kudrive_client::net::ku_protocol::FileError;
+use kudrive_client::net::relay::RelayState;
+use kudrive_client::net::route::PeerRoute;
+use kudrive_client::{
+    bump_transfer as bump, cancel_transfer as cancel, clients, file_receive, file_send,
+    pause_queue as pause, progress, rate_limits, relay_status, relays, resume_queue as resume,
+    routes, set_rate_limits as set_limits, transfers, ConflictPolicy,
+};
 use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tracing_subscriber::EnvFilter;
 
 static GLOBAL_STATE: LazyLock<Arc<Mutex<bool>>> = LazyLock::new(|| Arc::new(Mutex::new(true)));
 
@@ -68,11 +78,27 @@ async fn get_clients() -> Result<Vec<String>, String> {
 }
 
 #[tauri::command]
-async fn init_client() -> Result<(), String> {
+async fn init_client(app: AppHandle) -> Result<(), String> {
     let mut is_first = GLOBAL_STATE.lock().await;
     if *is_first {
         client_init().await;
         println!("Client initialized");
+
+        // forward transfer progress to the explorer
+        let mut progress = Box::pin(progress().await);
+        let progress_app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            while let Some(progress) = progress.next().await {
+                let _ = progress_app.emit("transfer-progress", progress);
+            }
+        });
+        // and relay connections as they come and go
+        let mut relay_status = Box::pin(relay_status().await);
+        tauri::async_runtime::spawn(async move {
+            while let Some(state) = relay_status.next().await {
+                let _ = app.emit("relay-status", state);
+            }
+        });
         *is_first = false;
         drop(is_first);
         return Ok(());
@@ -192,19 +218,74 @@ async fn get_foldermap() -> (
 }
 
 #[tauri::command]
-async fn send_file(id: Uuid, source: String, target: String) -> Result<(), String> {
+async fn send_file(
+    id: Uuid,
+    source: String,
+    target: String,
+    conflict: Option<ConflictPolicy>,
+) -> Result<(), FileError> {
     let source = resolve_path(source);
 
     println!("from {} to {} who {}", source, target, id);
 
-    file_send(id, source, target).await
+    file_send(id, source, target, conflict.unwrap_or_default()).await
 }
 
 #[tauri::command]
-async fn recive_file(id: Uuid, source: String, target: String) -> Result<(), String> {
+async fn recive_file(
+    id: Uuid,
+    source: String,
+    target: String,
+    conflict: Option<ConflictPolicy>,
+) -> Result<Vec<FileResult>, FileError> {
     let target = resolve_path(target);
     println!("from {} to {} who {}", source, target, id);
-    file_receive(id, source, target).await
+    file_receive(id, source, target, conflict.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn get_transfers() -> Result<Vec<ActiveTransfer>, String> {
+    transfers().await
+}
+
+#[tauri::command]
+async fn cancel_transfer(id: u64) -> Result<(), String> {
+    cancel(id).await
+}
+
+#[tauri::command]
+async fn bump_transfer(id: u64) -> Result<(), String> {
+    bump(id).await
+}
+
+#[tauri::command]
+async fn pause_queue() -> Result<(), String> {
+    pause().await
+}
+
+#[tauri::command]
+async fn resume_queue() -> Result<(), String> {
+    resume().await
+}
+
+#[tauri::command]
+async fn get_rate_limits() -> Result<RateLimits, String> {
+    rate_limits().await
+}
+
+#[tauri::command]
+async fn set_rate_limits(limits: RateLimits) -> Result<(), String> {
+    set_limits(limits).await
+}
+
+#[tauri::command]
+async fn get_routes() -> Result<Vec<PeerRoute>, String> {
+    routes().await
+}
+
+#[tauri::command]
+async fn get_relays() -> Result<Vec<RelayState>, String> {
+    relays().await
 }
 
 #[derive(Serialize)]
@@ -249,6 +330,15 @@ pub fn run() {
             get_foldermap,
             send_file,
             recive_file,
+            get_transfers,
+            cancel_transfer,
+            bump_transfer,
+            pause_queue,
+            resume_queue,
+            get_rate_limits,
+            set_rate_limits,
+            get_routes,
+            get_relays,
             get_workspace,
             get_clients,
             get_current_config
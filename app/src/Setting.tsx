@@ -7,6 +7,29 @@ interface DirectoryContents {
   folders: string[];
 }
 
+// bytes per second, null when unlimited
+interface RateLimits {
+  upload: number | null;
+  download: number | null;
+  peer_upload: number | null;
+  peer_download: number | null;
+}
+
+type RateLimitKey = keyof RateLimits;
+
+const rateLimitFields: [RateLimitKey, string][] = [
+  ['upload', '전체 업로드'],
+  ['download', '전체 다운로드'],
+  ['peer_upload', '기기별 업로드'],
+  ['peer_download', '기기별 다운로드'],
+];
+
+// shown in KB/s, empty when unlimited
+const toKilobytes = (limit: number | null) =>
+  limit ? Math.round(limit / 1024).toString() : '';
+const fromKilobytes = (value: string) =>
+  value.trim() && parseInt(value) > 0 ? parseInt(value) * 1024 : null;
+
 const Settings: React.FC = () => {
   const [isFirst, setIsFirst] = useState<boolean>(true);
   const [nickname, setNickname] = useState<string>('');
@@ -16,6 +39,12 @@ const Settings: React.FC = () => {
   const [hash, setHash] = useState<string>('');
   const [serverPort, setServerPort] = useState<string>('');
   const [p2pPort, setP2pPort] = useState<string>('');
+  const [rateLimits, setRateLimits] = useState<Record<RateLimitKey, string>>({
+    upload: '',
+    download: '',
+    peer_upload: '',
+    peer_download: '',
+  });
 
   const [openWorkspace, setOpenWorkspace] = useState<boolean>(false);
   const [folders, setFolders] = useState<string[]>([]);
@@ -56,6 +85,18 @@ const Settings: React.FC = () => {
         } catch (error) {
           console.error('설정값을 불러오는데 실패했습니다:', error);
         }
+
+        try {
+          const limits = await invoke<RateLimits>('get_rate_limits');
+          setRateLimits({
+            upload: toKilobytes(limits.upload),
+            download: toKilobytes(limits.download),
+            peer_upload: toKilobytes(limits.peer_upload),
+            peer_download: toKilobytes(limits.peer_download),
+          });
+        } catch (error) {
+          console.error('전송 속도 제한을 불러오는데 실패했습니다:', error);
+        }
       }
     };
     checkFirst();
@@ -107,6 +148,17 @@ const Settings: React.FC = () => {
   // 설정 저장
   const saveSetting = async () => {
     try {
+      // applied right away, unlike the settings below
+      if (!isFirst) {
+        await invoke('set_rate_limits', {
+          limits: {
+            upload: fromKilobytes(rateLimits.upload),
+            download: fromKilobytes(rateLimits.download),
+            peer_upload: fromKilobytes(rateLimits.peer_upload),
+            peer_download: fromKilobytes(rateLimits.peer_download),
+          },
+        });
+      }
       await invoke('init_config', {
         workspace,
         group,
@@ -375,7 +427,7 @@ const Settings: React.FC = () => {
 
               <div>
                 <label className="block text-sm font-medium text-gray-700 dark:text-gray-300 mb-1">
-                  해시 (선택)
+                  해시
                 </label>
                 <input
                   type="text"
@@ -384,7 +436,7 @@ const Settings: React.FC = () => {
                   className="w-full px-4 py-2 border rounded-lg focus:outline-none
                     focus:ring-2 focus:ring-blue-500 bg-white dark:bg-gray-700
                     border-gray-300 dark:border-gray-600 dark:text-gray-300"
-                  placeholder="해시를 입력하세요"
+                  placeholder="릴레이 서버의 Peer ID를 입력하세요"
                 />
               </div>
 
@@ -419,6 +471,35 @@ const Settings: React.FC = () => {
                   />
                 </div>
               </div>
+
+              {!isFirst && (
+                <div>
+                  <span className="block text-sm font-medium text-gray-700 dark:text-gray-300 mb-1">
+                    전송 속도 제한 (KB/s, 비우면 제한 없음)
+                  </span>
+                  <div className="grid grid-cols-2 gap-4">
+                    {rateLimitFields.map(([key, label]) => (
+                      <div key={key}>
+                        <label className="block text-sm text-gray-600 dark:text-gray-400 mb-1">
+                          {label}
+                        </label>
+                        <input
+                          type="number"
+                          min={0}
+                          value={rateLimits[key]}
+                          onChange={(e) =>
+                            setRateLimits({ ...rateLimits, [key]: e.target.value })
+                          }
+                          className="w-full px-4 py-2 border rounded-lg focus:outline-none
+                            focus:ring-2 focus:ring-blue-500 bg-white dark:bg-gray-700
+                            border-gray-300 dark:border-gray-600 dark:text-gray-300"
+                          placeholder="제한 없음"
+                        />
+                      </div>
+                    ))}
+                  </div>
+                </div>
+              )}
             </div>
           )}
         </div>
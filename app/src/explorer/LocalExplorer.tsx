@@ -2,7 +2,7 @@ import { VscChevronDown, VscChevronRight, VscFile } from 'react-icons/vsc'; // V
 import React, { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import './Explorer.css';
-import { Device, DirectoryContents } from './Types';
+import { Device, DirectoryContents, describeError } from './Types';
 import NavigationButtons from './components/NavigationButtons';
 
 interface FolderItemProps {
@@ -329,7 +329,7 @@ const FileExplorer: React.FC<FileExplorerProps> = ({
       }, 1500);
     } catch (error) {
       console.error('Error uploading file:', error);
-      alert('파일 업로드 중 에러가 발생했습니다: ' + error);
+      alert('파일 업로드 중 에러가 발생했습니다: ' + describeError(error));
     }
   };
 
@@ -5,10 +5,19 @@ import {
   VscFolder,
   VscFolderOpened,
 } from 'react-icons/vsc';
-import React, { useState, useEffect } from 'react';
+import React, { useState, useEffect, useRef } from 'react';
 import './Explorer.css';
 import { invoke } from '@tauri-apps/api/core';
-import { FolderNode } from './Types';
+import { listen } from '@tauri-apps/api/event';
+import {
+  ActiveTransfer,
+  FileResult,
+  FolderNode,
+  TransferProgress,
+  describeError,
+  fileErrorMessages,
+  formatBytes,
+} from './Types';
 
 interface RemoteExplorerProps {
   curDeviceName: string;
@@ -125,24 +134,28 @@ interface DownloadModalProps {
   isOpen: boolean;
   onClose: () => void;
   onConfirm: () => void;
+  onCancel: () => void;
   savePath: string;
   saveFileName: string;
   onFileNameChange: (name: string) => void;
   fileNameError: string;
   deviceName: string;
   isLoading: boolean;
+  progress: TransferProgress | null;
 }
 
 const DownloadModal: React.FC<DownloadModalProps> = ({
   isOpen,
   onClose,
   onConfirm,
+  onCancel,
   savePath,
   saveFileName,
   onFileNameChange,
   fileNameError,
   deviceName,
   isLoading,
+  progress,
 }) => {
   if (!isOpen) return null;
 
@@ -193,12 +206,28 @@ const DownloadModal: React.FC<DownloadModalProps> = ({
               </p>
             )}
           </div>
+          {isLoading && progress && progress.total > 0 && (
+            <div>
+              <div className="w-full h-2 bg-gray-200 dark:bg-gray-700 rounded">
+                <div
+                  className="h-2 bg-[#862633] rounded transition-all"
+                  style={{
+                    width: `${Math.min(100, (progress.bytes / progress.total) * 100)}%`,
+                  }}
+                />
+              </div>
+              <p className="mt-1 text-sm text-gray-600 dark:text-gray-400">
+                {formatBytes(progress.bytes)} / {formatBytes(progress.total)} ·{' '}
+                {formatBytes(progress.rate)}/s
+                {progress.eta !== null && ` · ${progress.eta}초 남음`}
+              </p>
+            </div>
+          )}
         </div>
 
         <div className="flex justify-end gap-2 mt-6">
           <button
-            onClick={onClose}
-            disabled={isLoading}
+            onClick={isLoading ? onCancel : onClose}
             className="px-4 py-2 text-gray-600 hover:text-gray-700 
               dark:text-gray-400 dark:hover:text-gray-300 transition-colors
               disabled:opacity-50 disabled:cursor-not-allowed"
@@ -284,6 +313,19 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
   const [showSuccess, setShowSuccess] = useState(false);
   const [isExiting, setIsExiting] = useState(false);
   const [isLoading, setIsLoading] = useState(false);
+  const [progress, setProgress] = useState<TransferProgress | null>(null);
+  const cancelled = useRef(false);
+
+  useEffect(() => {
+    const unlisten = listen<TransferProgress>('transfer-progress', (event) => {
+      if (event.payload.direction === 'Receive') {
+        setProgress(event.payload);
+      }
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
 
   useEffect(() => {
     const getWorkspace = async () => {
@@ -328,7 +370,7 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
           path: savePath,
         },
       );
-      return data.files.includes(fileName);
+      return data.files.includes(fileName) || data.folders.includes(fileName);
     } catch (error) {
       console.error('Error checking file:', error);
       return false;
@@ -340,6 +382,8 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
 
     try {
       setIsLoading(true);
+      setProgress(null);
+      cancelled.current = false;
       const exists = await checkFileExists(saveFileName);
 
       if (exists) {
@@ -348,12 +392,22 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
         return;
       }
 
-      await invoke('recive_file', {
+      const results = await invoke<FileResult[]>('recive_file', {
         id: deviceId,
         source: '.' + selectedPath?.substring(4),
         target: `./${saveFileName}`,
       });
 
+      const failures = results.flatMap((file) =>
+        'Err' in file.result
+          ? [`${file.path}: ${fileErrorMessages[file.result.Err.code]}`]
+          : [],
+      );
+      if (failures.length > 0) {
+        alert('파일 다운로드 실패:\n' + failures.join('\n'));
+        return;
+      }
+
       setShowModal(false);
       setSelectedPath(null);
       setSavePath('');
@@ -365,13 +419,30 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
         setShowSuccess(false);
       }, 1500);
     } catch (error) {
-      alert('파일 다운로드 중 에러 발생: ' + error);
+      if (!cancelled.current) {
+        alert('파일 다운로드 중 에러 발생: ' + describeError(error));
+      }
       console.error('Error downloading file:', error);
     } finally {
       setIsLoading(false);
     }
   };
 
+  const cancelDownload = async () => {
+    const source = '.' + selectedPath?.substring(4);
+    try {
+      const transfers = await invoke<ActiveTransfer[]>('get_transfers');
+      const transfer = transfers.find(
+        (t) => t.direction === 'Receive' && t.peer.source === source,
+      );
+      if (!transfer) return;
+      cancelled.current = true;
+      await invoke('cancel_transfer', { id: transfer.id });
+    } catch (error) {
+      console.error('Error cancelling download:', error);
+    }
+  };
+
   const buildFileAndFolderTree = (paths: string[]): FolderNode[] => {
     const tree: { [key: string]: FolderNode } = {};
     const root: FolderNode[] = [];
@@ -474,12 +545,14 @@ const RemoteExplorer: React.FC<RemoteExplorerProps> = ({
         isOpen={showModal}
         onClose={handleCloseModal}
         onConfirm={confirmDownload}
+        onCancel={cancelDownload}
         savePath={savePath}
         saveFileName={saveFileName}
         onFileNameChange={handleFileNameChange}
         fileNameError={fileNameError}
         deviceName={curDeviceName}
         isLoading={isLoading}
+        progress={progress}
       />
 
       <SuccessCheck show={showSuccess} />
@@ -21,6 +21,110 @@ export interface FolderNode {
   isFile?: boolean;
 }
 
+export type FileErrorCode =
+  | 'NotFound'
+  | 'PermissionDenied'
+  | 'ReadError'
+  | 'Rejected'
+  | 'TooLarge'
+  | 'WriteError'
+  | 'Corrupted'
+  | 'Network'
+  | 'Cancelled'
+  | 'Conflict'
+  | 'Incompatible'
+  | 'Internal';
+
+export type ConflictPolicy = 'Overwrite' | 'KeepBoth' | 'Skip' | 'Ask';
+
+export interface FileError {
+  code: FileErrorCode;
+  message: string;
+}
+
+export interface FileResult {
+  path: string;
+  result: { Ok: null } | { Err: FileError };
+  deduplicated: boolean;
+  skipped: boolean;
+}
+
+export interface ActiveTransfer {
+  id: number;
+  direction: 'Send' | 'Receive';
+  peer: {
+    id: string;
+    source: string;
+    target: string;
+    conflict: ConflictPolicy;
+  };
+  state: 'Queued' | 'Running';
+}
+
+// How a connected peer is reached, from `get_routes`
+export interface PeerRoute {
+  peer_id: string;
+  route: 'Direct' | 'Relayed';
+}
+
+// A lost relay is redialed on its own
+export type RelayStatus =
+  | 'Connecting'
+  | 'Connected'
+  | 'Reserved'
+  | { Disconnected: { retry_in_sec: number } };
+
+// From `get_relays`, one per configured relay, and from `relay-status` events as one changes
+export interface RelayState {
+  address: string;
+  status: RelayStatus;
+  rtt_ms: number | null;
+}
+
+export interface TransferProgress {
+  path: string;
+  direction: 'Send' | 'Receive';
+  bytes: number;
+  total: number;
+  rate: number;
+  eta: number | null;
+}
+
+export const formatBytes = (bytes: number): string => {
+  const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+};
+
+export const fileErrorMessages: Record<FileErrorCode, string> = {
+  NotFound: '파일을 찾을 수 없습니다',
+  PermissionDenied: '파일에 접근할 권한이 없습니다',
+  ReadError: '파일을 읽는 중 오류가 발생했습니다',
+  Rejected: '상대 기기가 요청을 거부했습니다',
+  TooLarge: '파일이 너무 큽니다',
+  WriteError: '파일을 저장하는 중 오류가 발생했습니다',
+  Corrupted: '받은 파일이 손상되었습니다',
+  Network: '네트워크 오류가 발생했습니다',
+  Cancelled: '전송이 취소되었습니다',
+  Conflict: '같은 이름의 파일이 이미 있습니다',
+  Incompatible: '상대 기기의 KUDRIVE 버전과 호환되지 않습니다',
+  Internal: '전송 요청을 처리하지 못했습니다',
+};
+
+// send_file and recive_file reject with a FileError
+export const describeError = (error: unknown): string => {
+  if (typeof error === 'object' && error !== null && 'code' in error) {
+    const { code, message } = error as FileError;
+    return fileErrorMessages[code] ?? message;
+  }
+  return String(error);
+};
+
 export const getOsIcon = (os: string) => {
   const osLower = os.toLowerCase();
 
@@ -1,23 +1,46 @@
 use std::{path::PathBuf, time::Duration};
 
+use super::scheduler::{Scheduler, TransferState};
 use crate::{
-    event::{ClientEvent, Command, Consequence},
+    event::{ClientEvent, Command, Consequence, Direction, Progress},
     file_server::FileServer,
-    net::{p2p::P2PTransport, server::Server},
+    net::{
+        ku_protocol::{ErrorCode, FileError},
+        p2p::P2PTransport,
+        relay::RelayState,
+        server::Server,
+    },
 };
 use futures::executor::block_on;
 use kudrive_common::{
     health::HealthChecker,
     message::{client::ClientMessage, server::ServerMessage, FileClaim},
+    p2p::load_or_create_keypair,
     pending::Pendings,
     Client,
 };
 
-use crate::config_loader::{get_relay_addr, get_uuid, get_workspace};
+use crate::config_loader::{
+    get_group_id, get_identity_path, get_ignore_list, get_rate_limits, get_relay_addrs,
+    get_transports, get_uuid, get_workspace, save_rate_limits, RateLimits,
+};
+use libp2p::PeerId;
 use tokio::sync::{
+    broadcast,
     mpsc::{self, error::TryRecvError, Receiver, Sender},
     oneshot,
 };
+use uuid::Uuid;
+
+const PROGRESS_BUFF_SIZE: usize = 1024;
+const RELAY_BUFF_SIZE: usize = 64;
+// every transfer shares the relay circuit, so only a few run at once
+const MAX_RUNNING_TRANSFERS: usize = 4;
+const MAX_PEER_TRANSFERS: usize = 2;
+const P2P_QUERY_TIMEOUT_SEC: u64 = 5;
+const IDENTITY_RETRY_SEC: u64 = 1;
+// longer than the remote may take to open the upload before it answers
+const CLAIM_TIMEOUT_SEC: u64 = 90;
 
 pub struct ClientHandler {
     sender: Sender<ClientEvent>,
@@ -28,6 +51,11 @@ pub struct ClientHandler {
     pub p2p_transport: P2PTransport,
     pendings: Pendings<oneshot::Sender<Consequence>>,
     clients: Vec<Client>,
+    progress: broadcast::Sender<Progress>,
+    relay_status: broadcast::Sender<RelayState>,
+    // sends and receives started here, until their consequence arrives
+    scheduler: Scheduler,
+    rate_limits: RateLimits,
 }
 
 impl ClientHandler {
@@ -37,8 +65,9 @@ impl ClientHandler {
         let (sender, receiver) = channel;
 
         let p2p_transport = P2PTransport::new(
-            get_relay_addr().as_str(),
-            get_uuid().to_string().as_str(),
+            &get_relay_addrs(),
+            load_or_create_keypair(&get_identity_path()).expect("Failed to load the p2p identity"),
+            get_transports(),
             sender.clone(),
             PathBuf::from(get_workspace()),
         )
@@ -47,6 +76,11 @@ impl ClientHandler {
         block_on(async {
             let _ = p2p_transport.warm_up_with_delay(6).await;
         });
+        let rate_limits = get_rate_limits();
+        block_on(async {
+            let _ = p2p_transport.set_rate_limits(rate_limits).await;
+            let _ = p2p_transport.set_ignore_list(get_ignore_list()).await;
+        });
 
         Self {
             server: Server::new(),
@@ -57,6 +91,10 @@ impl ClientHandler {
             health_checker: None,
             pendings: Pendings::new(),
             clients: Vec::new(),
+            progress: broadcast::channel(PROGRESS_BUFF_SIZE).0,
+            relay_status: broadcast::channel(RELAY_BUFF_SIZE).0,
+            scheduler: Scheduler::new(MAX_RUNNING_TRANSFERS, MAX_PEER_TRANSFERS),
+            rate_limits,
         }
     }
 
@@ -64,6 +102,14 @@ impl ClientHandler {
         self.sender.clone()
     }
 
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<Progress> {
+        self.progress.subscribe()
+    }
+
+    pub fn subscribe_relays(&self) -> broadcast::Receiver<RelayState> {
+        self.relay_status.subscribe()
+    }
+
     fn try_receive(&mut self) -> Result<ClientEvent, TryRecvError> {
         self.receiver.try_recv()
     }
@@ -72,6 +118,14 @@ impl ClientHandler {
         self.clients = clients;
     }
 
+    // Peers are dialed by the identity they registered with
+    fn peer_id(&self, id: Uuid) -> Option<PeerId> {
+        self.clients
+            .iter()
+            .find(|client| client.id == id)
+            .and_then(|client| client.identity.peer_id().ok())
+    }
+
     async fn transmit(&mut self, message: ClientMessage) {
         if let Err(_) = self.server.transmit(message).await {
             self.sender.send(ClientEvent::Unhealthy {}).await.unwrap();
@@ -92,6 +146,133 @@ impl ClientHandler {
         self.send_event(event).await;
     }
 
+    async fn get_transfers(&self, id: u64) {
+        let consequence = Consequence::Transfers {
+            result: Ok(self.scheduler.list()),
+        };
+
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+    }
+
+    async fn cancel_transfer(&mut self, id: u64, transfer: u64) {
+        let result = match self.scheduler.remove(transfer) {
+            Some((direction, peer, state)) => {
+                tracing::info!("Cancelling transfer {}: {:?}", transfer, peer);
+                if state == TransferState::Running {
+                    if let Err(e) = self.p2p_transport.cancel(transfer, direction, &peer).await {
+                        tracing::error!("Failed to cancel transfer {}: {:?}", transfer, e);
+                    }
+                }
+                // the aborted task will not answer, so resolve it here
+                if let Some(responder) = self.pendings.remove(transfer) {
+                    let error = FileError::new(ErrorCode::Cancelled, "Transfer cancelled");
+                    let consequence = match direction {
+                        Direction::Send => Consequence::FileSend { result: Err(error) },
+                        Direction::Receive => Consequence::FileReceive { result: Err(error) },
+                    };
+                    let _ = responder.send(consequence);
+                }
+                Ok(())
+            }
+            None => Err(format!("No such transfer: {}", transfer)),
+        };
+
+        let consequence = Consequence::CancelTransfer { result };
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+        self.dispatch().await;
+    }
+
+    async fn bump_transfer(&mut self, id: u64, transfer: u64) {
+        let result = match self.scheduler.bump(transfer) {
+            true => Ok(()),
+            false => Err(format!("Transfer is not queued: {}", transfer)),
+        };
+        let consequence = Consequence::BumpTransfer { result };
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+        self.dispatch().await;
+    }
+
+    async fn set_rate_limits(&mut self, id: u64, limits: RateLimits) {
+        let result = match self.p2p_transport.set_rate_limits(limits).await {
+            Ok(()) => {
+                self.rate_limits = limits;
+                save_rate_limits(limits)
+            }
+            Err(e) => Err(format!("Failed to apply rate limits: {:?}", e)),
+        };
+        let consequence = Consequence::SetRateLimits { result };
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+    }
+
+    async fn get_routes(&self, id: u64) {
+        let result = self
+            .p2p_transport
+            .routes(P2P_QUERY_TIMEOUT_SEC)
+            .await
+            .map_err(|e| format!("Failed to get routes: {:?}", e));
+        let consequence = Consequence::Routes { result };
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+    }
+
+    async fn get_relays(&self, id: u64) {
+        let result = self
+            .p2p_transport
+            .relays(P2P_QUERY_TIMEOUT_SEC)
+            .await
+            .map_err(|e| format!("Failed to get relays: {:?}", e));
+        let consequence = Consequence::Relays { result };
+        let event = ClientEvent::Consequence { id, consequence };
+        self.send_event(event).await;
+    }
+
+    // Fails receives the remote never answered, and starts the next ones in their place
+    async fn expire_claims(&mut self) {
+        let expired = self
+            .scheduler
+            .expire_claims(Duration::from_secs(CLAIM_TIMEOUT_SEC));
+        if expired.is_empty() {
+            return;
+        }
+        for (id, peer) in expired {
+            tracing::error!("File claim for transfer {} went unanswered: {:?}", id, peer);
+            let _ = self
+                .p2p_transport
+                .cancel(id, Direction::Receive, &peer)
+                .await;
+            if let Some(responder) = self.pendings.remove(id) {
+                let message = format!("Peer did not answer the file claim: {}", peer.id);
+                let error = FileError::new(ErrorCode::Network, message);
+                let _ = responder.send(Consequence::FileReceive { result: Err(error) });
+            }
+        }
+        self.dispatch().await;
+    }
+
+    // Starts whatever the scheduler lets through
+    async fn dispatch(&mut self) {
+        while let Some((id, direction, peer)) = self.scheduler.start_next() {
+            tracing::info!("Starting transfer {}: {:?}", id, peer);
+            match direction {
+                Direction::Send => {
+                    self.p2p_transport.send_open(true, id, peer).await;
+                }
+                Direction::Receive => {
+                    let message = ClientMessage::FileClaim {
+                        claim: FileClaim::SendClaim { pending: id },
+                        peer,
+                    };
+                    tracing::info!("Sending file claim: {:?}", message);
+                    self.transmit(message).await;
+                }
+            }
+        }
+    }
+
     async fn connect_server(&mut self) {
         // connect to server
         loop {
@@ -104,8 +285,19 @@ impl ClientHandler {
         }
 
         // register to server
+        let identity = loop {
+            let signed = self
+                .p2p_transport
+                .identity(get_group_id(), get_uuid(), P2P_QUERY_TIMEOUT_SEC)
+                .await;
+            match signed {
+                Ok(identity) => break identity,
+                Err(e) => tracing::error!("Failed to sign the p2p identity: {:?}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(IDENTITY_RETRY_SEC)).await;
+        };
         loop {
-            match self.server.register().await {
+            match self.server.register(identity.clone()).await {
                 Ok(_) => break,
                 Err(e) => {
                     tracing::error!("Failed to register to server: {:?}", e);
@@ -156,6 +348,18 @@ impl ClientHandler {
                     None => self.sender().send(ClientEvent::Unhealthy {}).await.unwrap(),
                 },
                 ServerMessage::ClientsUpdate { clients } => {
+                    // the server checked every identity's signature on registration
+                    let mut peers = Vec::new();
+                    let mut file_maps = Vec::new();
+                    for client in &clients {
+                        let Ok(peer) = client.identity.peer_id() else {
+                            continue;
+                        };
+                        peers.push((peer, client.identity.addresses()));
+                        file_maps.push((peer, client.files.clone()));
+                    }
+                    let _ = self.p2p_transport.update_group(peers).await;
+                    let _ = self.p2p_transport.update_holders(file_maps).await;
                     self.set_clients(clients);
                 }
                 ServerMessage::FileClaim { claim, peer } => match claim {
@@ -165,12 +369,33 @@ impl ClientHandler {
                     }
                     FileClaim::ReceiveClaim { pending } => {
                         tracing::info!("Received file RecieveClaim: {:?}", claim);
-                        self.p2p_transport.receive(pending, peer).await;
+                        if pending.is_some_and(|id| !self.scheduler.contains(id)) {
+                            tracing::info!("Transfer was cancelled, not receiving: {:?}", peer);
+                            return Ok(());
+                        }
+                        if let Some(id) = pending {
+                            self.scheduler.answered(id);
+                        }
+                        let Some(remote_peer_id) = self.peer_id(peer.id) else {
+                            tracing::error!("Peer has no registered identity: {:?}", peer);
+                            if let Some(id) = pending {
+                                let message = format!("Unknown peer: {}", peer.id);
+                                let error = FileError::new(ErrorCode::Rejected, message);
+                                let consequence = Consequence::FileReceive { result: Err(error) };
+                                self.send_event(ClientEvent::Consequence { id, consequence })
+                                    .await;
+                            }
+                            return Ok(());
+                        };
+                        self.p2p_transport
+                            .receive(pending, remote_peer_id, peer)
+                            .await;
                     }
                 },
             },
             ClientEvent::FileMapUpdate { file_map } => {
                 // TODO: implement file map update
+                let _ = self.p2p_transport.update_shares(file_map.clone()).await;
                 let message = ClientMessage::FileMapUpdate { file_map };
                 self.transmit(message).await;
             }
@@ -182,27 +407,74 @@ impl ClientHandler {
                         self.get_clients(id).await;
                     }
                     Command::FileSend { peer } => {
-                        self.p2p_transport.send_open(true, id, peer).await;
+                        self.scheduler.push(id, Direction::Send, peer);
+                        self.dispatch().await;
                     }
                     Command::FileReceive { peer } => {
-                        let message = ClientMessage::FileClaim {
-                            claim: FileClaim::SendClaim { pending: id },
-                            peer,
+                        self.scheduler.push(id, Direction::Receive, peer);
+                        self.dispatch().await;
+                    }
+                    Command::Transfers {} => {
+                        self.get_transfers(id).await;
+                    }
+                    Command::CancelTransfer { id: transfer } => {
+                        self.cancel_transfer(id, transfer).await;
+                    }
+                    Command::BumpTransfer { id: transfer } => {
+                        self.bump_transfer(id, transfer).await;
+                    }
+                    Command::PauseQueue {} => {
+                        tracing::info!("Transfer queue paused");
+                        self.scheduler.pause();
+                        let consequence = Consequence::PauseQueue { result: Ok(()) };
+                        self.send_event(ClientEvent::Consequence { id, consequence })
+                            .await;
+                    }
+                    Command::ResumeQueue {} => {
+                        tracing::info!("Transfer queue resumed");
+                        self.scheduler.resume();
+                        let consequence = Consequence::ResumeQueue { result: Ok(()) };
+                        self.send_event(ClientEvent::Consequence { id, consequence })
+                            .await;
+                        self.dispatch().await;
+                    }
+                    Command::RateLimits {} => {
+                        let consequence = Consequence::RateLimits {
+                            result: Ok(self.rate_limits),
                         };
-                        tracing::info!("Sending file claim: {:?}", message);
-                        self.transmit(message).await;
+                        self.send_event(ClientEvent::Consequence { id, consequence })
+                            .await;
+                    }
+                    Command::SetRateLimits { limits } => {
+                        self.set_rate_limits(id, limits).await;
+                    }
+                    Command::Routes {} => {
+                        self.get_routes(id).await;
+                    }
+                    Command::Relays {} => {
+                        self.get_relays(id).await;
                     }
                 }
             }
             ClientEvent::Consequence { id, consequence } => {
+                let finished = self.scheduler.remove(id).is_some();
                 if let Some(responder) = self.pendings.remove(id) {
                     responder.send(consequence).unwrap();
                 }
+                if finished {
+                    self.dispatch().await;
+                }
             }
             ClientEvent::Opened { ids, convey } => {
                 let (wid, rid) = ids;
                 let (peer, rx) = convey;
 
+                // cancelled while the upload was being opened
+                if let Some(id) = wid.filter(|id| !self.scheduler.contains(*id)) {
+                    let _ = self.p2p_transport.cancel(id, Direction::Send, &peer).await;
+                    return Ok(());
+                }
+
                 let message = ClientMessage::FileClaim {
                     claim: FileClaim::ReceiveClaim { pending: rid },
                     peer: peer.clone(),
@@ -211,8 +483,17 @@ impl ClientHandler {
 
                 self.p2p_transport.send_wait(wid, peer, rx).await;
             }
+            ClientEvent::Progress { progress } => {
+                // nobody listening is fine
+                let _ = self.progress.send(progress);
+            }
+            ClientEvent::Relay { state } => {
+                tracing::info!("Relay {} is {:?}", state.address, state.status);
+                let _ = self.relay_status.send(state);
+            }
             ClientEvent::Timer {} => {
                 self.transmit(ClientMessage::HealthCheck {}).await;
+                self.expire_claims().await;
             }
             ClientEvent::Unhealthy {} => {
                 tracing::info!("Server is unhealthy.");
@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use kudrive_common::Peer;
+use serde::Serialize;
+
+use crate::event::{ActiveTransfer, Direction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TransferState {
+    Queued,
+    Running,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    direction: Direction,
+    peer: Peer,
+    state: TransferState,
+    // a receive waits on the remote to answer its claim from then on
+    claimed: Option<Instant>,
+}
+
+// Decides when queued sends and receives may start. The queue is FIFO, except for
+// transfers bumped to its front, and a peer at its limit does not hold up the others.
+#[derive(Debug)]
+pub struct Scheduler {
+    transfers: HashMap<u64, Transfer>,
+    queue: VecDeque<u64>,
+    paused: bool,
+    max_running: usize,
+    max_per_peer: usize,
+}
+
+impl Scheduler {
+    pub fn new(max_running: usize, max_per_peer: usize) -> Self {
+        Self {
+            transfers: HashMap::new(),
+            queue: VecDeque::new(),
+            paused: false,
+            max_running,
+            max_per_peer,
+        }
+    }
+
+    pub fn push(&mut self, id: u64, direction: Direction, peer: Peer) {
+        let transfer = Transfer {
+            direction,
+            peer,
+            state: TransferState::Queued,
+            claimed: None,
+        };
+        self.transfers.insert(id, transfer);
+        self.queue.push_back(id);
+    }
+
+    // Moves a queued transfer to the front, false if it is not queued
+    pub fn bump(&mut self, id: u64) -> bool {
+        let Some(index) = self.queue.iter().position(|queued| *queued == id) else {
+            return false;
+        };
+        self.queue.remove(index);
+        self.queue.push_front(id);
+        true
+    }
+
+    // Finished or cancelled, either way it frees its slot
+    pub fn remove(&mut self, id: u64) -> Option<(Direction, Peer, TransferState)> {
+        let transfer = self.transfers.remove(&id)?;
+        if transfer.state == TransferState::Queued {
+            self.queue.retain(|queued| *queued != id);
+        }
+        Some((transfer.direction, transfer.peer, transfer.state))
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.transfers.contains_key(&id)
+    }
+
+    // Running transfers carry on, only new ones are held back
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    // The next transfer allowed to start, already marked as running
+    pub fn start_next(&mut self) -> Option<(u64, Direction, Peer)> {
+        if self.paused || self.running().count() >= self.max_running {
+            return None;
+        }
+        let index = self.queue.iter().position(|id| {
+            let peer = &self.transfers[id].peer;
+            self.running()
+                .filter(|transfer| transfer.peer.id == peer.id)
+                .count()
+                < self.max_per_peer
+        })?;
+        let id = self.queue.remove(index)?;
+        let transfer = self.transfers.get_mut(&id)?;
+        transfer.state = TransferState::Running;
+        if transfer.direction == Direction::Receive {
+            transfer.claimed = Some(Instant::now());
+        }
+        Some((id, transfer.direction, transfer.peer.clone()))
+    }
+
+    // The remote took up the claim, the transfer is no longer at risk of waiting forever
+    pub fn answered(&mut self, id: u64) {
+        if let Some(transfer) = self.transfers.get_mut(&id) {
+            transfer.claimed = None;
+        }
+    }
+
+    // Removes receives whose claim went unanswered for `timeout`, the server drops claims
+    // for peers that are offline, so they would hold their slot forever
+    pub fn expire_claims(&mut self, timeout: Duration) -> Vec<(u64, Peer)> {
+        let expired: Vec<u64> = self
+            .transfers
+            .iter()
+            .filter(|(_, transfer)| transfer.claimed.is_some_and(|at| at.elapsed() >= timeout))
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.remove(id).map(|(_, peer, _)| (id, peer)))
+            .collect()
+    }
+
+    // Running transfers first, then the queue in order
+    pub fn list(&self) -> Vec<ActiveTransfer> {
+        let running = self
+            .transfers
+            .iter()
+            .filter(|(_, transfer)| transfer.state == TransferState::Running)
+            .map(|(id, _)| *id);
+        running
+            .chain(self.queue.iter().copied())
+            .map(|id| {
+                let transfer = &self.transfers[&id];
+                ActiveTransfer {
+                    id,
+                    direction: transfer.direction,
+                    peer: transfer.peer.clone(),
+                    state: transfer.state,
+                }
+            })
+            .collect()
+    }
+
+    fn running(&self) -> impl Iterator<Item = &Transfer> {
+        self.transfers
+            .values()
+            .filter(|transfer| transfer.state == TransferState::Running)
+    }
+}
@@ -1,3 +1,4 @@
+use kudrive_common::p2p::Transports;
 use serde::{Deserialize, Serialize};
 use serde_yaml::{from_str, to_string};
 use std::path::PathBuf;
@@ -19,6 +20,12 @@ pub struct ServerConfig {
     pub p2p_port: u16,
     pub hash: String,
     pub p2p_relay_addr: String,
+    // further relays, in other regions for instance
+    #[serde(default)]
+    pub p2p_relay_addrs: Vec<String>,
+    // transports used towards the relay and other peers
+    #[serde(default)]
+    pub transports: Transports,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,6 +33,17 @@ pub struct FileConfig {
     pub workspace: String,
     pub refresh_time: u128,
     pub ignore_list: Vec<String>, // ignore_list를 Vec<String>으로 매핑
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+}
+
+// Transfer rate limits in bytes per second, unlimited when unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimits {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+    pub peer_upload: Option<u64>,
+    pub peer_download: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -84,9 +102,26 @@ pub async fn is_first_run() -> bool {
     !(path.exists() && path.is_file())
 }
 
-pub fn get_relay_addr() -> String {
+// The relay next to the control server first, then the further ones
+pub fn get_relay_addrs() -> Vec<String> {
+    let config = get_config();
+    let mut addresses = vec![config.server.p2p_relay_addr.clone()];
+    for address in &config.server.p2p_relay_addrs {
+        if !addresses.contains(address) {
+            addresses.push(address.clone());
+        }
+    }
+    addresses
+}
+
+// The libp2p identity lives next to config.yaml
+pub fn get_identity_path() -> PathBuf {
+    get_data_dir().with_file_name("identity.key")
+}
+
+pub fn get_transports() -> Transports {
     let config = get_config();
-    config.server.p2p_relay_addr.clone()
+    config.server.transports
 }
 
 pub fn get_nickname() -> String {
@@ -124,6 +159,25 @@ pub fn get_workspace() -> String {
     config.file.workspace.clone()
 }
 
+pub fn get_rate_limits() -> RateLimits {
+    let config = get_config();
+    config.file.rate_limits
+}
+
+// The file on disk, which may have changed since the config was loaded
+fn read_saved_config() -> Option<Config> {
+    let contents = fs::read_to_string(get_data_dir()).ok()?;
+    from_str::<Config>(&contents).ok()
+}
+
+// Rate limits change at runtime, so they are saved on their own
+pub fn save_rate_limits(rate_limits: RateLimits) -> Result<(), String> {
+    let mut config = read_saved_config().ok_or("Config file not found")?;
+    config.file.rate_limits = rate_limits;
+    let yaml_content = to_string(&config).map_err(|e| e.to_string())?;
+    fs::write(get_data_dir(), yaml_content).map_err(|e| e.to_string())
+}
+
 // Setter to update and save the configuration
 pub async fn set_config(
     workspace: String,
@@ -137,7 +191,10 @@ pub async fn set_config(
     let domain = domain.unwrap_or("127.0.0.1".to_string());
     let server_port = server_port.unwrap_or(7878);
     let p2p_port = p2p_port.unwrap_or(4001);
-    let hash = hash.unwrap_or("12D3KooWA768LzHMatxkjD1f9DrYW375GZJr6MHPCNEdDtHeTNRt".to_string());
+    // the relay's identity is random, so there is no default to fall back on
+    let hash = hash
+        .or_else(|| read_saved_config().map(|config| config.server.hash))
+        .ok_or("The relay's peer id (hash) is required")?;
 
     let new_config = Config {
         server: ServerConfig {
@@ -151,11 +208,22 @@ pub async fn set_config(
                 p2p_port,
                 hash.clone()
             ),
+            // not asked for by the setup, so kept like the rate limits
+            p2p_relay_addrs: read_saved_config()
+                .map(|config| config.server.p2p_relay_addrs)
+                .unwrap_or_default(),
+            transports: read_saved_config()
+                .map(|config| config.server.transports)
+                .unwrap_or_default(),
         },
         file: FileConfig {
             workspace,
             refresh_time: 600,
             ignore_list: vec![],
+            // kept when the other settings are saved again
+            rate_limits: read_saved_config()
+                .map(|config| config.file.rate_limits)
+                .unwrap_or_default(),
         },
         id: IdConfig {
             group_id: Uuid::new_v5(&Uuid::NAMESPACE_OID, group_name.as_bytes()),
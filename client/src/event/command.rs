@@ -1,16 +1,87 @@
 use kudrive_common::{Client, Peer};
+use serde::Serialize;
+
+use super::progress::Direction;
+use crate::client::scheduler::TransferState;
+use crate::config_loader::RateLimits;
+use crate::net::ku_protocol::FileError;
+use crate::net::relay::RelayState;
+use crate::net::route::PeerRoute;
 
 #[derive(Debug)]
 
 pub enum Command {
     Clients {},
+    // `peer.conflict` is honoured by whichever side receives
     FileSend { peer: Peer },
     FileReceive { peer: Peer },
+    Transfers {},
+    CancelTransfer { id: u64 },
+    BumpTransfer { id: u64 },
+    PauseQueue {},
+    ResumeQueue {},
+    RateLimits {},
+    SetRateLimits { limits: RateLimits },
+    Routes {},
+    Relays {},
 }
 
 #[derive(Debug)]
 pub enum Consequence {
-    Clients { result: Result<Vec<Client>, String> },
-    FileSend { result: Result<(), String> },
-    FileReceive { result: Result<(), String> },
+    Clients {
+        result: Result<Vec<Client>, String>,
+    },
+    FileSend {
+        result: Result<(), FileError>,
+    },
+    FileReceive {
+        result: Result<Vec<FileResult>, FileError>,
+    },
+    Transfers {
+        result: Result<Vec<ActiveTransfer>, String>,
+    },
+    CancelTransfer {
+        result: Result<(), String>,
+    },
+    BumpTransfer {
+        result: Result<(), String>,
+    },
+    PauseQueue {
+        result: Result<(), String>,
+    },
+    ResumeQueue {
+        result: Result<(), String>,
+    },
+    RateLimits {
+        result: Result<RateLimits, String>,
+    },
+    SetRateLimits {
+        result: Result<(), String>,
+    },
+    Routes {
+        result: Result<Vec<PeerRoute>, String>,
+    },
+    Relays {
+        result: Result<Vec<RelayState>, String>,
+    },
+}
+
+// Outcome of one file within a (possibly folder) transfer
+#[derive(Debug, Clone, Serialize)]
+pub struct FileResult {
+    pub path: String,
+    pub result: Result<(), FileError>,
+    // an identical copy was already in the workspace, nothing was transferred
+    pub deduplicated: bool,
+    // the target already existed and the conflict policy left it alone
+    pub skipped: bool,
+}
+
+// A send or receive queued or in flight; `id` is what `Command::CancelTransfer` takes
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveTransfer {
+    pub id: u64,
+    pub direction: Direction,
+    pub peer: Peer,
+    pub state: TransferState,
 }
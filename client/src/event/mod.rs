@@ -1,9 +1,14 @@
 pub mod command;
+pub mod progress;
 
-pub use command::{Command, Consequence};
+pub use command::{ActiveTransfer, Command, Consequence, FileResult};
 use kudrive_common::{event::Event, message::server::ServerMessage, FileMap, Peer};
+pub use progress::{Direction, Progress};
 use tokio::sync::oneshot;
 
+use crate::net::ku_protocol::FileError;
+use crate::net::relay::RelayState;
+
 #[derive(Debug)]
 pub enum ClientEvent {
     Message {
@@ -22,7 +27,13 @@ pub enum ClientEvent {
     },
     Opened {
         ids: (Option<u64>, Option<u64>),
-        convey: (Peer, oneshot::Receiver<Result<(), String>>),
+        convey: (Peer, oneshot::Receiver<Result<(), FileError>>),
+    },
+    Progress {
+        progress: Progress,
+    },
+    Relay {
+        state: RelayState,
     },
     Timer {},
     Unhealthy {},
@@ -0,0 +1,54 @@
+use serde::Serialize;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+// Snapshot of a single file transfer, emitted after every chunk
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub path: String,
+    pub direction: Direction,
+    pub bytes: u64,
+    pub total: u64,
+    // bytes per second since the transfer (or its resumption) started
+    pub rate: f64,
+    // seconds left at the current rate
+    pub eta: Option<u64>,
+}
+
+// Measures the rate of one transfer from where it started
+#[derive(Debug, Clone, Copy)]
+pub struct Meter {
+    started: Instant,
+    start_bytes: u64,
+}
+
+impl Meter {
+    pub fn new(start_bytes: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            start_bytes,
+        }
+    }
+
+    pub fn progress(&self, path: &str, direction: Direction, bytes: u64, total: u64) -> Progress {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = match elapsed > 0.0 {
+            true => bytes.saturating_sub(self.start_bytes) as f64 / elapsed,
+            false => 0.0,
+        };
+        let eta = (rate > 0.0).then(|| (total.saturating_sub(bytes) as f64 / rate).ceil() as u64);
+        Progress {
+            path: path.to_string(),
+            direction,
+            bytes,
+            total,
+            rate,
+            eta,
+        }
+    }
+}
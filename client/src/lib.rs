@@ -10,9 +10,18 @@ use std::{
 };
 
 use client::handler::ClientHandler;
-use event::{ClientEvent, Command, Consequence};
+use config_loader::RateLimits;
+use event::{ActiveTransfer, ClientEvent, Command, Consequence, FileResult, Progress};
+use futures::Stream;
+pub use kudrive_common::ConflictPolicy;
 use kudrive_common::{Client, Peer};
-use tokio::sync::{oneshot, Mutex};
+use net::ku_protocol::{ErrorCode, FileError};
+use net::relay::RelayState;
+use net::route::PeerRoute;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    oneshot, Mutex,
+};
 use tracing_subscriber::filter::LevelFilter;
 use uuid::Uuid;
 
@@ -76,25 +85,51 @@ pub async fn execute_command(command: Command) -> Result<Consequence, String> {
     }
 }
 
-pub async fn file_send(id: Uuid, source: String, target: String) -> Result<(), String> {
-    let peer = Peer { id, source, target };
+pub async fn file_send(
+    id: Uuid,
+    source: String,
+    target: String,
+    conflict: ConflictPolicy,
+) -> Result<(), FileError> {
+    let peer = Peer {
+        id,
+        source,
+        target,
+        conflict,
+    };
     let command = Command::FileSend { peer: peer.clone() };
 
     match execute_command(command).await {
         Ok(Consequence::FileSend { result }) => result,
-        Ok(_) => Err("Unexpected consequence".to_string()),
-        Err(e) => Err(e),
+        Ok(_) => Err(FileError::new(
+            ErrorCode::Internal,
+            "Unexpected consequence",
+        )),
+        Err(e) => Err(FileError::new(ErrorCode::Internal, e)),
     }
 }
 
-pub async fn file_receive(id: Uuid, source: String, target: String) -> Result<(), String> {
-    let peer = Peer { id, source, target };
+pub async fn file_receive(
+    id: Uuid,
+    source: String,
+    target: String,
+    conflict: ConflictPolicy,
+) -> Result<Vec<FileResult>, FileError> {
+    let peer = Peer {
+        id,
+        source,
+        target,
+        conflict,
+    };
     let command = Command::FileReceive { peer };
 
     match execute_command(command).await {
         Ok(Consequence::FileReceive { result }) => result,
-        Ok(_) => Err("Unexpected consequence".to_string()),
-        Err(e) => Err(e),
+        Ok(_) => Err(FileError::new(
+            ErrorCode::Internal,
+            "Unexpected consequence",
+        )),
+        Err(e) => Err(FileError::new(ErrorCode::Internal, e)),
     }
 }
 
@@ -108,6 +143,132 @@ pub async fn clients() -> Result<Vec<Client>, String> {
     }
 }
 
+pub async fn transfers() -> Result<Vec<ActiveTransfer>, String> {
+    let command = Command::Transfers {};
+
+    match execute_command(command).await {
+        Ok(Consequence::Transfers { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// The cancelled send or receive resolves with an error of its own
+pub async fn cancel_transfer(id: u64) -> Result<(), String> {
+    let command = Command::CancelTransfer { id };
+
+    match execute_command(command).await {
+        Ok(Consequence::CancelTransfer { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Lets a queued transfer start before everything queued ahead of it
+pub async fn bump_transfer(id: u64) -> Result<(), String> {
+    let command = Command::BumpTransfer { id };
+
+    match execute_command(command).await {
+        Ok(Consequence::BumpTransfer { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Holds back queued transfers; running ones are not interrupted
+pub async fn pause_queue() -> Result<(), String> {
+    let command = Command::PauseQueue {};
+
+    match execute_command(command).await {
+        Ok(Consequence::PauseQueue { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn resume_queue() -> Result<(), String> {
+    let command = Command::ResumeQueue {};
+
+    match execute_command(command).await {
+        Ok(Consequence::ResumeQueue { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn rate_limits() -> Result<RateLimits, String> {
+    let command = Command::RateLimits {};
+
+    match execute_command(command).await {
+        Ok(Consequence::RateLimits { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Takes effect right away and is saved to the config file
+pub async fn set_rate_limits(limits: RateLimits) -> Result<(), String> {
+    let command = Command::SetRateLimits { limits };
+
+    match execute_command(command).await {
+        Ok(Consequence::SetRateLimits { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Whether each connected peer is reached directly or through the relay
+pub async fn routes() -> Result<Vec<PeerRoute>, String> {
+    let command = Command::Routes {};
+
+    match execute_command(command).await {
+        Ok(Consequence::Routes { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Lost relays are redialed on their own, this only tells how far along that is
+pub async fn relays() -> Result<Vec<RelayState>, String> {
+    let command = Command::Relays {};
+
+    match execute_command(command).await {
+        Ok(Consequence::Relays { result }) => result,
+        Ok(_) => Err("Unexpected consequence".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+// Progress of every transfer from now on; a slow subscriber skips stale updates
+pub async fn progress() -> impl Stream<Item = Progress> {
+    let handler = GLOBAL_STATE.lock().await;
+    let receiver = handler.subscribe_progress();
+    drop(handler);
+
+    updates(receiver)
+}
+
+// Every change of a relay's state from now on, as `relays` would report it
+pub async fn relay_status() -> impl Stream<Item = RelayState> {
+    let handler = GLOBAL_STATE.lock().await;
+    let receiver = handler.subscribe_relays();
+    drop(handler);
+
+    updates(receiver)
+}
+
+fn updates<T: Clone>(receiver: broadcast::Receiver<T>) -> impl Stream<Item = T> {
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(update) => return Some((update, receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
 pub async fn shutdown() {
     let mut handler = GLOBAL_STATE.lock().await;
     handler.shutdown().await;
@@ -2,11 +2,13 @@
 // 클라이언트 개발 테스트 용도
 use dotenv::{dotenv, from_path};
 use kudrive_client::{
-    config_loader::get_config,
+    config_loader::{get_config, get_identity_path, get_relay_addrs},
     event_loop, file_receive, file_send, init,
+    net::ku_protocol::FileError,
     p2p::{cli_helpfn, run_cli_command, P2PTransport},
     shutdown,
 };
+use kudrive_common::p2p::load_or_create_keypair;
 use std::{env, path::PathBuf};
 use tokio::{
     io::{self, AsyncBufReadExt as _},
@@ -36,12 +38,13 @@ async fn run_p2p_cli() {
     let _ = from_path(".client.env");
     dotenv().ok();
 
-    let client_hostname = get_config().id.nickname.clone();
-    let relay_address = get_config().server.p2p_relay_addr.clone();
+    let keypair = load_or_create_keypair(&get_identity_path()).expect("Failed to load identity");
+    let relay_addresses = get_relay_addrs();
     let (tx, rx) = tokio::sync::mpsc::channel(1024);
     let mut p2p_client = P2PTransport::new(
-        &relay_address,
-        &client_hostname,
+        &relay_addresses,
+        keypair,
+        get_config().server.transports,
         tx,
         PathBuf::from(get_config().file.workspace.clone()),
     )
@@ -49,7 +52,7 @@ async fn run_p2p_cli() {
     let _ = p2p_client.connect_relay(10).await;
     let _ = p2p_client.listen_on_peer(10).await;
     let mut stdin = io::BufReader::new(io::stdin()).lines();
-    let mut send_rx: Option<Receiver<Result<(), String>>> = None;
+    let mut send_rx: Option<Receiver<Result<(), FileError>>> = None;
     cli_helpfn();
     loop {
         tokio::select! {
@@ -0,0 +1,73 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path},
+};
+
+use kudrive_common::FileMap;
+use libp2p::{Multiaddr, PeerId};
+
+use super::ku_protocol::{ErrorCode, FileError};
+
+// The file map names every shared path under this prefix instead of the workspace
+const SHARE_ROOT: &str = "home";
+
+// Who may pull from this node, and what
+#[derive(Debug, Default)]
+pub struct Access {
+    // group peers with the addresses they registered
+    peers: HashMap<PeerId, Vec<Multiaddr>>,
+    shared: HashSet<String>,
+}
+
+impl Access {
+    pub fn set_peers(&mut self, peers: Vec<(PeerId, Vec<Multiaddr>)>) {
+        self.peers = peers.into_iter().collect();
+    }
+
+    pub fn addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.peers.get(peer).cloned().unwrap_or_default()
+    }
+
+    // Only what was published in the file map is served
+    pub fn set_shares(&mut self, file_map: &FileMap) {
+        self.shared = shared_paths(file_map);
+    }
+
+    pub fn authorize(&self, peer: &PeerId, path: &str) -> Result<(), FileError> {
+        if !self.peers.contains_key(peer) {
+            let message = format!("Peer is not in this group: {}", peer);
+            return Err(FileError::new(ErrorCode::Rejected, message));
+        }
+        match normalize(path) {
+            Some(path) if self.shared.contains(&path) => Ok(()),
+            _ => {
+                let message = format!("Path is not shared: {}", path);
+                Err(FileError::new(ErrorCode::Rejected, message))
+            }
+        }
+    }
+}
+
+// Every path a file map publishes, as `normalize` would put it
+pub fn shared_paths(file_map: &FileMap) -> HashSet<String> {
+    let files = file_map.files.iter().map(|file| &file.name);
+    let folders = file_map.folders.iter().map(|folder| &folder.name);
+    files
+        .chain(folders)
+        .filter_map(|name| name.strip_prefix(SHARE_ROOT))
+        .filter_map(|name| normalize(name.trim_start_matches('/')))
+        .collect()
+}
+
+// Workspace-relative and '/'-joined, `None` unless every component is plain
+pub fn normalize(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
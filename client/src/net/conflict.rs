@@ -0,0 +1,71 @@
+use std::{fs, path::Path};
+
+use kudrive_common::ConflictPolicy;
+
+use super::ku_protocol::{ErrorCode, FileError};
+use super::sandbox;
+
+// Numbered names tried for `KeepBoth` before giving up
+const MAX_COPIES: u32 = 1000;
+
+// Where a received file is saved under `policy`, `None` when it is skipped
+pub fn resolve(
+    base_dir: &Path,
+    save_path: &str,
+    policy: ConflictPolicy,
+) -> Result<Option<String>, FileError> {
+    if !is_taken(&sandbox::resolve(base_dir, save_path)?) {
+        return Ok(Some(save_path.to_string()));
+    }
+    match policy {
+        ConflictPolicy::Overwrite => Ok(Some(save_path.to_string())),
+        ConflictPolicy::Skip => Ok(None),
+        ConflictPolicy::Ask => {
+            let message = format!("Already exists: {}", save_path);
+            Err(FileError::new(ErrorCode::Conflict, message))
+        }
+        ConflictPolicy::KeepBoth => {
+            for copy in 1..=MAX_COPIES {
+                let candidate = numbered(save_path, copy);
+                if !is_taken(&sandbox::resolve(base_dir, &candidate)?) {
+                    return Ok(Some(candidate));
+                }
+            }
+            let message = format!("No free name left for {}", save_path);
+            Err(FileError::new(ErrorCode::Conflict, message))
+        }
+    }
+}
+
+// A name differing only in case is the same file on some systems, so it counts too
+pub fn is_taken(path: &Path) -> bool {
+    if path.symlink_metadata().is_ok() {
+        return true;
+    }
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    fs::read_dir(parent)
+        .map(|entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)
+        })
+        .unwrap_or(false)
+}
+
+// "docs/report.pdf" becomes "docs/report (1).pdf", a leading dot is not an extension
+pub fn numbered(save_path: &str, copy: u32) -> String {
+    let (dir, name) = match save_path.rfind('/') {
+        Some(index) => save_path.split_at(index + 1),
+        None => ("", save_path),
+    };
+    match name.rfind('.').filter(|index| *index > 0) {
+        Some(index) => {
+            let (stem, extension) = name.split_at(index);
+            format!("{}{} ({}){}", dir, stem, copy, extension)
+        }
+        None => format!("{}{} ({})", dir, name, copy),
+    }
+}
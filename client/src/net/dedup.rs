@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::ku_protocol::FileMeta;
+use super::{meta, transfer};
+
+// how long one walk of the workspace answers which files have a given size, every file of
+// a folder transfer is looked up in it
+const SIZE_INDEX_TTL_SEC: u64 = 10;
+
+// Workspace files by size, as of one walk
+struct SizeIndex {
+    root: PathBuf,
+    walked: Instant,
+    files: HashMap<u64, Vec<PathBuf>>,
+}
+
+// Digests of workspace files, trusted for as long as their size and mtime stay the same.
+// Files are hashed without holding either lock, so lookups only wait on each other's walk
+#[derive(Clone, Default)]
+pub struct ContentIndex {
+    digests: Arc<Mutex<HashMap<PathBuf, (u64, SystemTime, String)>>>,
+    sizes: Arc<Mutex<Option<SizeIndex>>>,
+}
+
+impl ContentIndex {
+    // A workspace file with this content, only files of the same size are hashed
+    pub fn find(&self, root: &Path, size: u64, digest: &str) -> Option<PathBuf> {
+        let root = root.canonicalize().ok()?;
+        for path in self.same_size(&root, size) {
+            // the walk may be a few seconds old
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            if !metadata.is_file() || metadata.len() != size {
+                continue;
+            }
+            let cached = self
+                .digests()
+                .get(&path)
+                .filter(|(len, mtime, _)| *len == size && *mtime == modified)
+                .map(|(_, _, digest)| digest.clone());
+            let actual = match cached {
+                Some(digest) => digest,
+                None => match transfer::hash_file(&path) {
+                    Ok(digest) => {
+                        self.digests()
+                            .insert(path.clone(), (size, modified, digest.clone()));
+                        digest
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to hash {:?}: {}", path, e);
+                        continue;
+                    }
+                },
+            };
+            if actual == digest {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    // Whether any workspace file could hold content of `size` bytes, which is worth its digest
+    pub fn has_size(&self, root: &Path, size: u64) -> bool {
+        let Ok(root) = root.canonicalize() else {
+            return false;
+        };
+        !self.same_size(&root, size).is_empty()
+    }
+
+    fn same_size(&self, root: &Path, size: u64) -> Vec<PathBuf> {
+        let mut sizes = self.sizes.lock().expect("Size index poisoned");
+        let ttl = Duration::from_secs(SIZE_INDEX_TTL_SEC);
+        let fresh = sizes
+            .as_ref()
+            .is_some_and(|index| index.root == root && index.walked.elapsed() < ttl);
+        if !fresh {
+            *sizes = Some(SizeIndex {
+                root: root.to_path_buf(),
+                walked: Instant::now(),
+                files: walk(root),
+            });
+        }
+        sizes
+            .as_ref()
+            .and_then(|index| index.files.get(&size))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn digests(&self) -> MutexGuard<'_, HashMap<PathBuf, (u64, SystemTime, String)>> {
+        self.digests.lock().expect("Content index poisoned")
+    }
+}
+
+// Regular files under `root` by size, links are not followed
+fn walk(root: &Path) -> HashMap<u64, Vec<PathBuf>> {
+    let mut found: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                folders.push(path);
+            } else if metadata.is_file() && !transfer::is_partial(&path) {
+                found.entry(metadata.len()).or_default().push(path);
+            }
+        }
+    }
+    found
+}
+
+// Puts a copy of `source` at `target` with the sender's metadata, a link would let a later
+// edit of either file change the other
+pub fn place(root: &Path, source: &Path, target: &Path, file_meta: &FileMeta) -> io::Result<()> {
+    if source == target {
+        return meta::restore(root, target, file_meta);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let staged = transfer::partial_path(target);
+    let _ = fs::remove_file(&staged);
+    fs::copy(source, &staged)?;
+    fs::rename(&staged, target)?;
+    meta::restore(root, target, file_meta)
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+// Blocks are at least this large, and grow so a signature stays small enough to send
+const MIN_BLOCK_SIZE: u64 = 4 * 1024;
+const MAX_BLOCKS: u64 = 16 * 1024;
+const READ_BUF_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: [u8; 16],
+}
+
+// What the receiver already has, one entry per whole block of its copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signature {
+    pub block_size: u64,
+    pub blocks: Vec<BlockSignature>,
+}
+
+// Rebuilds the sender's file in order: `Copy` takes blocks of the receiver's copy,
+// `Literal` bytes still have to be pulled from the sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeltaOp {
+    Copy { block: u64, count: u64 },
+    Literal { length: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delta {
+    pub size: u64,
+    pub digest: String,
+    pub ops: Vec<DeltaOp>,
+}
+
+// The rsync weak checksum, cheap to roll forward one byte at a time
+struct Rolling {
+    a: u32,
+    b: u32,
+    length: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let length = window.len() as u32;
+        let (mut a, mut b) = (0u32, 0u32);
+        for (index, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((length - index as u32).wrapping_mul(*byte as u32));
+        }
+        Self { a, b, length }
+    }
+
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.length.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong(block: &[u8]) -> [u8; 16] {
+    let mut strong = [0; 16];
+    strong.copy_from_slice(&blake3::hash(block).as_bytes()[..16]);
+    strong
+}
+
+pub fn block_size(size: u64) -> u64 {
+    size.div_ceil(MAX_BLOCKS).max(MIN_BLOCK_SIZE)
+}
+
+// Signs the receiver's existing copy, a short last block is left out
+pub fn signature(path: &Path) -> io::Result<Signature> {
+    let mut file = File::open(path)?;
+    let block_size = block_size(file.metadata()?.len());
+    let mut block = vec![0; block_size as usize];
+    let mut blocks = Vec::new();
+    loop {
+        let mut filled = 0;
+        while filled < block.len() {
+            match file.read(&mut block[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        if filled < block.len() {
+            break;
+        }
+        blocks.push(BlockSignature {
+            weak: Rolling::new(&block).digest(),
+            strong: strong(&block),
+        });
+    }
+    Ok(Signature { block_size, blocks })
+}
+
+fn push_copy(ops: &mut Vec<DeltaOp>, index: u64) {
+    if let Some(DeltaOp::Copy { block, count }) = ops.last_mut() {
+        if *block + *count == index {
+            *count += 1;
+            return;
+        }
+    }
+    ops.push(DeltaOp::Copy {
+        block: index,
+        count: 1,
+    });
+}
+
+// Sender side: matches the receiver's blocks at any offset of `path`, hashing it on the way.
+// A window is held in memory, so blocks larger than `path` itself would use are refused
+pub fn diff(path: &Path, signature: &Signature) -> io::Result<Delta> {
+    let mut file = File::open(path)?;
+    let largest = block_size(file.metadata()?.len());
+    if !(MIN_BLOCK_SIZE..=largest).contains(&signature.block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Block size {} out of range", signature.block_size),
+        ));
+    }
+    let block_size = signature.block_size as usize;
+    let mut table: HashMap<u32, Vec<u64>> = HashMap::new();
+    for (index, block) in signature.blocks.iter().enumerate() {
+        table.entry(block.weak).or_default().push(index as u64);
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut read_buf = vec![0; READ_BUF_SIZE];
+    // `buf[pos]` is at `base + pos` in the file
+    let (mut base, mut pos) = (0u64, 0usize);
+    let mut eof = false;
+    let mut ops = Vec::new();
+    let mut literal_start = 0u64;
+    let mut rolling: Option<Rolling> = None;
+
+    loop {
+        // keep a whole window plus the byte rolled in next
+        if buf.len() - pos <= block_size && !eof {
+            buf.drain(..pos);
+            base += pos as u64;
+            pos = 0;
+            let read = file.read(&mut read_buf)?;
+            hasher.update(&read_buf[..read]);
+            buf.extend_from_slice(&read_buf[..read]);
+            eof = read == 0;
+            continue;
+        }
+        if buf.len() - pos < block_size {
+            break;
+        }
+
+        let window = &buf[pos..pos + block_size];
+        let weak = rolling.get_or_insert_with(|| Rolling::new(window)).digest();
+        let matched = table.get(&weak).and_then(|candidates| {
+            let strong = strong(window);
+            candidates
+                .iter()
+                .find(|index| signature.blocks[**index as usize].strong == strong)
+        });
+        if let Some(index) = matched {
+            let offset = base + pos as u64;
+            if offset > literal_start {
+                ops.push(DeltaOp::Literal {
+                    length: offset - literal_start,
+                });
+            }
+            push_copy(&mut ops, *index);
+            pos += block_size;
+            literal_start = offset + block_size as u64;
+            rolling = None;
+            continue;
+        }
+
+        if pos + block_size == buf.len() {
+            break;
+        }
+        if let Some(rolling) = rolling.as_mut() {
+            rolling.roll(buf[pos], buf[pos + block_size]);
+        }
+        pos += 1;
+    }
+
+    // drain whatever was not read yet, so the digest covers the whole file
+    if !eof {
+        io::copy(&mut file, &mut hasher)?;
+    }
+    let size = file.metadata()?.len();
+    if size > literal_start {
+        ops.push(DeltaOp::Literal {
+            length: size - literal_start,
+        });
+    }
+    Ok(Delta {
+        size,
+        digest: hasher.finalize().to_hex().to_string(),
+        ops,
+    })
+}
+
+// Receiver side: writes the copied blocks of `old` into `partial`, returning the
+// ranges left to pull. The last block is always among them, so the sender still
+// serves the final chunk that ends its upload.
+pub fn patch(
+    old: &Path,
+    partial: &Path,
+    block_size: u64,
+    delta: &Delta,
+) -> io::Result<Vec<(u64, u64)>> {
+    let mut old = File::open(old)?;
+    let mut partial = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(partial)?;
+    partial.set_len(delta.size)?;
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    let mut offset = 0;
+    for op in &delta.ops {
+        match *op {
+            DeltaOp::Copy { block, count } => {
+                let length = count * block_size;
+                old.seek(SeekFrom::Start(block * block_size))?;
+                partial.seek(SeekFrom::Start(offset))?;
+                let copied = io::copy(&mut (&mut old).take(length), &mut partial)?;
+                if copied != length {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Existing copy changed while patching",
+                    ));
+                }
+                offset += length;
+            }
+            DeltaOp::Literal { length } => {
+                ranges.push((offset, offset + length));
+                offset += length;
+            }
+        }
+    }
+    if offset != delta.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Delta covers {} of {} bytes", offset, delta.size),
+        ));
+    }
+    partial.flush()?;
+
+    let tail = delta.size.saturating_sub(block_size);
+    match ranges.last_mut() {
+        Some((_, end)) if *end == delta.size => {}
+        Some((_, end)) if *end >= tail => *end = delta.size,
+        _ => ranges.push((tail, delta.size)),
+    }
+    Ok(ranges)
+}
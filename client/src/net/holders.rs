@@ -0,0 +1,33 @@
+use std::collections::{HashMap, HashSet};
+
+use kudrive_common::FileMap;
+use libp2p::PeerId;
+
+use super::access::{normalize, shared_paths};
+
+// Which group peers publish which paths, so a download can find more sources
+#[derive(Debug, Default)]
+pub struct Holders {
+    shares: HashMap<PeerId, HashSet<String>>,
+}
+
+impl Holders {
+    pub fn set(&mut self, file_maps: Vec<(PeerId, FileMap)>) {
+        self.shares = file_maps
+            .iter()
+            .map(|(peer, file_map)| (*peer, shared_paths(file_map)))
+            .collect();
+    }
+
+    // Peers whose file map has `path`, the same name is not yet the same content
+    pub fn of(&self, path: &str) -> Vec<PeerId> {
+        let Some(path) = normalize(path) else {
+            return Vec::new();
+        };
+        self.shares
+            .iter()
+            .filter(|(_, shared)| shared.contains(&path))
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+}
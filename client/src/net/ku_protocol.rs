@@ -4,23 +4,308 @@ use futures::io::{AsyncRead, AsyncWrite};
 use libp2p::request_response::Codec;
 use libp2p::StreamProtocol;
 use serde::{Deserialize, Serialize};
-use std::io;
+use std::{fmt, io, time::SystemTime};
+
+use super::delta::{Delta, Signature};
+use super::version::ProtocolVersion;
+
+// Files are pulled in fixed-size chunks, one request per chunk
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+// Upper bounds on a single encoded message, so a peer cannot make us buffer more;
+// requests leave room for a delta signature
+const MAX_REQUEST_SIZE: u64 = 512 * 1024;
+const MAX_RESPONSE_SIZE: u64 = CHUNK_SIZE + 64 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileRequest {
     pub file_name: String,
     pub target_path: String,
     pub save_path: String,
+    pub offset: u64,
+    pub length: u64,
+    // asks the sender to drop the transfer instead of serving a chunk
+    pub cancel: bool,
+    // asks for the whole file's digest along with a zero-length probe
+    pub digest: bool,
+    // asks for a delta against the receiver's existing copy instead of a chunk, or
+    // without a signature for the one an earlier request started
+    pub delta: bool,
+    pub signature: Option<Signature>,
+    // tells the sender the receiver will not pull the file, with the receiver's
+    // outcome for it, so nothing is read
+    pub skip: Option<Result<(), FileError>>,
+    // arrived over 1.0.0, so the answer has to be the whole file
+    #[serde(skip)]
+    pub legacy: bool,
+}
+
+// One entry of a folder listing, relative to the requested folder and '/'-separated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderEntry {
+    pub path: String,
+    pub is_dir: bool,
+}
+
+// Why a transfer failed; WriteError, Corrupted, Network and Conflict are raised by the
+// receiving side itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    NotFound,
+    PermissionDenied,
+    ReadError,
+    Rejected,
+    TooLarge,
+    WriteError,
+    Corrupted,
+    Network,
+    Cancelled,
+    Conflict,
+    // the peer speaks no file transfer version this client does
+    Incompatible,
+    // the local client could not carry out the request at all
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl FileError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    // Classifies a failure to read a file being served
+    pub fn read(e: &io::Error, message: impl Into<String>) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            _ => ErrorCode::ReadError,
+        };
+        Self::new(code, message)
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileResponse {
-    // pub is_success: bool,
-    // pub message: String,
+    pub status: Result<(), FileError>,
     pub file_name: String,
     pub src_path: String,
     pub tgt_path: String,
+    pub offset: u64,
+    pub size: u64,
     pub content: Vec<u8>,
+    // BLAKE3 of the whole file, sent along with the last chunk
+    pub digest: Option<String>,
+    // set instead of content when the target is a folder
+    pub entries: Option<Vec<FolderEntry>>,
+    // answers a delta request, `None` while the sender is still computing it
+    pub delta: Option<Delta>,
+    // restored on save, sent along with the digest
+    pub meta: Option<FileMeta>,
+}
+
+// What a saved file keeps of the sender's copy besides its content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMeta {
+    pub modified: Option<SystemTime>,
+    // permission bits on unix, other systems only know `readonly`
+    pub mode: Option<u32>,
+    pub readonly: bool,
+    // relative target when the sender's path is a symbolic link
+    pub link: Option<String>,
+}
+
+// Paths inside a remote folder are always joined with '/'
+pub fn join_remote(folder: &str, path: &str) -> String {
+    format!("{}/{}", folder.trim_end_matches('/'), path)
+}
+
+async fn read_bounded<T>(io: &mut T, limit: u64) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut buf = Vec::new();
+    let bytes_read = futures::io::AsyncReadExt::read_to_end(
+        &mut futures::io::AsyncReadExt::take(io, limit + 1),
+        &mut buf,
+    )
+    .await?;
+    tracing::debug!("Bytes read: {}", bytes_read);
+    if bytes_read as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message exceeds {} bytes", limit),
+        ));
+    }
+    Ok(buf)
+}
+
+// The 1.0.0 messages: a request names a file, the response carries all of it
+#[derive(Serialize, Deserialize)]
+struct LegacyRequest {
+    file_name: String,
+    target_path: String,
+    save_path: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyResponse {
+    file_name: String,
+    src_path: String,
+    tgt_path: String,
+    content: Vec<u8>,
+}
+
+// 1.0.0 peers report a failure in place of the file name, with no content
+const LEGACY_ERROR_PREFIX: &str = "KUDrive Error: ";
+const LEGACY_ERRORS: [(ErrorCode, &str); 3] = [
+    (ErrorCode::NotFound, "File Not Found"),
+    (ErrorCode::TooLarge, "File too large"),
+    (ErrorCode::ReadError, "File read error"),
+];
+
+fn legacy_unsupported(what: &str) -> io::Error {
+    let message = format!("{} is not supported by /ku-file-transfer/1.0.0", what);
+    io::Error::new(io::ErrorKind::Unsupported, message)
+}
+
+impl From<LegacyRequest> for FileRequest {
+    // Asks for the first chunk, which has to be the whole file to be answered
+    fn from(request: LegacyRequest) -> Self {
+        FileRequest {
+            file_name: request.file_name,
+            target_path: request.target_path,
+            save_path: request.save_path,
+            offset: 0,
+            length: CHUNK_SIZE,
+            cancel: false,
+            digest: false,
+            delta: false,
+            signature: None,
+            skip: None,
+            legacy: true,
+        }
+    }
+}
+
+impl TryFrom<FileRequest> for LegacyRequest {
+    type Error = io::Error;
+
+    // Only reading a file from its start can be asked for, probes, listings and the
+    // like fail instead
+    fn try_from(request: FileRequest) -> io::Result<Self> {
+        let plain = request.offset == 0
+            && request.length > 0
+            && !request.cancel
+            && !request.digest
+            && !request.delta
+            && request.signature.is_none()
+            && request.skip.is_none();
+        if !plain {
+            return Err(legacy_unsupported("This request"));
+        }
+        Ok(LegacyRequest {
+            file_name: request.file_name,
+            target_path: request.target_path,
+            save_path: request.save_path,
+        })
+    }
+}
+
+impl From<LegacyResponse> for FileResponse {
+    // The whole file arrived, so its digest is that of the content
+    fn from(response: LegacyResponse) -> Self {
+        if let Some(reason) = response.file_name.strip_prefix(LEGACY_ERROR_PREFIX) {
+            let code = LEGACY_ERRORS
+                .iter()
+                .find(|(_, known)| *known == reason)
+                .map_or(ErrorCode::ReadError, |(code, _)| *code);
+            let message = format!("{}: {}", reason, response.src_path);
+            return FileResponse {
+                status: Err(FileError::new(code, message)),
+                file_name: response.file_name,
+                src_path: response.src_path,
+                tgt_path: response.tgt_path,
+                offset: 0,
+                size: 0,
+                digest: None,
+                content: Vec::new(),
+                entries: None,
+                delta: None,
+                meta: None,
+            };
+        }
+        FileResponse {
+            status: Ok(()),
+            file_name: response.file_name,
+            src_path: response.src_path,
+            tgt_path: response.tgt_path,
+            offset: 0,
+            size: response.content.len() as u64,
+            digest: Some(blake3::hash(&response.content).to_hex().to_string()),
+            content: response.content,
+            entries: None,
+            delta: None,
+            meta: None,
+        }
+    }
+}
+
+impl TryFrom<FileResponse> for LegacyResponse {
+    type Error = io::Error;
+
+    // Failures are put the way 1.0.0 peers report them, a file larger than the response
+    // is refused as too large. Listings and deltas are never asked for over 1.0.0
+    fn try_from(response: FileResponse) -> io::Result<Self> {
+        if response.entries.is_some() || response.delta.is_some() {
+            return Err(legacy_unsupported("This response"));
+        }
+        let code = match response.status {
+            Err(error) => Some(error.code),
+            Ok(()) if response.offset != 0 || response.content.len() as u64 != response.size => {
+                Some(ErrorCode::TooLarge)
+            }
+            Ok(()) => None,
+        };
+        if let Some(code) = code {
+            let reason = LEGACY_ERRORS
+                .iter()
+                .find(|(known, _)| *known == code)
+                .map_or("File read error", |(_, reason)| *reason);
+            return Ok(LegacyResponse {
+                file_name: format!("{}{}", LEGACY_ERROR_PREFIX, reason),
+                src_path: response.src_path,
+                tgt_path: response.tgt_path,
+                content: Vec::new(),
+            });
+        }
+        Ok(LegacyResponse {
+            file_name: response.file_name,
+            src_path: response.src_path,
+            tgt_path: response.tgt_path,
+            content: response.content,
+        })
+    }
+}
+
+// Only versions in `ProtocolVersion::SUPPORTED` are offered, so any other one is refused
+fn wire_version(protocol: &StreamProtocol) -> io::Result<ProtocolVersion> {
+    ProtocolVersion::of(protocol).ok_or_else(|| {
+        let message = format!("Unsupported protocol {}", protocol);
+        io::Error::new(io::ErrorKind::Unsupported, message)
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -32,14 +317,23 @@ impl Codec for KuFileTransferCodec {
     type Request = FileRequest;
     type Response = FileResponse;
 
-    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    async fn read_request<T>(
+        &mut self,
+        protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
     where
         T: AsyncRead + Unpin + Send,
     {
-        let mut buf = Vec::new();
-        let bytes_read = futures::io::AsyncReadExt::read_to_end(io, &mut buf).await?;
-        tracing::debug!("Bytes read: {}", bytes_read);
-        bincode::deserialize(&buf).map_err(|e| {
+        let version = wire_version(protocol)?;
+        let buf = read_bounded(io, MAX_REQUEST_SIZE).await?;
+        let request = match version {
+            ProtocolVersion::V1 => {
+                bincode::deserialize::<LegacyRequest>(&buf).map(FileRequest::from)
+            }
+            ProtocolVersion::V2 => bincode::deserialize(&buf),
+        };
+        request.map_err(|e| {
             tracing::error!("Deserialization error: {:?}", e);
             io::Error::new(io::ErrorKind::InvalidData, "Failed to deserialize request")
         })
@@ -47,35 +341,39 @@ impl Codec for KuFileTransferCodec {
 
     async fn read_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
     ) -> io::Result<Self::Response>
     where
         T: AsyncRead + Unpin + Send,
     {
-        let mut buf = Vec::new();
-        let bytes_read = futures::io::AsyncReadExt::read_to_end(io, &mut buf).await?;
-        tracing::debug!("Bytes read: {}", bytes_read);
-
-        match bincode::deserialize(&buf) {
-            Ok(response) => Ok(response),
-            Err(e) => {
-                tracing::error!("Deserialization failed: {:?}", e);
-                Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        let version = wire_version(protocol)?;
+        let buf = read_bounded(io, MAX_RESPONSE_SIZE).await?;
+        let response = match version {
+            ProtocolVersion::V1 => {
+                bincode::deserialize::<LegacyResponse>(&buf).map(FileResponse::from)
             }
-        }
+            ProtocolVersion::V2 => bincode::deserialize(&buf),
+        };
+        response.map_err(|e| {
+            tracing::error!("Deserialization failed: {:?}", e);
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        })
     }
 
     async fn write_request<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         request: Self::Request,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = bincode::serialize(&request).unwrap();
+        let data = match wire_version(protocol)? {
+            ProtocolVersion::V1 => bincode::serialize(&LegacyRequest::try_from(request)?).unwrap(),
+            ProtocolVersion::V2 => bincode::serialize(&request).unwrap(),
+        };
         tracing::debug!("Serialized request size: {}", data.len());
         futures::io::AsyncWriteExt::write_all(io, &data).await?;
         tracing::debug!("Request written successfully.");
@@ -84,14 +382,19 @@ impl Codec for KuFileTransferCodec {
 
     async fn write_response<T>(
         &mut self,
-        _: &Self::Protocol,
+        protocol: &Self::Protocol,
         io: &mut T,
         response: Self::Response,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        let data = bincode::serialize(&response).unwrap();
+        let data = match wire_version(protocol)? {
+            ProtocolVersion::V1 => {
+                bincode::serialize(&LegacyResponse::try_from(response)?).unwrap()
+            }
+            ProtocolVersion::V2 => bincode::serialize(&response).unwrap(),
+        };
         tracing::debug!("Serialized response size: {}", data.len());
         futures::io::AsyncWriteExt::write_all(io, &data).await?;
         tracing::debug!("Response written successfully.");
@@ -99,6 +402,11 @@ impl Codec for KuFileTransferCodec {
     }
 }
 
+// Whether the encoded response stays within what the receiver is willing to read
+pub fn fits_response(response: &FileResponse) -> bool {
+    bincode::serialized_size(response).is_ok_and(|size| size <= MAX_RESPONSE_SIZE)
+}
+
 // #[derive(Debug, Clone, Serialize, Deserialize)]
 // pub struct Message(pub String);
 
@@ -0,0 +1,51 @@
+use std::collections::{HashMap, HashSet};
+
+use libp2p::{Multiaddr, PeerId};
+
+// Peers mDNS found on the local network. They are dialed directly, the relay is only
+// the fallback when that fails.
+#[derive(Default)]
+pub struct LocalPeers {
+    addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    // direct dials still in flight
+    dialing: HashSet<PeerId>,
+}
+
+impl LocalPeers {
+    // True if the address is new
+    pub fn discovered(&mut self, peer: PeerId, address: Multiaddr) -> bool {
+        let addresses = self.addresses.entry(peer).or_default();
+        if addresses.contains(&address) {
+            return false;
+        }
+        addresses.push(address);
+        true
+    }
+
+    pub fn expired(&mut self, peer: &PeerId, address: &Multiaddr) {
+        if let Some(addresses) = self.addresses.get_mut(peer) {
+            addresses.retain(|known| known != address);
+            if addresses.is_empty() {
+                self.addresses.remove(peer);
+            }
+        }
+    }
+
+    pub fn addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.addresses.get(peer).cloned().unwrap_or_default()
+    }
+
+    pub fn dialing(&mut self, peer: PeerId) {
+        self.dialing.insert(peer);
+    }
+
+    // Clears a direct dial once the peer is connected one way or another
+    pub fn connected(&mut self, peer: &PeerId) {
+        self.dialing.remove(peer);
+    }
+
+    // True if the failed dial was a direct one, which is then retried through the relay
+    pub fn dial_failed(&mut self, peer: &PeerId) -> bool {
+        self.dialing.remove(peer)
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs, io,
+    path::{Component, Path},
+};
+
+use super::ku_protocol::FileMeta;
+
+// Sender side: `literal` is the served path before links are resolved, `resolved` after
+pub fn read(literal: &Path, resolved: &Path) -> io::Result<FileMeta> {
+    let metadata = fs::metadata(resolved)?;
+    // only relative links mean the same thing on the receiver
+    let link = fs::symlink_metadata(literal)
+        .ok()
+        .filter(|metadata| metadata.file_type().is_symlink())
+        .and_then(|_| fs::read_link(literal).ok())
+        .filter(|target| target.is_relative())
+        .map(|target| target.to_string_lossy().to_string());
+    Ok(FileMeta {
+        modified: metadata.modified().ok(),
+        mode: mode(&metadata),
+        readonly: metadata.permissions().readonly(),
+        link,
+    })
+}
+
+// Whether `path` already carries the metadata, so it can be shared as is
+pub fn matches(path: &Path, meta: &FileMeta) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    meta.link.is_none()
+        && metadata.modified().ok() == meta.modified
+        && mode(&metadata) == meta.mode
+        && metadata.permissions().readonly() == meta.readonly
+}
+
+// Receiver side: a saved file becomes a link again where it can, otherwise it gets
+// the sender's permissions and modification time
+pub fn restore(root: &Path, path: &Path, meta: &FileMeta) -> io::Result<()> {
+    if let Some(ref link) = meta.link {
+        if relink(root, path, link)? {
+            return Ok(());
+        }
+    }
+    // times first, a read-only file can no longer be opened to set them
+    if let Some(modified) = meta.modified {
+        fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
+    }
+    let mut permissions = fs::metadata(path)?.permissions();
+    set_mode(&mut permissions, meta);
+    fs::set_permissions(path, permissions)
+}
+
+// Replaces the saved file with a link to `link`, if that points inside the workspace
+// and is there already; a link to something missing would lose the content
+fn relink(root: &Path, path: &Path, link: &str) -> io::Result<bool> {
+    let relative = Path::new(link);
+    let plain = relative.components().all(|component| {
+        matches!(
+            component,
+            Component::Normal(_) | Component::CurDir | Component::ParentDir
+        )
+    });
+    let Some(parent) = path.parent().filter(|_| plain) else {
+        return Ok(false);
+    };
+    let Ok(target) = parent.join(relative).canonicalize() else {
+        return Ok(false);
+    };
+    if !target.starts_with(root.canonicalize()?) || target == path {
+        return Ok(false);
+    }
+    fs::remove_file(path)?;
+    symlink(relative, path)?;
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn mode(_: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// Plain permission bits only, a peer does not get to set setuid and the like
+#[cfg(unix)]
+fn set_mode(permissions: &mut fs::Permissions, meta: &FileMeta) {
+    use std::os::unix::fs::PermissionsExt;
+    match meta.mode {
+        Some(mode) => permissions.set_mode(mode & 0o777),
+        None => permissions.set_readonly(meta.readonly),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(permissions: &mut fs::Permissions, meta: &FileMeta) {
+    permissions.set_readonly(meta.readonly);
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "No symbolic links",
+    ))
+}
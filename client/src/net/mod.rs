@@ -1,3 +1,16 @@
+pub mod access;
+pub mod conflict;
+pub mod dedup;
+pub mod delta;
+pub mod holders;
 pub mod ku_protocol;
+pub mod lan;
+pub mod meta;
 pub mod p2p;
+pub mod relay;
+pub mod route;
+pub mod sandbox;
 pub mod server;
+pub mod throttle;
+pub mod transfer;
+pub mod version;
@@ -1,30 +1,72 @@
 use tokio::{sync::oneshot, time};
 
-use kudrive_common::Peer;
+use kudrive_common::{ConflictPolicy, FileMap, Peer};
 use tokio::sync::mpsc::Sender;
 
-use crate::event::{ClientEvent, Consequence};
+use crate::config_loader::RateLimits;
+use crate::event::{ClientEvent, Consequence, Direction, FileResult, Progress};
 
-use futures::{executor::block_on, future::FutureExt, stream::StreamExt};
-use kudrive_common::p2p::generate_ed25519;
+use futures::{
+    executor::block_on,
+    future::{self, FutureExt},
+    stream::StreamExt,
+    Future,
+};
+use kudrive_common::p2p::{
+    listen_addresses, prefer_quic, quic_address, transport, PeerIdentity, Transports,
+    IDENTIFY_PROTOCOL,
+};
 use libp2p::{
-    core::multiaddr::{Multiaddr, Protocol},
-    dcutr, identify, noise, ping, relay,
-    request_response::{self, ProtocolSupport},
-    swarm::{DialError, NetworkBehaviour, SwarmEvent},
-    tcp, yamux, PeerId, StreamProtocol, Swarm,
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        transport::ListenerId,
+    },
+    dcutr, identify, identity, mdns, noise, ping, relay,
+    request_response::{self, InboundFailure, OutboundFailure, OutboundRequestId, ProtocolSupport},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        DialError, NetworkBehaviour, SwarmEvent,
+    },
+    yamux, PeerId, Swarm,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZero,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use std::{
+    error::Error,
+    time::{Duration, Instant},
 };
-use std::{collections::HashMap, num::NonZero, path::PathBuf, str::FromStr};
-use std::{error::Error, time::Duration};
 use tokio::{
-    fs::File,
-    io::AsyncReadExt,
     select,
     sync::mpsc::{self, Receiver},
+    task::AbortHandle,
 };
 use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
-use super::ku_protocol::{self, KuFileTransferCodec};
+use super::access::Access;
+use super::conflict;
+use super::dedup::{self, ContentIndex};
+use super::delta::{self, Delta, Signature};
+use super::holders::Holders;
+use super::ku_protocol::{
+    join_remote, ErrorCode, FileError, FileMeta, FileRequest, KuFileTransferCodec, CHUNK_SIZE,
+};
+use super::lan::LocalPeers;
+use super::relay::{peer_id as relay_peer_id, RelayState, Relays};
+use super::route::{PeerRoute, Route, Routes};
+use super::sandbox;
+use super::throttle::Throttle;
+use super::transfer::{
+    self, Download, DownloadKey, FileProbe, FolderListing, Mirrors, Patch, TransferEvent,
+    Transfers, Upload,
+};
+use super::version::{PeerVersions, ProtocolVersion};
 
 // Swarm config
 const SWARM_IDLE_TIMEOUT: u64 = 60;
@@ -37,6 +79,10 @@ const MAX_DIAL_RETRY: usize = 3;
 const INIT_LISTEN_DELAY: u64 = 2;
 const CMD_BUFF_SIZE: usize = 10000;
 const REQUEST_TIMEOUT_SEC: u64 = 50;
+const TRANSFER_STALL_CHECK_SEC: u64 = 5;
+const MIRROR_PROBE_TIMEOUT_SEC: u64 = 10;
+const DELTA_POLL_INTERVAL_MS: u64 = 500;
+const RELAY_CHECK_SEC: u64 = 1;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum P2pStatus {
@@ -51,6 +97,8 @@ pub struct Behaviour {
     ping: ping::Behaviour,
     identify: identify::Behaviour,
     dcutr: dcutr::Behaviour,
+    // off where the host cannot do multicast, everything then goes through the relay
+    mdns: Toggle<mdns::tokio::Behaviour>,
     ku_file_transfer: request_response::Behaviour<KuFileTransferCodec>,
     // ku_messaging: request_response::Behaviour<MessagingCodec>,
 }
@@ -69,6 +117,12 @@ pub enum P2pCommand {
     GetListenAddr {
         response_tx: oneshot::Sender<Vec<String>>,
     },
+    GetRoutes {
+        response_tx: oneshot::Sender<Vec<PeerRoute>>,
+    },
+    GetRelays {
+        response_tx: oneshot::Sender<Vec<RelayState>>,
+    },
     ConnectToRelay {
         response_tx: oneshot::Sender<Result<(), String>>,
     },
@@ -83,30 +137,113 @@ pub enum P2pCommand {
         remote_peer_id: String,
         src_path: String,
         tgt_path: String,
-        response_tx: oneshot::Sender<Result<(), String>>,
+        mirrors: Mirrors,
+        patch: Option<Patch>,
+        transfer: Option<u64>,
+        response_tx: oneshot::Sender<Result<(), FileError>>,
     },
     SendFileOpen {
         src_path: String,
-        response_tx: oneshot::Sender<Result<(), String>>,
+        response_tx: oneshot::Sender<Result<(), FileError>>,
+    },
+    ListFolder {
+        remote_peer_id: PeerId,
+        src_path: String,
+        response_tx: oneshot::Sender<FolderListing>,
+    },
+    ProbeFile {
+        remote_peer_id: PeerId,
+        src_path: String,
+        digest: bool,
+        response_tx: oneshot::Sender<FileProbe>,
+    },
+    SkipFile {
+        remote_peer_id: PeerId,
+        src_path: String,
+        outcome: Result<(), FileError>,
+        response_tx: oneshot::Sender<Result<(), FileError>>,
+    },
+    // `None` asks again for the delta an earlier signature started
+    RequestDelta {
+        remote_peer_id: PeerId,
+        src_path: String,
+        signature: Option<Signature>,
+        response_tx: oneshot::Sender<Result<Option<Delta>, FileError>>,
+    },
+    FindHolders {
+        src_path: String,
+        except: PeerId,
+        response_tx: oneshot::Sender<Vec<PeerId>>,
     },
+    UpdateShares {
+        file_map: FileMap,
+    },
+    UpdateGroup {
+        peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    },
+    UpdateHolders {
+        file_maps: Vec<(PeerId, FileMap)>,
+    },
+    // received files are matched by the transfer they belong to, uploads by their path
+    CancelTransfer {
+        direction: Direction,
+        transfer: u64,
+        path: String,
+    },
+    SetRateLimits {
+        limits: RateLimits,
+    },
+    SetIgnoreList {
+        patterns: Vec<String>,
+    },
+}
+
+// What became of a received file that did not fail
+enum Received {
+    Pulled,
+    // an identical copy was already in the workspace
+    Deduplicated,
+    // the target existed and the conflict policy said to leave it
+    Skipped,
+}
+
+impl Received {
+    fn result(path: String, result: Result<Self, FileError>) -> FileResult {
+        FileResult {
+            path,
+            deduplicated: matches!(result, Ok(Received::Deduplicated)),
+            skipped: matches!(result, Ok(Received::Skipped)),
+            result: result.map(|_| ()),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct P2PTransport {
     pub p2p_id: PeerId,
-    pub relay_address: Multiaddr,
+    keypair: identity::Keypair,
+    pub relay_addresses: Vec<Multiaddr>,
     pub command_tx: Sender<P2pCommand>,
     responder: Sender<ClientEvent>,
+    base_dir_path: PathBuf,
+    // spawned transfer tasks by pending id, so they can be aborted
+    tasks: Arc<Mutex<HashMap<u64, AbortHandle>>>,
+    contents: ContentIndex,
 }
 
 impl P2PTransport {
     pub fn new_mock(responder: Sender<ClientEvent>) -> Self {
         let (tx, _) = mpsc::channel::<P2pCommand>(CMD_BUFF_SIZE);
+        let keypair = identity::Keypair::generate_ed25519();
         Self {
-            p2p_id: PeerId::random(),
-            relay_address: Multiaddr::empty(),
+            p2p_id: keypair.public().to_peer_id(),
+            keypair,
+            relay_addresses: Vec::new(),
             command_tx: tx,
             responder,
+            base_dir_path: PathBuf::new(),
+            tasks: Arc::default(),
+            contents: ContentIndex::default(),
         }
     }
 
@@ -114,48 +251,50 @@ impl P2PTransport {
         self.responder.clone()
     }
 
+    // Every relay in `relay_addresses` is dialed and listened through
     pub fn new(
-        relay_address: &str,
-        secret_key_seed: &str,
+        relay_addresses: &[String],
+        keypair: identity::Keypair,
+        transports: Transports,
         responder: Sender<ClientEvent>,
         base_dir_path: PathBuf,
     ) -> Result<Self, Box<dyn Error>> {
-        let relay_address = Multiaddr::from_str(relay_address).expect("Invalid relay address");
-        let mut swarm = Self::init_swarm(secret_key_seed, &mut relay_address.clone())?;
+        let relay_addresses: Vec<Multiaddr> = relay_addresses
+            .iter()
+            .map(|address| Multiaddr::from_str(address).expect("Invalid relay address"))
+            .collect();
+        let mut swarm = Self::init_swarm(keypair.clone(), transports)?;
         let p2p_id = swarm.local_peer_id().clone();
 
         let (tx, rx) = mpsc::channel::<P2pCommand>(CMD_BUFF_SIZE);
 
         let p2p_client = Self {
             p2p_id,
-            relay_address,
+            keypair,
+            relay_addresses,
             command_tx: tx,
             responder,
+            base_dir_path: base_dir_path.clone(),
+            tasks: Arc::default(),
+            contents: ContentIndex::default(),
         };
-        let relay_address = p2p_client.relay_address.clone();
+        let mut relays = Relays::new(p2p_client.relay_addresses.clone());
 
-        if !Self::is_relay_connected(&swarm, &relay_address.to_string()) {
-            if let Ok(()) = block_on(async { Self::dial_relay(&mut swarm, &relay_address).await }) {
-            } else {
-                tracing::error!("Failed to dial to relay ");
+        for (_, relay_address) in relays.addresses() {
+            if block_on(async { Self::dial_relay(&mut swarm, &relay_address).await }).is_err() {
+                tracing::error!("Failed to dial to relay {}", relay_address);
             }
         }
 
-        if !Self::is_listen_peer_via_relay(&mut swarm) {
-            for _ in 0..MAX_DIAL_RETRY {
-                if block_on(async { Self::listen_peer_via_relay(&mut swarm, &relay_address).await })
-                    .is_ok()
-                {
-                    break;
-                }
-            }
-            if !Self::is_listen_peer_via_relay(&mut swarm) {
-                tracing::error!("Failed to listen via relay: {:?}", &relay_address);
-            }
+        let targets = relays.not_listening(false);
+        block_on(async { Self::listen_via_relays(&mut swarm, &mut relays, targets).await });
+        if !relays.is_listening() {
+            tracing::error!("Failed to listen via relay: {:?}", relays.addresses());
         }
 
+        let responder = p2p_client.responder();
         tokio::task::spawn(async move {
-            Self::swarm_event_loop(swarm, rx, base_dir_path, relay_address).await;
+            Self::swarm_event_loop(swarm, rx, base_dir_path, relays, responder).await;
         });
         Ok(p2p_client)
     }
@@ -165,7 +304,8 @@ impl P2PTransport {
 
         let p2p_transport = self.clone();
         let peer_clone = peer.clone();
-        tokio::spawn(async move {
+        // only our own pending id can be cancelled here
+        self.spawn_transfer(own.then_some(pending), async move {
             /* START TEMP */
             match p2p_transport.send_file_open(peer.source).await {
                 Ok(rx) => {
@@ -182,9 +322,7 @@ impl P2PTransport {
                         responder
                             .send(ClientEvent::Consequence {
                                 id: pending,
-                                consequence: Consequence::FileSend {
-                                    result: Err(format!("Failed to send file open : {:?}", e)),
-                                },
+                                consequence: Consequence::FileSend { result: Err(e) },
                             })
                             .await
                             .expect("Failed to send file open consequence");
@@ -199,16 +337,13 @@ impl P2PTransport {
         &self,
         pending: Option<u64>,
         _peer: Peer,
-        mut rx: oneshot::Receiver<Result<(), String>>,
+        mut rx: oneshot::Receiver<Result<(), FileError>>,
     ) {
         let responder = self.responder();
 
         let p2p_transport = self.clone();
-        tokio::spawn(async move {
-            match p2p_transport
-                .send_file_wait(&mut rx, REQUEST_TIMEOUT_SEC)
-                .await
-            {
+        self.spawn_transfer(pending, async move {
+            match p2p_transport.send_file_wait(&mut rx).await {
                 Ok(_) => {
                     if let Some(id) = pending {
                         let consequence = Consequence::FileSend { result: Ok(()) };
@@ -221,9 +356,7 @@ impl P2PTransport {
                         responder
                             .send(ClientEvent::Consequence {
                                 id,
-                                consequence: Consequence::FileSend {
-                                    result: Err(format!("Failed to send file wait : {:?}", e)),
-                                },
+                                consequence: Consequence::FileSend { result: Err(e) },
                             })
                             .await
                             .expect("Failed to send file wait consequence");
@@ -234,28 +367,30 @@ impl P2PTransport {
         ()
     }
 
-    pub async fn receive(&self, pending: Option<u64>, peer: Peer) {
+    // `remote_peer_id` is the identity `peer` registered with the server
+    pub async fn receive(&self, pending: Option<u64>, remote_peer_id: PeerId, peer: Peer) {
         let responder = self.responder();
 
-        let remote_peer_id = generate_ed25519(peer.id.to_string().as_str())
-            .public()
-            .to_peer_id()
-            .to_string();
+        let remote_peer_id = remote_peer_id.to_string();
 
         let p2p_transport = self.clone();
-        tokio::spawn(async move {
+        self.spawn_transfer(pending, async move {
             match p2p_transport
-                .recv_file(
+                .recv_path(
                     remote_peer_id,
                     peer.source,
                     peer.target,
+                    peer.conflict,
                     REQUEST_TIMEOUT_SEC,
+                    pending,
                 )
                 .await
             {
-                Ok(_) => {
+                Ok(results) => {
                     if let Some(id) = pending {
-                        let consequence = Consequence::FileReceive { result: Ok(()) };
+                        let consequence = Consequence::FileReceive {
+                            result: Ok(results),
+                        };
                         let event = ClientEvent::Consequence { id, consequence };
                         responder.send(event).await.unwrap();
                     };
@@ -265,9 +400,7 @@ impl P2PTransport {
                         responder
                             .send(ClientEvent::Consequence {
                                 id,
-                                consequence: Consequence::FileReceive {
-                                    result: Err(format!("Failed to receive file : {:?}", e)),
-                                },
+                                consequence: Consequence::FileReceive { result: Err(e) },
                             })
                             .await
                             .expect("Failed to send file receive consequence");
@@ -278,6 +411,96 @@ impl P2PTransport {
         ()
     }
 
+    fn spawn_transfer<F>(&self, pending: Option<u64>, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Some(id) = pending else {
+            tokio::spawn(task);
+            return;
+        };
+        // held until the handle is stored, so a task that ends at once still finds it
+        let mut tasks = self.tasks.lock().expect("Transfer tasks poisoned");
+        let tasks_clone = self.tasks.clone();
+        let handle = tokio::spawn(async move {
+            task.await;
+            let mut tasks = tasks_clone.lock().expect("Transfer tasks poisoned");
+            // `send_wait` may already have taken over the id from `send_open`
+            if tasks
+                .get(&id)
+                .is_some_and(|handle| handle.id() == tokio::task::id())
+            {
+                tasks.remove(&id);
+            }
+        });
+        tasks.insert(id, handle.abort_handle());
+    }
+
+    // Stops the transfer's task and tears it down on both peers, removing partial files
+    pub async fn cancel(
+        &self,
+        pending: u64,
+        direction: Direction,
+        peer: &Peer,
+    ) -> Result<(), Box<dyn Error>> {
+        let task = self
+            .tasks
+            .lock()
+            .expect("Transfer tasks poisoned")
+            .remove(&pending);
+        if let Some(task) = task {
+            task.abort();
+        }
+        let command = P2pCommand::CancelTransfer {
+            direction,
+            transfer: pending,
+            path: peer.source.clone(),
+        };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
+    // Files are only served if they are in the published file map
+    pub async fn update_shares(&self, file_map: FileMap) -> Result<(), Box<dyn Error>> {
+        let command = P2pCommand::UpdateShares { file_map };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
+    // ...and only to peers of the same group, who are also dialed on the addresses they registered
+    pub async fn update_group(
+        &self,
+        peers: Vec<(PeerId, Vec<Multiaddr>)>,
+    ) -> Result<(), Box<dyn Error>> {
+        let command = P2pCommand::UpdateGroup { peers };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
+    // What the group's peers publish, to pull a file from every identical copy
+    pub async fn update_holders(
+        &self,
+        file_maps: Vec<(PeerId, FileMap)>,
+    ) -> Result<(), Box<dyn Error>> {
+        let command = P2pCommand::UpdateHolders { file_maps };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
+    // Applies to chunks from now on, including those of running transfers
+    pub async fn set_rate_limits(&self, limits: RateLimits) -> Result<(), Box<dyn Error>> {
+        let command = P2pCommand::SetRateLimits { limits };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
+    // Names matching these are not listed to peers receiving a folder
+    pub async fn set_ignore_list(&self, patterns: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let command = P2pCommand::SetIgnoreList { patterns };
+        self.command_tx.send(command).await?;
+        Ok(())
+    }
+
     pub async fn exit(&self) -> Result<(), Box<dyn Error>> {
         let command = P2pCommand::Exit;
         self.command_tx.send(command).await?;
@@ -307,51 +530,471 @@ impl P2PTransport {
         remote_peer_id: String,
         target_path: String,
         save_path: String,
+        conflict: ConflictPolicy,
         timeout: u64,
     ) -> Result<(), String> {
-        let (tx, rx) = oneshot::channel();
-        let command = P2pCommand::RecvFile {
-            remote_peer_id: remote_peer_id.clone(),
-            src_path: target_path.clone(),
-            tgt_path: save_path,
-            response_tx: tx,
+        self.connect_remote(&remote_peer_id, timeout).await?;
+        self.request_file(remote_peer_id, target_path, save_path, conflict, None)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // Receives a file, or a whole folder one file at a time, as part of `transfer`. A single
+    // file or a listing that fails is the error, a folder reports every file on its own
+    pub async fn recv_path(
+        &self,
+        remote_peer_id: String,
+        target_path: String,
+        save_path: String,
+        conflict: ConflictPolicy,
+        timeout: u64,
+        transfer: Option<u64>,
+    ) -> Result<Vec<FileResult>, FileError> {
+        self.connect_remote(&remote_peer_id, timeout)
+            .await
+            .map_err(|e| FileError::new(ErrorCode::Network, e))?;
+
+        let entries = match self.list_folder(&remote_peer_id, &target_path).await {
+            Ok(Some(entries)) => entries,
+            Ok(None) => {
+                let received = self
+                    .request_file(
+                        remote_peer_id,
+                        target_path.clone(),
+                        save_path,
+                        conflict,
+                        transfer,
+                    )
+                    .await?;
+                return Ok(vec![Received::result(target_path, Ok(received))]);
+            }
+            Err(e) => return Err(e),
         };
 
+        tracing::info!(
+            "Receiving folder {:?}: {} entries",
+            target_path,
+            entries.len()
+        );
+        let save_dir = sandbox::resolve(&self.base_dir_path, &save_path)?;
+        tokio::fs::create_dir_all(save_dir).await.map_err(|e| {
+            let message = format!("Failed to create folder {}: {}", save_path, e);
+            FileError::new(ErrorCode::WriteError, message)
+        })?;
+        let mut results = Vec::new();
+        // names already received, which may only differ in case from a later entry
+        let mut received = HashSet::new();
+        for entry in entries {
+            let src_path = join_remote(&target_path, &entry.path);
+            let tgt_path = join_remote(&save_path, &entry.path);
+            // entries come from the peer, so they are confined like any other save path
+            if entry.is_dir {
+                let created = match sandbox::resolve(&self.base_dir_path, &tgt_path) {
+                    Ok(dir) => tokio::fs::create_dir_all(dir)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = created {
+                    tracing::error!("Failed to create folder {:?}: {}", tgt_path, e);
+                }
+                continue;
+            }
+            // overwriting is meant for what was there before, not for another file of
+            // this transfer
+            let conflict = match received.insert(tgt_path.to_lowercase()) {
+                false if conflict == ConflictPolicy::Overwrite => ConflictPolicy::KeepBoth,
+                _ => conflict,
+            };
+            let result = self
+                .request_file(
+                    remote_peer_id.clone(),
+                    src_path.clone(),
+                    tgt_path,
+                    conflict,
+                    transfer,
+                )
+                .await;
+            if let Err(ref e) = result {
+                if e.code == ErrorCode::Cancelled {
+                    return Err(e.clone());
+                }
+            }
+            results.push(Received::result(src_path, result));
+        }
+        Ok(results)
+    }
+
+    async fn connect_remote(&self, remote_peer_id: &str, timeout: u64) -> Result<(), String> {
         self.connect_relay(timeout)
             .await
             .map_err(|e| e.to_string())?;
-        self.connect_peer(remote_peer_id.clone(), timeout)
+        self.connect_peer(remote_peer_id.to_string(), timeout)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| e.to_string())
+    }
+
+    // `None` when the remote path is a regular file
+    async fn list_folder(&self, remote_peer_id: &str, src_path: &str) -> FolderListing {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::ListFolder {
+            remote_peer_id: PeerId::from_str(remote_peer_id).map_err(|e| network(e.to_string()))?,
+            src_path: src_path.to_string(),
+            response_tx: tx,
+        };
+        self.command_tx
+            .send(command)
+            .await
+            .map_err(|e| network(e.to_string()))?;
+        rx.await.map_err(|e| network(e.to_string()))?
+    }
+
+    // Hashing the whole file is left to the sender only when `digest` asks for it
+    async fn probe_file(&self, remote_peer_id: PeerId, src_path: &str, digest: bool) -> FileProbe {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::ProbeFile {
+            remote_peer_id,
+            src_path: src_path.to_string(),
+            digest,
+            response_tx: tx,
+        };
+        self.command_tx
+            .send(command)
+            .await
+            .map_err(|e| network(e.to_string()))?;
+        rx.await.map_err(|e| network(e.to_string()))?
+    }
+
+    // Other group peers holding a copy of the same size and digest as the named peer's. Each
+    // holder hashes its copy to answer, one still busy with it is left out
+    async fn find_mirrors(
+        &self,
+        remote_peer_id: PeerId,
+        src_path: &str,
+        size: u64,
+        digest: Option<String>,
+    ) -> Mirrors {
+        if size <= CHUNK_SIZE {
+            return Mirrors::default();
+        }
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::FindHolders {
+            src_path: src_path.to_string(),
+            except: remote_peer_id,
+            response_tx: tx,
+        };
+        if self.command_tx.send(command).await.is_err() {
+            return Mirrors::default();
+        }
+        let holders = rx.await.unwrap_or_default();
+        if holders.is_empty() {
+            return Mirrors::default();
+        }
+        // the named peer only hashes its copy once there are holders to compare with
+        let digest = match digest {
+            Some(digest) => digest,
+            None => match self.probe_file(remote_peer_id, src_path, true).await {
+                Ok((_, Some(digest), _)) => digest,
+                _ => return Mirrors::default(),
+            },
+        };
+
+        // a slow or unreachable holder is left out rather than waited for
+        let digest = &digest;
+        let probes = holders.into_iter().map(|peer| async move {
+            let probe = async {
+                self.connect_peer(peer.to_string(), MIRROR_PROBE_TIMEOUT_SEC)
+                    .await
+                    .ok()?;
+                self.probe_file(peer, src_path, true).await.ok()
+            };
+            let timeout = Duration::from_secs(MIRROR_PROBE_TIMEOUT_SEC);
+            let probe = time::timeout(timeout, probe).await.ok().flatten()?;
+            let (held, held_digest, _) = probe;
+            (held == size && held_digest.as_ref() == Some(digest)).then_some(peer)
+        });
+        let peers: Vec<PeerId> = futures::future::join_all(probes)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        Mirrors { size, peers }
+    }
+
+    // The sender diffs in the background, so it is asked again until the delta is done
+    async fn fetch_delta(
+        &self,
+        remote_peer_id: PeerId,
+        src_path: &str,
+        signature: Signature,
+    ) -> Result<Delta, FileError> {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let mut signature = Some(signature);
+        loop {
+            let (tx, rx) = oneshot::channel();
+            let command = P2pCommand::RequestDelta {
+                remote_peer_id,
+                src_path: src_path.to_string(),
+                signature: signature.take(),
+                response_tx: tx,
+            };
+            self.command_tx
+                .send(command)
+                .await
+                .map_err(|e| network(e.to_string()))?;
+            match rx.await.map_err(|e| network(e.to_string()))?? {
+                Some(delta) => return Ok(delta),
+                None => time::sleep(Duration::from_millis(DELTA_POLL_INTERVAL_MS)).await,
+            }
+        }
+    }
+
+    // Rebuilds what it can of the file from an existing copy at `save_path`, the
+    // rest is pulled as usual. `None` sends the file whole.
+    async fn request_delta(
+        &self,
+        remote_peer_id: &str,
+        src_path: &str,
+        save_path: &str,
+    ) -> Option<Patch> {
+        let path = sandbox::resolve(&self.base_dir_path, save_path).ok()?;
+        let metadata = tokio::fs::metadata(&path).await.ok()?;
+        // small files are cheaper to send whole, and a partial is resumed instead
+        if !metadata.is_file() || metadata.len() < CHUNK_SIZE || transfer::is_resumable(&path).await
+        {
+            return None;
+        }
+        let old = path.clone();
+        let signature = tokio::task::spawn_blocking(move || delta::signature(&old))
+            .await
+            .ok()?
+            .ok()?;
+        let block_size = signature.block_size;
+
+        let remote_peer_id = PeerId::from_str(remote_peer_id).ok()?;
+        let delta = match self.fetch_delta(remote_peer_id, src_path, signature).await {
+            Ok(delta) => delta,
+            Err(e) => {
+                tracing::warn!("No delta for {:?}, sending it whole: {}", src_path, e);
+                return None;
+            }
+        };
+
+        let partial = transfer::partial_path(&path);
+        let patched = tokio::task::spawn_blocking(move || {
+            let ranges = delta::patch(&path, &partial, block_size, &delta)?;
+            Ok::<_, std::io::Error>(Patch {
+                size: delta.size,
+                digest: delta.digest,
+                ranges,
+            })
+        })
+        .await
+        .ok()?;
+        match patched {
+            Ok(patch) => {
+                let missing: u64 = patch.ranges.iter().map(|(start, end)| end - start).sum();
+                tracing::info!(
+                    "Delta for {:?}: pulling {} of {} bytes",
+                    src_path,
+                    missing,
+                    patch.size
+                );
+                Some(patch)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to patch {:?}, sending it whole: {}", save_path, e);
+                None
+            }
+        }
+    }
+
+    async fn has_size(&self, size: u64) -> bool {
+        let root = self.base_dir_path.clone();
+        let contents = self.contents.clone();
+        tokio::task::spawn_blocking(move || contents.has_size(&root, size))
+            .await
+            .unwrap_or(false)
+    }
+
+    // Puts an identical workspace file at `save_path` instead of pulling it, true if there was one
+    async fn deduplicate(
+        &self,
+        size: u64,
+        digest: &str,
+        file_meta: &FileMeta,
+        save_path: &str,
+    ) -> bool {
+        let Ok(target) = sandbox::resolve(&self.base_dir_path, save_path) else {
+            return false;
+        };
+        let root = self.base_dir_path.clone();
+        let contents = self.contents.clone();
+        let digest = digest.to_string();
+        let file_meta = file_meta.clone();
+        let placed = tokio::task::spawn_blocking(move || {
+            let source = contents.find(&root, size, &digest)?;
+            Some(dedup::place(&root, &source, &target, &file_meta).map(|()| source))
+        })
+        .await;
+        match placed {
+            Ok(Some(Ok(source))) => {
+                tracing::info!("Deduplicated {:?} from {:?}", save_path, source);
+                true
+            }
+            Ok(Some(Err(e))) => {
+                tracing::warn!("Failed to place a local copy at {:?}: {}", save_path, e);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    // Tells the sender its file is not pulled after all, which ends it there too with `outcome`
+    async fn skip_file(
+        &self,
+        remote_peer_id: PeerId,
+        src_path: &str,
+        outcome: Result<(), FileError>,
+    ) -> Result<(), FileError> {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::SkipFile {
+            remote_peer_id,
+            src_path: src_path.to_string(),
+            outcome,
+            response_tx: tx,
+        };
+        self.command_tx
+            .send(command)
+            .await
+            .map_err(|e| network(e.to_string()))?;
+        rx.await.map_err(|e| network(e.to_string()))?
+    }
+
+    async fn request_file(
+        &self,
+        remote_peer_id: String,
+        target_path: String,
+        save_path: String,
+        conflict: ConflictPolicy,
+        transfer: Option<u64>,
+    ) -> Result<Received, FileError> {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let peer_id = PeerId::from_str(&remote_peer_id).map_err(|e| network(e.to_string()))?;
+
+        // the sender waits on the file all the same, so it is told when nothing is pulled
+        let save_path = match conflict::resolve(&self.base_dir_path, &save_path, conflict) {
+            Ok(Some(path)) => {
+                if path != save_path {
+                    tracing::info!("{:?} exists, keeping both as {:?}", save_path, path);
+                }
+                path
+            }
+            Ok(None) => {
+                tracing::info!("{:?} exists, skipping it", save_path);
+                if let Err(e) = self.skip_file(peer_id, &target_path, Ok(())).await {
+                    tracing::warn!("Failed to tell the sender about {:?}: {}", target_path, e);
+                }
+                return Ok(Received::Skipped);
+            }
+            Err(error) => {
+                let outcome = Err(error.clone());
+                if let Err(e) = self.skip_file(peer_id, &target_path, outcome).await {
+                    tracing::warn!("Failed to tell the sender about {:?}: {}", target_path, e);
+                }
+                return Err(error);
+            }
+        };
+
+        // content already here is not pulled again, the sender only hashes its file when a
+        // local one of the same size could hold it
+        let probe = self.probe_file(peer_id, &target_path, false).await.ok();
+        let digest = match &probe {
+            Some((size, _, _)) if *size > 0 && self.has_size(*size).await => self
+                .probe_file(peer_id, &target_path, true)
+                .await
+                .ok()
+                .and_then(|(_, digest, _)| digest),
+            _ => None,
+        };
+        if let (Some((size, _, meta)), Some(digest)) = (&probe, &digest) {
+            if self.deduplicate(*size, digest, meta, &save_path).await {
+                if let Err(e) = self.skip_file(peer_id, &target_path, Ok(())).await {
+                    tracing::warn!("Failed to tell the sender about {:?}: {}", target_path, e);
+                }
+                return Ok(Received::Deduplicated);
+            }
+        }
+
+        let patch = self
+            .request_delta(&remote_peer_id, &target_path, &save_path)
+            .await;
+        // a delta is only worked out against the named peer's copy
+        let mirrors = match (&patch, probe) {
+            (None, Some((size, _, _))) => {
+                self.find_mirrors(peer_id, &target_path, size, digest).await
+            }
+            _ => Mirrors::default(),
+        };
+        self.pull_file(
+            &remote_peer_id,
+            &target_path,
+            &save_path,
+            mirrors,
+            patch,
+            transfer,
+        )
+        .await
+        .map(|()| Received::Pulled)
+    }
+
+    async fn pull_file(
+        &self,
+        remote_peer_id: &str,
+        target_path: &str,
+        save_path: &str,
+        mirrors: Mirrors,
+        patch: Option<Patch>,
+        transfer: Option<u64>,
+    ) -> Result<(), FileError> {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::RecvFile {
+            remote_peer_id: remote_peer_id.to_string(),
+            src_path: target_path.to_string(),
+            tgt_path: save_path.to_string(),
+            mirrors,
+            patch,
+            transfer,
+            response_tx: tx,
+        };
 
         tracing::info!("Sending file request to cmd q: {:?}", target_path);
         self.command_tx
             .send(command)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| network(e.to_string()))?;
         tracing::info!("Sent file request to cmd q: {:?}", target_path);
         tracing::info!("Waiting for file request response: {:?}", target_path);
-        tokio::select! {
-            res = rx => {
-                match res {
-                    Ok(Ok(())) => Ok(()),
-                    Ok(Err(err)) => Err(err.into()),
-                    Err(recv_err) => Err(recv_err.to_string()),
-                }
-            },
-            _ = tokio::time::sleep(Duration::from_secs(REQUEST_TIMEOUT_SEC)) => {
-                Err("Timeout while waiting for file request response".into())
-            }
+        // every chunk request is bounded by REQUEST_TIMEOUT_SEC in the swarm
+        match rx.await {
+            Ok(result) => result,
+            Err(recv_err) => Err(network(recv_err.to_string())),
         }
     }
 
     pub async fn send_file_open(
         &self,
         src_path: String,
-    ) -> Result<oneshot::Receiver<Result<(), String>>, String> {
+    ) -> Result<oneshot::Receiver<Result<(), FileError>>, FileError> {
+        let network = |e: String| FileError::new(ErrorCode::Network, e);
         self.listen_on_peer(REQUEST_TIMEOUT_SEC)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| network(format!("Failed to send file open: {}", e)))?;
         let (tx, rx) = oneshot::channel();
         let command = P2pCommand::SendFileOpen {
             src_path,
@@ -360,26 +1003,18 @@ impl P2PTransport {
         self.command_tx
             .send(command)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| FileError::new(ErrorCode::Internal, e.to_string()))?;
         Ok(rx)
     }
 
     pub async fn send_file_wait(
         &self,
-        rx: &mut oneshot::Receiver<Result<(), String>>,
-        timeout: u64,
-    ) -> Result<(), String> {
-        tokio::select! {
-            res = rx => {
-                match res {
-                    Ok(Ok(())) => Ok(()),
-                    Ok(Err(err)) => Err(err),
-                    Err(recv_err) => Err(recv_err.to_string()),
-                }
-            },
-            _ = tokio::time::sleep(Duration::from_secs(timeout)) => {
-                Err("Timeout while waiting for file send response".into())
-            }
+        rx: &mut oneshot::Receiver<Result<(), FileError>>,
+    ) -> Result<(), FileError> {
+        // the swarm fails uploads that see no chunk request for REQUEST_TIMEOUT_SEC
+        match rx.await {
+            Ok(result) => result,
+            Err(recv_err) => Err(FileError::new(ErrorCode::Internal, recv_err.to_string())),
         }
     }
 
@@ -458,8 +1093,66 @@ impl P2PTransport {
                 }
             },
             _ = tokio::time::sleep(Duration::from_secs(timeout)) => {
-                tracing::error!("Timeout while waiting for listen on peer response");
-                return Err("Timeout while waiting for listen on peer response".into());
+                tracing::error!("Timeout while waiting for listen on peer response");
+                return Err("Timeout while waiting for listen on peer response".into());
+            }
+        }
+    }
+
+    // What to register with the server: our peer id and where we listen, signed for
+    // this client. Listening nowhere yet is no reason not to register
+    pub async fn identity(
+        &self,
+        group: Uuid,
+        id: Uuid,
+        timeout: u64,
+    ) -> Result<PeerIdentity, Box<dyn Error>> {
+        let addresses = match self.get_listen_addr(timeout).await {
+            Ok(addresses) => addresses
+                .iter()
+                .filter_map(|address| Multiaddr::from_str(address).ok())
+                .filter(|address| !Self::is_loopback(address))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Registering without listen addresses: {:?}", e);
+                Vec::new()
+            }
+        };
+        Ok(PeerIdentity::new(&self.keypair, group, id, addresses)?)
+    }
+
+    fn is_loopback(address: &Multiaddr) -> bool {
+        address.iter().any(|protocol| match protocol {
+            Protocol::Ip4(ip) => ip.is_loopback(),
+            Protocol::Ip6(ip) => ip.is_loopback(),
+            _ => false,
+        })
+    }
+
+    // Whether each connected peer is reached directly or through the relay
+    pub async fn routes(&self, timeout: u64) -> Result<Vec<PeerRoute>, Box<dyn Error>> {
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::GetRoutes { response_tx: tx };
+        self.command_tx.send(command).await?;
+        tokio::select! {
+            res = rx => Ok(res?),
+            _ = tokio::time::sleep(Duration::from_secs(timeout)) => {
+                tracing::error!("Timeout while waiting for routes");
+                Err("Timeout while waiting for routes".into())
+            }
+        }
+    }
+
+    // Each relay's status and round trip time
+    pub async fn relays(&self, timeout: u64) -> Result<Vec<RelayState>, Box<dyn Error>> {
+        let (tx, rx) = oneshot::channel();
+        let command = P2pCommand::GetRelays { response_tx: tx };
+        self.command_tx.send(command).await?;
+        tokio::select! {
+            res = rx => Ok(res?),
+            _ = tokio::time::sleep(Duration::from_secs(timeout)) => {
+                tracing::error!("Timeout while waiting for relays");
+                Err("Timeout while waiting for relays".into())
             }
         }
     }
@@ -473,10 +1166,20 @@ impl P2PTransport {
         mut swarm: Swarm<Behaviour>,
         mut command_rx: Receiver<P2pCommand>,
         base_dir_path: std::path::PathBuf,
-        mut relay_addr: Multiaddr,
+        mut relays: Relays,
+        responder: Sender<ClientEvent>,
     ) {
         let mut pending_requests: HashMap<String, oneshot::Sender<Result<(), String>>> =
             HashMap::new();
+        let (mut transfers, mut transfer_rx) = Transfers::new();
+        let mut access = Access::default();
+        let mut throttle = Throttle::default();
+        let mut holders = Holders::default();
+        let mut versions = PeerVersions::default();
+        let mut lan = LocalPeers::default();
+        let mut routes = Routes::default();
+        let mut stall_check = tokio::time::interval(Duration::from_secs(TRANSFER_STALL_CHECK_SEC));
+        let mut relay_check = tokio::time::interval(Duration::from_secs(RELAY_CHECK_SEC));
         let mut told_relay_observed_addr = false;
         let mut learned_observed_addr = false;
         let mut is_exit = false;
@@ -484,19 +1187,35 @@ impl P2PTransport {
         loop {
             select! {
                 Some(command) = command_rx.recv() => {
-                    Self::handle_command(&mut swarm, command, &mut pending_requests, &mut relay_addr, &mut is_exit).await;
+                    Self::handle_command(&mut swarm, command, &base_dir_path, &mut pending_requests, &mut transfers, &mut access, &mut holders, &mut throttle, &versions, &mut lan, &routes, &mut relays, &mut is_exit).await;
                 }
                 Some(event) = swarm.next() => {
                     let _ = Self::handle_swarm_event(
                         &mut swarm,
-                        &relay_addr,
                         &mut told_relay_observed_addr,
                         &mut learned_observed_addr,
                         event,
                         base_dir_path.clone(),
-                        &mut pending_requests
+                        &mut pending_requests,
+                        &mut transfers,
+                        &access,
+                        &mut throttle,
+                        &mut versions,
+                        &mut lan,
+                        &mut routes,
+                        &mut relays,
+                        &responder
                     ).await;
                 }
+                Some(event) = transfer_rx.recv() => {
+                    Self::handle_transfer_event(&mut swarm, event, &mut transfers, &throttle, &responder);
+                }
+                _ = stall_check.tick() => {
+                    Self::expire_stalled_uploads(&mut transfers);
+                }
+                _ = relay_check.tick() => {
+                    Self::keep_relays(&mut swarm, &mut relays, &responder).await;
+                }
                 else => {
                     tracing::info!("EventLoop closing. Exiting swarm_event_loop.");
                     break;
@@ -508,11 +1227,20 @@ impl P2PTransport {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn handle_command(
         swarm: &mut Swarm<Behaviour>,
         command: P2pCommand,
+        base_dir_path: &Path,
         pending_requests: &mut HashMap<String, oneshot::Sender<Result<(), String>>>,
-        relay_addr: &mut Multiaddr,
+        transfers: &mut Transfers,
+        access: &mut Access,
+        holders: &mut Holders,
+        throttle: &mut Throttle,
+        versions: &PeerVersions,
+        lan: &mut LocalPeers,
+        routes: &Routes,
+        relays: &mut Relays,
         is_exit: &mut bool,
     ) {
         match command {
@@ -521,7 +1249,13 @@ impl P2PTransport {
                 *is_exit = true;
             }
             P2pCommand::GetPendingRequests { response_tx } => {
-                let pending_requests: Vec<String> = pending_requests.keys().cloned().collect();
+                let pending_requests: Vec<String> = pending_requests
+                    .keys()
+                    .chain(transfers.senders.keys())
+                    .cloned()
+                    .chain(transfers.receivers.keys().map(|(_, path)| path.clone()))
+                    .chain(relays.waiting().iter().map(PeerId::to_string))
+                    .collect();
                 tracing::info!("Pending request----");
                 for pending_request in &pending_requests {
                     tracing::info!("Pending request: {:?}", pending_request);
@@ -534,10 +1268,10 @@ impl P2PTransport {
             }
             P2pCommand::GetStatus { response_tx } => {
                 tracing::info!("Received GetStatus command");
-                let status = if Self::is_relay_connected(&swarm, &relay_addr.to_string()) {
+                let status = if Self::is_relay_connected(&swarm, relays) {
                     let peer_ids: Vec<String> = swarm
                         .connected_peers()
-                        .filter(|peer_id| peer_id.to_string() != relay_addr.to_string())
+                        .filter(|peer_id| !relays.is_relay(peer_id))
                         .map(|peer_id| peer_id.to_string())
                         .collect();
                     if peer_ids.is_empty() {
@@ -555,31 +1289,51 @@ impl P2PTransport {
                     swarm.listeners().map(|addr| addr.to_string()).collect();
                 let _ = response_tx.send(listen_addrs);
             }
+            P2pCommand::GetRoutes { response_tx } => {
+                let _ = response_tx.send(routes.list(&relays.ids()));
+            }
+            P2pCommand::GetRelays { response_tx } => {
+                let _ = response_tx.send(relays.states());
+            }
             P2pCommand::ConnectToRelay { response_tx } => {
-                if Self::is_relay_connected(&swarm, &relay_addr.to_string()) {
+                if Self::is_relay_connected(&swarm, relays) {
                     let _ = response_tx.send(Ok(()));
                     return;
                 } else {
-                    if let Ok(()) = Self::dial_relay(swarm, &relay_addr).await {
-                        pending_requests.insert(relay_addr.to_string(), response_tx);
-                    } else {
-                        tracing::error!("Failed to dial to relay ");
+                    // every relay being dialed gets a waiter, the first one ready answers
+                    let mut waiting = Vec::new();
+                    for (relay_id, relay_address) in relays.addresses() {
+                        match Self::dial_relay(swarm, &relay_address).await {
+                            // a dial already in flight is waited on all the same
+                            Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => {
+                                let (tx, rx) = oneshot::channel();
+                                relays.wait(&relay_id, tx);
+                                waiting.push(
+                                    rx.map(|result| result.unwrap_or_else(|e| Err(e.to_string()))),
+                                );
+                            }
+                            Err(e) => {
+                                tracing::error!("Failed to dial to relay {}: {}", relay_address, e)
+                            }
+                        }
+                    }
+                    if waiting.is_empty() {
                         let _ = response_tx.send(Err("Failed to dial to relay".into()));
+                        return;
                     }
+                    tokio::spawn(async move {
+                        let result = future::select_ok(waiting).await.map(|_| ());
+                        let _ = response_tx.send(result);
+                    });
                 }
             }
             P2pCommand::ListenToPeer { response_tx } => {
-                if !Self::is_listen_peer_via_relay(&swarm) {
-                    for _ in 0..MAX_DIAL_RETRY {
-                        if Self::listen_peer_via_relay(swarm, relay_addr).await.is_ok() {
-                            break;
-                        }
-                    }
-                    if !Self::is_listen_peer_via_relay(&swarm) {
-                        tracing::error!("Failed to listen on relay: {:?}", relay_addr);
-                        let _ = response_tx.send(Err("Failed to listen on relay".into()));
-                        return;
-                    }
+                let targets = relays.not_listening(false);
+                Self::listen_via_relays(swarm, relays, targets).await;
+                if !relays.is_listening() {
+                    tracing::error!("Failed to listen on relay: {:?}", relays.addresses());
+                    let _ = response_tx.send(Err("Failed to listen on relay".into()));
+                    return;
                 }
                 let _ = response_tx.send(Ok(()));
             }
@@ -587,7 +1341,7 @@ impl P2PTransport {
                 remote_peer_id,
                 response_tx,
             } => {
-                if let Ok(()) = Self::dial_peer(swarm, remote_peer_id, &relay_addr) {
+                if let Ok(()) = Self::dial_peer(swarm, remote_peer_id, relays, access, lan) {
                     pending_requests.insert(remote_peer_id.to_string(), response_tx);
                 } else {
                     tracing::error!("Failed to dial to peer: {:?}", remote_peer_id);
@@ -598,20 +1352,28 @@ impl P2PTransport {
                 remote_peer_id,
                 src_path: target_path,
                 tgt_path: save_path,
+                mirrors,
+                patch,
+                transfer,
                 response_tx,
             } => {
                 tracing::info!("File recv request isstarting : {:?}", target_path);
                 if PeerId::from_str(&remote_peer_id).is_err() {
                     tracing::error!("Invalid PeerId: {:?}", remote_peer_id);
-                    let _ = response_tx.send(Err("Invalid PeerId".into()));
+                    let error = FileError::new(ErrorCode::Network, "Invalid PeerId");
+                    let _ = response_tx.send(Err(error));
                     return;
                 }
                 let remote_peer_id = PeerId::from_str(&remote_peer_id).expect("Invalid PeerId");
+                if let Err(error) = versions.check(&remote_peer_id) {
+                    let _ = response_tx.send(Err(error));
+                    return;
+                }
 
                 tracing::info!("Connecting to peer...");
                 if !Self::is_peer_connected(&swarm, &remote_peer_id.to_string()) {
                     for _ in 0..MAX_DIAL_RETRY {
-                        let _ = Self::dial_peer(swarm, remote_peer_id, &relay_addr);
+                        let _ = Self::dial_peer(swarm, remote_peer_id, relays, access, lan);
                         if Self::is_peer_connected(&swarm, &remote_peer_id.to_string()) {
                             tracing::info!("Connected to peer");
                             break;
@@ -621,33 +1383,57 @@ impl P2PTransport {
                     }
                     if !Self::is_peer_connected(&swarm, &remote_peer_id.to_string()) {
                         tracing::error!("Failed to connect to peer: {:?}", remote_peer_id);
-                        let _ = response_tx.send(Err("Failed to connect to peer".into()));
+                        let error = FileError::new(ErrorCode::Network, "Failed to connect to peer");
+                        let _ = response_tx.send(Err(error));
                         return;
                     }
                 }
                 tracing::info!("Connected to peer");
 
+                let key = (remote_peer_id, target_path.clone());
+                if transfers.receivers.contains_key(&key) {
+                    tracing::error!(
+                        "Already receiving {:?} from {:?}",
+                        target_path,
+                        remote_peer_id
+                    );
+                    let error = FileError::new(ErrorCode::Conflict, "Already receiving this file");
+                    let _ = response_tx.send(Err(error));
+                    return;
+                }
                 if let Some(file_name) = std::path::Path::new(&target_path).file_name() {
                     let file_name = file_name.to_string_lossy().to_string();
-                    let save_path = save_path.clone();
-                    let request = ku_protocol::FileRequest {
-                        file_name: file_name.clone(),
-                        target_path: target_path.clone(),
-                        save_path: save_path.clone(),
+                    let download = Download::resume(
+                        base_dir_path,
+                        remote_peer_id,
+                        file_name,
+                        target_path.clone(),
+                        save_path.clone(),
+                    )
+                    .await;
+                    let mut download = match download {
+                        Ok(download) => download,
+                        Err(error) => {
+                            tracing::error!("Refusing to save to {:?}: {}", save_path, error);
+                            let _ = response_tx.send(Err(error));
+                            return;
+                        }
                     };
-                    swarm
-                        .behaviour_mut()
-                        .ku_file_transfer
-                        .send_request(&remote_peer_id, request);
-
-                    pending_requests.insert(target_path.clone(), response_tx);
+                    download.transfer = transfer;
+                    match patch {
+                        Some(patch) => download.fill(patch),
+                        None => download.spread(mirrors),
+                    }
+                    Self::pull(swarm, transfers, throttle, download);
+                    transfers.receivers.insert(key, response_tx);
                     tracing::info!(
                         "File recv request: {:?} is sent and listening for res",
                         target_path
                     );
                 } else {
                     tracing::error!("Invalid file path: {:?}", target_path);
-                    let _ = response_tx.send(Err("Invalid file path".into()));
+                    let error = FileError::new(ErrorCode::NotFound, "Invalid file path");
+                    let _ = response_tx.send(Err(error));
                 }
             }
             P2pCommand::SendFileOpen {
@@ -659,29 +1445,334 @@ impl P2PTransport {
                     swarm.listeners().map(|addr| addr.to_string()).collect();
                 if !listen_addrs.iter().any(|addr| addr.contains("p2p-circuit")) {
                     tracing::error!("Swarm is not listening while SendFileOpen");
-                    let _ = response_tx.send(Err("Failed to listen via relay".into()));
+                    let error = FileError::new(ErrorCode::Network, "Failed to listen via relay");
+                    let _ = response_tx.send(Err(error));
                     return;
                 }
-                pending_requests.insert(src_path.clone(), response_tx);
+                transfers.cancelled.remove(&src_path);
+                transfers.senders.insert(src_path.clone(), response_tx);
+                transfers.uploads.insert(src_path.clone(), Upload::new());
                 tracing::info!("File send request: {:?} is listening", src_path);
             }
+            P2pCommand::ListFolder {
+                remote_peer_id,
+                src_path,
+                response_tx,
+            } => {
+                if let Err(error) = versions.check(&remote_peer_id) {
+                    let _ = response_tx.send(Err(error));
+                    return;
+                }
+                let request = transfer::probe(&src_path, false);
+                let request_id = Self::send_once(swarm, transfers, remote_peer_id, request);
+                transfers.listings.insert(request_id, response_tx);
+            }
+            P2pCommand::ProbeFile {
+                remote_peer_id,
+                src_path,
+                digest,
+                response_tx,
+            } => {
+                if let Err(error) = versions.check(&remote_peer_id) {
+                    let _ = response_tx.send(Err(error));
+                    return;
+                }
+                let request = transfer::probe(&src_path, digest);
+                let request_id = Self::send_once(swarm, transfers, remote_peer_id, request);
+                transfers.probes.insert(request_id, response_tx);
+            }
+            P2pCommand::SkipFile {
+                remote_peer_id,
+                src_path,
+                outcome,
+                response_tx,
+            } => {
+                let request = FileRequest {
+                    skip: Some(outcome),
+                    ..transfer::probe(&src_path, false)
+                };
+                let request_id = Self::send_once(swarm, transfers, remote_peer_id, request);
+                transfers.skips.insert(request_id, response_tx);
+            }
+            P2pCommand::RequestDelta {
+                remote_peer_id,
+                src_path,
+                signature,
+                response_tx,
+            } => {
+                let request = FileRequest {
+                    delta: true,
+                    signature,
+                    ..transfer::probe(&src_path, false)
+                };
+                let request_id = Self::send_once(swarm, transfers, remote_peer_id, request);
+                transfers.deltas.insert(request_id, response_tx);
+            }
+            P2pCommand::FindHolders {
+                src_path,
+                except,
+                response_tx,
+            } => {
+                let local_peer_id = *swarm.local_peer_id();
+                let peers = holders
+                    .of(&src_path)
+                    .into_iter()
+                    .filter(|peer| *peer != except && *peer != local_peer_id)
+                    .collect();
+                let _ = response_tx.send(peers);
+            }
+            P2pCommand::UpdateShares { file_map } => {
+                access.set_shares(&file_map);
+            }
+            P2pCommand::UpdateGroup { peers } => {
+                tracing::info!("Serving files to {} group peers", peers.len());
+                access.set_peers(peers);
+            }
+            P2pCommand::UpdateHolders { file_maps } => {
+                holders.set(file_maps);
+            }
+            P2pCommand::CancelTransfer {
+                direction,
+                transfer,
+                path,
+            } => match direction {
+                Direction::Receive => {
+                    let keys: Vec<DownloadKey> = transfers
+                        .downloads
+                        .iter()
+                        .filter(|(_, download)| download.transfer == Some(transfer))
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in keys {
+                        let Some(mut download) = transfers.downloads.remove(&key) else {
+                            continue;
+                        };
+                        swarm
+                            .behaviour_mut()
+                            .ku_file_transfer
+                            .send_request(&download.peer, download.cancel_request());
+                        download.discard().await;
+                        let message = format!("Transfer cancelled: {}", key.1);
+                        transfers.resolve(&key, Err(FileError::new(ErrorCode::Cancelled, message)));
+                        tracing::info!("Cancelled download: {:?}", key);
+                    }
+                }
+                Direction::Send => {
+                    transfers.uploads.remove(&path);
+                    if let Some(sender) = transfers.senders.remove(&path) {
+                        let error = FileError::new(ErrorCode::Cancelled, "Transfer cancelled");
+                        let _ = sender.send(Err(error));
+                    }
+                    // the receiver learns about it from its next chunk request
+                    transfers
+                        .cancelled
+                        .insert(path.clone(), time::Instant::now());
+                    tracing::info!("Cancelled upload: {:?}", path);
+                }
+            },
+            P2pCommand::SetRateLimits { limits } => {
+                tracing::info!("Transfer rate limits: {:?}", limits);
+                throttle.set_limits(limits);
+            }
+            P2pCommand::SetIgnoreList { patterns } => {
+                tracing::info!("Ignored in folder listings: {:?}", patterns);
+                transfers.set_ignored(&patterns);
+            }
         }
     }
 
-    fn is_relay_connected(swarm: &Swarm<Behaviour>, relay_addr: &str) -> bool {
-        let relay_id = relay_addr.rsplit('/').next().unwrap_or("");
-        tracing::info!("Relay ID: {:?}", relay_id);
-        swarm.connected_peers().any(|peer_id| {
-            tracing::info!(
-                "Peer ID: {:?} equals relay {:?}",
-                peer_id,
-                (&*peer_id.to_string() == relay_id)
-            );
-            &*peer_id.to_string() == relay_id
+    fn handle_transfer_event(
+        swarm: &mut Swarm<Behaviour>,
+        event: TransferEvent,
+        transfers: &mut Transfers,
+        throttle: &Throttle,
+        responder: &Sender<ClientEvent>,
+    ) {
+        match event {
+            TransferEvent::Served {
+                channel,
+                response,
+                result,
+            } => {
+                let src_path = response.src_path.clone();
+                let file_name = response.file_name.clone();
+                let entries = response.entries.clone();
+                let key = transfers
+                    .upload_key(&src_path)
+                    .unwrap_or_else(|| src_path.clone());
+                // probes, listings and errors carry no file data
+                let is_chunk = response.status.is_ok()
+                    && entries.is_none()
+                    && (!response.content.is_empty() || result.is_some());
+                if let Some(upload) = transfers.uploads.get_mut(&key).filter(|_| is_chunk) {
+                    Self::report(responder, upload.progress(&response));
+                }
+                if swarm
+                    .behaviour_mut()
+                    .ku_file_transfer
+                    .send_response(channel, *response)
+                    .is_err()
+                {
+                    tracing::error!("Failed to send file response: {}", file_name);
+                }
+                // the sender is done once the last chunk (or an error) went out
+                let outcome = match transfers.uploads.get_mut(&key) {
+                    Some(upload) => {
+                        if let Some(entries) = entries {
+                            upload.expect_files(&src_path, &entries);
+                        }
+                        match result {
+                            Some(result) => upload.finish(&src_path, result),
+                            None => upload.outcome(),
+                        }
+                    }
+                    None => result,
+                };
+                if let Some(result) = outcome {
+                    transfers.uploads.remove(&key);
+                    if let Some(sender) = transfers.senders.remove(&key) {
+                        let _ = sender.send(result);
+                    }
+                    tracing::info!("Sent file: {}", key);
+                }
+            }
+            TransferEvent::Completed { key, result } => {
+                match &result {
+                    Ok(()) => tracing::info!("File saved from: {:?}", key),
+                    Err(e) => tracing::error!("Failed to save file from {:?}: {}", key, e),
+                }
+                transfers.resolve(&key, result);
+            }
+            TransferEvent::Pull { key, segment } => {
+                // unless it was cancelled or its source dropped in the meantime
+                let download = transfers
+                    .downloads
+                    .get_mut(&key)
+                    .filter(|download| download.is_waiting(segment));
+                if let Some(download) = download {
+                    let length = throttle.chunk_len(Direction::Receive, CHUNK_SIZE);
+                    let (peer, request) = download.next_request(segment, length);
+                    let request_id = swarm
+                        .behaviour_mut()
+                        .ku_file_transfer
+                        .send_request(&peer, request);
+                    download.sent(segment, request_id);
+                }
+            }
+        }
+    }
+
+    // Requests the next chunk from every idle source, as soon as the download budget allows
+    fn pull(
+        swarm: &mut Swarm<Behaviour>,
+        transfers: &mut Transfers,
+        throttle: &mut Throttle,
+        mut download: Download,
+    ) {
+        let key = download.key();
+        for segment in download.idle() {
+            let length = throttle.chunk_len(Direction::Receive, CHUNK_SIZE);
+            let (peer, request) = download.next_request(segment, length);
+            let delay = throttle.reserve(Direction::Receive, peer, length);
+            if delay.is_zero() {
+                let request_id = swarm
+                    .behaviour_mut()
+                    .ku_file_transfer
+                    .send_request(&peer, request);
+                download.sent(segment, request_id);
+            } else {
+                download.wait(segment);
+                let events = transfers.events();
+                let event = TransferEvent::Pull {
+                    key: key.clone(),
+                    segment,
+                };
+                tokio::spawn(async move {
+                    time::sleep(delay).await;
+                    let _ = events.send(event).await;
+                });
+            }
+        }
+        transfers.downloads.insert(key, download);
+    }
+
+    // Redials lost relays once their backoff is over, and listens through a relay again
+    // when it is back without a reservation
+    async fn keep_relays(
+        swarm: &mut Swarm<Behaviour>,
+        relays: &mut Relays,
+        responder: &Sender<ClientEvent>,
+    ) {
+        for (relay_id, relay_address) in relays.due(Instant::now()) {
+            tracing::info!(address=%relay_address, "Redialing relay");
+            Self::report_relay(responder, relays.state(&relay_id));
+            match Self::dial_relay(swarm, &relay_address).await {
+                Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to redial relay: {}", e);
+                    Self::report_relay(responder, relays.lost(&relay_id, Instant::now()));
+                }
+            }
+        }
+        let targets = relays.not_listening(true);
+        Self::listen_via_relays(swarm, relays, targets).await;
+    }
+
+    async fn listen_via_relays(
+        swarm: &mut Swarm<Behaviour>,
+        relays: &mut Relays,
+        targets: Vec<(PeerId, Multiaddr)>,
+    ) {
+        for (relay_id, relay_address) in targets {
+            for _ in 0..MAX_DIAL_RETRY {
+                match Self::listen_peer_via_relay(swarm, &relay_address).await {
+                    Ok(listener) => {
+                        relays.listening(&relay_id, listener);
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::warn!(address=%relay_address, "Failed to listen via relay: {}", e)
+                    }
+                }
+            }
+        }
+    }
+
+    fn report_relay(responder: &Sender<ClientEvent>, state: Option<RelayState>) {
+        if let Some(state) = state {
+            tracing::info!(?state, "Relay status changed");
+            let _ = responder.try_send(ClientEvent::Relay { state });
+        }
+    }
+
+    // Progress is best effort, it never holds up the event loop
+    fn report(responder: &Sender<ClientEvent>, progress: Progress) {
+        let _ = responder.try_send(ClientEvent::Progress { progress });
+    }
+
+    fn expire_stalled_uploads(transfers: &mut Transfers) {
+        let timeout = Duration::from_secs(REQUEST_TIMEOUT_SEC);
+        transfers
+            .cancelled
+            .retain(|_, cancelled_at| cancelled_at.elapsed() < timeout);
+        transfers.uploads.retain(|src_path, upload| {
+            if upload.last_activity.elapsed() < timeout {
+                return true;
+            }
+            tracing::error!("File send stalled: {:?}", src_path);
+            if let Some(sender) = transfers.senders.remove(src_path) {
+                let message = "Timeout while waiting for file request";
+                let _ = sender.send(Err(FileError::new(ErrorCode::Network, message)));
+            }
+            false
         });
-        swarm
-            .connected_peers()
-            .any(|peer_id| &*peer_id.to_string() == relay_id)
+    }
+
+    fn is_relay_connected(swarm: &Swarm<Behaviour>, relays: &Relays) -> bool {
+        relays
+            .ids()
+            .iter()
+            .any(|relay_id| swarm.is_connected(relay_id))
     }
 
     fn is_peer_connected(swarm: &Swarm<Behaviour>, remote_peer_id: &str) -> bool {
@@ -697,62 +1788,92 @@ impl P2PTransport {
             .any(|peer_id| &(*peer_id.to_string()) == remote_peer_id)
     }
 
-    fn is_listen_peer_via_relay(swarm: &Swarm<Behaviour>) -> bool {
-        swarm
-            .listeners()
-            .any(|addr| addr.iter().any(|proto| proto == Protocol::P2pCircuit))
+    // Sends a request answered by a single response, kept to send it again should its
+    // connection close under it
+    fn send_once(
+        swarm: &mut Swarm<Behaviour>,
+        transfers: &mut Transfers,
+        peer: PeerId,
+        request: FileRequest,
+    ) -> OutboundRequestId {
+        let request_id = swarm
+            .behaviour_mut()
+            .ku_file_transfer
+            .send_request(&peer, request.clone());
+        transfers.sent.insert(request_id, (peer, request));
+        request_id
     }
 
+    // The relay listens on QUIC and TCP at the same port, QUIC is tried first and a
+    // transport turned off in the config fails right away. A relay already connected or
+    // being dialed is not dialed again and fails with `DialPeerConditionFalse`
     async fn dial_relay(
         swarm: &mut Swarm<Behaviour>,
         relay_address: &Multiaddr,
     ) -> Result<(), DialError> {
-        swarm.dial(relay_address.clone())
+        let Some(relay_id) = relay_peer_id(relay_address) else {
+            return swarm.dial(relay_address.clone());
+        };
+        let addresses = quic_address(relay_address)
+            .into_iter()
+            .chain([relay_address.clone()]);
+        swarm.dial(
+            DialOpts::peer_id(relay_id)
+                .addresses(prefer_quic(addresses))
+                .override_dial_concurrency_factor(NonZero::new(1).expect("1 is NonZero"))
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build(),
+        )
     }
 
     async fn listen_peer_via_relay(
         swarm: &mut Swarm<Behaviour>,
         relay_addr: &Multiaddr,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<ListenerId, Box<dyn Error>> {
         let relay_address = relay_addr.clone();
-        swarm.listen_on(relay_address.with(Protocol::P2pCircuit))?;
-        Ok(())
+        Ok(swarm.listen_on(relay_address.with(Protocol::P2pCircuit))?)
     }
 
     fn init_swarm(
-        secret_key_seed: &str,
-        relay_addr: &mut Multiaddr,
+        keypair: identity::Keypair,
+        transports: Transports,
     ) -> Result<Swarm<Behaviour>, Box<dyn Error>> {
         let _ = tracing_subscriber::fmt()
             .with_env_filter(EnvFilter::from_default_env())
             .try_init();
 
         let mut swarm =
-            libp2p::SwarmBuilder::with_existing_identity(generate_ed25519(secret_key_seed))
+            libp2p::SwarmBuilder::with_existing_identity(keypair)
                 .with_tokio()
-                .with_tcp(
-                    tcp::Config::default().nodelay(true),
-                    noise::Config::new,
-                    yamux::Config::default,
-                )?
-                // .with_quic()
+                .with_other_transport(|keypair| transport(keypair, transports))?
                 // .with_dns()?
                 .with_relay_client(noise::Config::new, yamux::Config::default)?
                 .with_behaviour(|keypair, relay_behaviour| Behaviour {
                     relay_client: relay_behaviour,
                     ping: ping::Behaviour::new(ping::Config::new()),
-                    identify: identify::Behaviour::new(identify::Config::new(
-                        "/KUDRIVE/0.0.1".to_string(),
-                        keypair.public(),
-                    )),
+                    identify: identify::Behaviour::new(
+                        identify::Config::new(IDENTIFY_PROTOCOL.to_string(), keypair.public())
+                            .with_agent_version(format!(
+                                "kudrive-client/{}",
+                                env!("CARGO_PKG_VERSION")
+                            )),
+                    ),
                     dcutr: dcutr::Behaviour::new(keypair.public().to_peer_id()),
+                    mdns: Toggle::from(
+                        mdns::tokio::Behaviour::new(
+                            mdns::Config::default(),
+                            keypair.public().to_peer_id(),
+                        )
+                        .map_err(|e| tracing::warn!("LAN discovery unavailable: {}", e))
+                        .ok(),
+                    ),
                     ku_file_transfer: request_response::Behaviour::with_codec(
                         KuFileTransferCodec(),
-                        vec![(
-                            StreamProtocol::new("/ku-file-transfer/1.0.0"),
-                            ProtocolSupport::Full,
-                        )],
-                        Default::default(),
+                        ProtocolVersion::SUPPORTED
+                            .into_iter()
+                            .map(|version| (version.protocol(), ProtocolSupport::Full)),
+                        request_response::Config::default()
+                            .with_request_timeout(Duration::from_secs(REQUEST_TIMEOUT_SEC)),
                     ),
                     // ku_messaging: request_response::Behaviour::with_codec(
                     //     MessagingCodec,
@@ -771,8 +1892,9 @@ impl P2PTransport {
                     .with_notify_handler_buffer_size(NonZero::new(SWARM_NOTIFY_BUF_SIZE).expect("SWARM_NOTIFY_BUF_SIZE must be NonZero"))
                 )
                 .build();
-        // swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
-        swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+        for address in listen_addresses(transports, 0) {
+            swarm.listen_on(address)?;
+        }
 
         block_on(async {
             let mut delay =
@@ -784,7 +1906,7 @@ impl P2PTransport {
                             SwarmEvent::NewListenAddr { address, .. } => {
                                 tracing::info!(%address, "Listening on address");
                             }
-                            event => panic!("{event:?}"),
+                            event => tracing::debug!("{event:?}"),
                         }
                     }
                     _ = delay => {
@@ -797,76 +1919,219 @@ impl P2PTransport {
         Ok(swarm)
     }
 
+    // A peer on the local network is dialed directly, QUIC addresses first and one at a time;
+    // the relay is the fallback
     fn dial_peer(
         swarm: &mut Swarm<Behaviour>,
         remote_peer_id: PeerId,
-        relay_address: &Multiaddr,
+        relays: &Relays,
+        access: &Access,
+        lan: &mut LocalPeers,
+    ) -> Result<(), DialError> {
+        let addresses = prefer_quic(lan.addresses(&remote_peer_id));
+        if !addresses.is_empty() {
+            let opts = DialOpts::peer_id(remote_peer_id)
+                .addresses(addresses)
+                .override_dial_concurrency_factor(NonZero::new(1).expect("1 is NonZero"))
+                .condition(PeerCondition::Always)
+                .build();
+            match swarm.dial(opts) {
+                Ok(()) => {
+                    tracing::info!(peer=%remote_peer_id, "Dialing directly on the local network");
+                    lan.dialing(remote_peer_id);
+                    return Ok(());
+                }
+                Err(e) => tracing::warn!(peer=%remote_peer_id, "Direct dial failed: {}", e),
+            }
+        }
+        Self::dial_relayed(swarm, remote_peer_id, relays, access)
+    }
+
+    // Through the fastest relay first, the others in turn when the peer is not reserved there,
+    // then on the addresses the peer registered
+    fn dial_relayed(
+        swarm: &mut Swarm<Behaviour>,
+        remote_peer_id: PeerId,
+        relays: &Relays,
+        access: &Access,
     ) -> Result<(), DialError> {
+        let mut addresses: Vec<Multiaddr> = relays
+            .by_latency()
+            .into_iter()
+            .map(|relay_address| {
+                relay_address
+                    .with(Protocol::P2pCircuit)
+                    .with(Protocol::P2p(remote_peer_id))
+            })
+            .collect();
+        for address in prefer_quic(access.addresses(&remote_peer_id)) {
+            let address = match address.iter().last() {
+                Some(Protocol::P2p(_)) => address,
+                _ => address.with(Protocol::P2p(remote_peer_id)),
+            };
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
         swarm.dial(
-            relay_address
-                .clone()
-                .with(Protocol::P2pCircuit)
-                .with(Protocol::P2p(remote_peer_id)),
+            DialOpts::peer_id(remote_peer_id)
+                .addresses(addresses)
+                .override_dial_concurrency_factor(NonZero::new(1).expect("1 is NonZero"))
+                .condition(PeerCondition::Always)
+                .build(),
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn handle_swarm_event(
         swarm: &mut Swarm<Behaviour>,
-        relay_address: &Multiaddr,
         told_relay_observed_addr: &mut bool,
         learned_observed_addr: &mut bool,
         event: SwarmEvent<BehaviourEvent>,
         base_dir_path: std::path::PathBuf,
         pending_requests: &mut HashMap<String, oneshot::Sender<Result<(), String>>>,
+        transfers: &mut Transfers,
+        access: &Access,
+        throttle: &mut Throttle,
+        versions: &mut PeerVersions,
+        lan: &mut LocalPeers,
+        routes: &mut Routes,
+        relays: &mut Relays,
+        responder: &Sender<ClientEvent>,
     ) -> Result<(), Box<dyn Error>> {
         match event {
             SwarmEvent::NewListenAddr { address, .. } => {
                 tracing::info!(%address, "Listening on address");
             }
             SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
-                relay::client::Event::ReservationReqAccepted { .. },
+                relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    ..
+                },
             )) => {
-                tracing::info!("Relay accepted our reservation request");
+                tracing::info!(relay=%relay_peer_id, renewal, "Relay accepted our reservation request");
+                Self::report_relay(responder, relays.reserved(&relay_peer_id));
             }
-            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
-                tracing::info!(?event)
+            SwarmEvent::ListenerClosed { listener_id, .. } => {
+                if let Some(state) = relays.listener_closed(listener_id) {
+                    tracing::warn!(relay=%state.address, "Relay reservation ended");
+                    Self::report_relay(responder, Some(state));
+                }
             }
-            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => {
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
                 tracing::info!(?event)
             }
-            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Sent { .. })) => {
+            // the direct connection itself shows up as `ConnectionEstablished`
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            })) => match result {
+                Ok(_) => tracing::info!(peer=%remote_peer_id, "Hole punching succeeded"),
+                Err(e) => {
+                    tracing::info!(peer=%remote_peer_id, "Hole punching failed, staying relayed: {e}")
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Sent {
+                peer_id,
+                ..
+            })) => {
                 tracing::info!("Told relay its public address");
                 *told_relay_observed_addr = true;
                 if *told_relay_observed_addr && *learned_observed_addr {
-                    if let Some(sender) = pending_requests.remove(&relay_address.to_string()) {
-                        let _ = sender.send(Ok(()));
-                    }
+                    relays.answer(&peer_id, Ok(()));
                 }
             }
             SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
-                info: identify::Info { observed_addr, .. },
+                peer_id,
+                info,
                 ..
             })) => {
+                match versions.identify(peer_id, &info.agent_version, &info.protocols) {
+                    Some(Ok(version)) => {
+                        tracing::info!(peer=%peer_id, "File transfers use {}", version.protocol())
+                    }
+                    Some(Err(reason)) => tracing::warn!("Incompatible peer: {}", reason),
+                    None => {}
+                }
+                let observed_addr = info.observed_addr;
+                // hole punching needs to know where peers can reach us from outside the NAT
+                if relays.is_relay(&peer_id) {
+                    swarm.add_external_address(observed_addr.clone());
+                }
                 tracing::info!(address=%observed_addr, "Relay told us our observed address");
                 *learned_observed_addr = true;
                 if *told_relay_observed_addr && *learned_observed_addr {
-                    if let Some(sender) = pending_requests.remove(&relay_address.to_string()) {
-                        let _ = sender.send(Ok(()));
+                    relays.answer(&peer_id, Ok(()));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(found))) => {
+                for (peer_id, address) in found {
+                    if lan.discovered(peer_id, address.clone()) {
+                        tracing::info!(peer=%peer_id, %address, "Found peer on the local network");
                     }
                 }
             }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Expired(gone))) => {
+                for (peer_id, address) in gone {
+                    lan.expired(&peer_id, &address);
+                }
+            }
+            // relays are ranked by round trip time
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event {
+                peer,
+                result: Ok(rtt),
+                ..
+            })) => relays.rtt(&peer, rtt),
             SwarmEvent::Behaviour(BehaviourEvent::Ping(_)) => {}
             SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
             } => {
+                lan.connected(&peer_id);
+                Self::report_relay(responder, relays.connected(&peer_id));
+                let route = match endpoint.is_relayed() {
+                    true => Route::Relayed,
+                    false => Route::Direct,
+                };
+                routes.established(peer_id, connection_id, route);
+                // requests in flight on a closed connection are sent again on the direct one
+                for relayed in routes.superseded(&peer_id) {
+                    tracing::info!(peer=%peer_id, "Direct connection up, closing the relayed one");
+                    swarm.close_connection(relayed);
+                }
                 if let Some(sender) = pending_requests.remove(&peer_id.to_string()) {
                     tracing::info!(peer=%peer_id, ?endpoint, "Established new connection!!!");
                     let _ = sender.send(Ok(()));
                 }
                 tracing::info!(peer=%peer_id, ?endpoint, "Established new connection");
             }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
+                routes.closed(&peer_id, connection_id);
+                if num_established == 0 && relays.is_relay(&peer_id) {
+                    tracing::warn!(relay=%peer_id, "Lost the relay connection");
+                    Self::report_relay(responder, relays.lost(&peer_id, Instant::now()));
+                    relays.answer(&peer_id, Err("Lost the relay connection".into()));
+                }
+            }
             SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
                 if let Some(peer_id) = peer_id {
+                    Self::report_relay(responder, relays.lost(&peer_id, Instant::now()));
+                    relays.answer(&peer_id, Err(error.to_string()));
+                    // whoever waits on the connection keeps waiting for the relayed one
+                    if lan.dial_failed(&peer_id) {
+                        tracing::warn!(peer=%peer_id, "Direct dial failed, using the relay: {error}");
+                        if Self::dial_relayed(swarm, peer_id, relays, access).is_ok() {
+                            return Ok(());
+                        }
+                    }
                     if let Some(sender) = pending_requests.remove(&peer_id.to_string()) {
                         let _ = sender.send(Err(error.to_string()));
                     }
@@ -906,139 +2171,211 @@ impl P2PTransport {
             SwarmEvent::Behaviour(BehaviourEvent::KuFileTransfer(event)) => match event {
                 request_response::Event::Message { peer, message } => match message {
                     request_response::Message::Request {
-                        request, channel, ..
+                        mut request,
+                        channel,
+                        ..
                     } => {
-                        tracing::info!("Received file request: {:?}", request);
-                        let sender_opt: Option<oneshot::Sender<Result<(), String>>>;
-                        if let Some(sender) = pending_requests.remove(&request.target_path) {
-                            sender_opt = Some(sender);
-                        } else {
-                            tracing::warn!(
-                                "No pending request found for response file: {}",
-                                request.file_name
-                            );
-                            sender_opt = None;
-                        }
-                        let file_path = base_dir_path.join(&request.target_path);
-                        // let file_path = std::path::Path::new(&request.target_path);
-                        if let Ok(mut file) = File::open(file_path).await {
-                            let mut content = Vec::new();
-                            if file.read_to_end(&mut content).await.is_ok() {
-                                let response = ku_protocol::FileResponse {
-                                    file_name: request.file_name.clone(),
-                                    content,
-                                    src_path: request.target_path,
-                                    tgt_path: request.save_path,
-                                };
-                                swarm
-                                    .behaviour_mut()
-                                    .ku_file_transfer
-                                    .send_response(channel, response)
-                                    .expect("Failed to send file response");
-                                if let Some(sender) = sender_opt {
-                                    let _ = sender.send(Ok(()));
-                                }
-                                tracing::info!("Sent file: {}", request.file_name);
-                            } else {
-                                let error_response = ku_protocol::FileResponse {
-                                    file_name: "KUDrive Error: File read error".to_string(),
-                                    content: Vec::new(),
-                                    src_path: request.target_path.clone(),
-                                    tgt_path: request.save_path,
-                                };
-                                swarm
-                                    .behaviour_mut()
-                                    .ku_file_transfer
-                                    .send_response(channel, error_response)
-                                    .expect("Failed to send error response");
-                                if let Some(sender) = sender_opt {
-                                    let _ = sender.send(Err(format!(
-                                        "Failed to read file after open: {}",
-                                        request.target_path
-                                    )));
+                        tracing::info!(
+                            "Received file request: {:?} at {}",
+                            request.target_path,
+                            request.offset
+                        );
+                        let cancelled =
+                            |message: String| FileError::new(ErrorCode::Cancelled, message);
+                        let denial = match access.authorize(&peer, &request.target_path) {
+                            Err(error) => {
+                                tracing::warn!("Denied file request from {}: {}", peer, error);
+                                Some(error)
+                            }
+                            Ok(()) if request.cancel => {
+                                let key = transfers
+                                    .upload_key(&request.target_path)
+                                    .unwrap_or_else(|| request.target_path.clone());
+                                transfers.uploads.remove(&key);
+                                if let Some(sender) = transfers.senders.remove(&key) {
+                                    let message = "Transfer cancelled by peer";
+                                    let error = FileError::new(ErrorCode::Cancelled, message);
+                                    let _ = sender.send(Err(error));
                                 }
-                                tracing::error!(
-                                    "Failed to read file after open: {}",
-                                    request.file_name
-                                );
+                                tracing::info!("{} cancelled the transfer of {:?}", peer, key);
+                                Some(cancelled(format!("Transfer cancelled: {}", key)))
                             }
-                        } else {
-                            let error_response: ku_protocol::FileResponse =
-                                ku_protocol::FileResponse {
-                                    file_name: "KUDrive Error: File Not Found".to_string(),
-                                    content: Vec::new(),
-                                    src_path: request.target_path.clone(),
-                                    tgt_path: request.save_path,
-                                };
-                            swarm
+                            Ok(()) if transfers.take_cancelled(&request.target_path) => Some(
+                                cancelled(format!("Transfer cancelled: {}", request.target_path)),
+                            ),
+                            Ok(()) => None,
+                        };
+                        if let Some(error) = denial {
+                            let response = transfer::deny(&request, error);
+                            if swarm
                                 .behaviour_mut()
                                 .ku_file_transfer
-                                .send_response(channel, error_response)
-                                .expect("Failed to send error response");
-                            if let Some(sender) = sender_opt {
-                                let _ = sender.send(Err(format!(
-                                    "Failed to open file: {}",
-                                    request.target_path
-                                )));
+                                .send_response(channel, response)
+                                .is_err()
+                            {
+                                tracing::error!(
+                                    "Failed to send file response: {}",
+                                    request.file_name
+                                );
                             }
-                            tracing::error!("File not found: {}", request.file_name);
+                            return Ok(());
+                        }
+                        let upload = transfers
+                            .upload_key(&request.target_path)
+                            .and_then(|key| transfers.uploads.get_mut(&key));
+                        match upload {
+                            Some(upload) => upload.touch(),
+                            None => tracing::warn!(
+                                "No pending request found for response file: {}",
+                                request.file_name
+                            ),
                         }
+                        // throttled chunks are smaller and answered once their share of the
+                        // budget is free, so a peer that does not pace itself is held to it too.
+                        // A 1.0.0 reply is never cut short: files of up to a chunk are sent
+                        // whole however long that waits, larger ones are refused
+                        if !request.legacy {
+                            request.length =
+                                throttle.chunk_len(Direction::Send, request.length.min(CHUNK_SIZE));
+                        }
+                        let delay = throttle.reserve(Direction::Send, peer, request.length);
+                        // disk reads and hashing stay off the event loop
+                        let events = transfers.events();
+                        let digests = transfers.digests.clone();
+                        let deltas = transfers.served_deltas.clone();
+                        let ignored = transfers.ignored.clone();
+                        tokio::spawn(async move {
+                            if !delay.is_zero() {
+                                time::sleep(delay).await;
+                            }
+                            let (response, result) = transfer::serve_chunk(
+                                &base_dir_path,
+                                &digests,
+                                &deltas,
+                                peer,
+                                ignored,
+                                request,
+                            )
+                            .await;
+                            let event = TransferEvent::Served {
+                                channel,
+                                response: Box::new(response),
+                                result,
+                            };
+                            let _ = events.send(event).await;
+                        });
                     }
-                    request_response::Message::Response { response, .. } => {
-                        tracing::info!(
-                            "File Response recieved: {:?} > {:?} ",
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    } => {
+                        transfers.sent.remove(&request_id);
+                        if let Some(sender) = transfers.listings.remove(&request_id) {
+                            let listing = response.status.map(|()| response.entries);
+                            let _ = sender.send(listing);
+                            return Ok(());
+                        }
+                        if let Some(sender) = transfers.probes.remove(&request_id) {
+                            let probe = response.status.and_then(|()| match response.entries {
+                                Some(_) => {
+                                    let message = format!("Not a file: {}", response.src_path);
+                                    Err(FileError::new(ErrorCode::ReadError, message))
+                                }
+                                None => Ok((
+                                    response.size,
+                                    response.digest,
+                                    response.meta.unwrap_or_default(),
+                                )),
+                            });
+                            let _ = sender.send(probe);
+                            return Ok(());
+                        }
+                        if let Some(sender) = transfers.skips.remove(&request_id) {
+                            let _ = sender.send(response.status);
+                            return Ok(());
+                        }
+                        if let Some(sender) = transfers.deltas.remove(&request_id) {
+                            let _ = sender.send(response.status.map(|()| response.delta));
+                            return Ok(());
+                        }
+                        tracing::debug!(
+                            "File Response recieved: {:?} > {:?} at {}",
                             &response.src_path,
-                            &response.tgt_path
+                            &response.tgt_path,
+                            response.offset
                         );
-                        let sender_opt: Option<oneshot::Sender<Result<(), String>>>;
-                        if let Some(sender) = pending_requests.remove(&response.src_path) {
-                            sender_opt = Some(sender);
-                        } else {
+                        // mirrors answer for the download too, so it is found by the request
+                        let Some(key) = transfers.find_download(request_id) else {
+                            // a source dropped earlier may still answer
                             tracing::warn!(
-                                "No pending request found for response file: {}",
-                                response.file_name
+                                "No pending request found for response file {} from {:?}",
+                                response.file_name,
+                                peer
                             );
-                            sender_opt = None;
-                        }
-                        if response.file_name.starts_with("KUDrive Error:") {
+                            return Ok(());
+                        };
+                        let Some(mut download) = transfers.downloads.remove(&key) else {
+                            return Ok(());
+                        };
+                        let result = if let Err(ref error) = response.status {
                             tracing::error!(
-                                "Error occurred while receiving the file: {}",
-                                response.file_name
+                                "Error occurred while receiving the file {}: {}",
+                                response.file_name,
+                                error
                             );
-                            if let Some(sender) = sender_opt {
-                                let _ = sender.send(Err(response.file_name));
+                            if error.code != ErrorCode::Cancelled && download.drop_source(peer) {
+                                tracing::warn!(
+                                    "Falling back from {:?} for {}",
+                                    peer,
+                                    response.file_name
+                                );
+                                None
+                            } else {
+                                // neither can be resumed later
+                                if matches!(error.code, ErrorCode::NotFound | ErrorCode::Cancelled)
+                                {
+                                    download.discard().await;
+                                }
+                                Some(Err(error.clone()))
                             }
                         } else {
-                            tracing::info!("Received file from {:?}: {}", peer, response.file_name);
-                            // if let file_path = base_dir_path.join(&response.file_name) {
-                            if let Ok(mut file_path) = PathBuf::from_str(&response.tgt_path) {
-                                file_path = base_dir_path.join(file_path);
-                                // if tokio::fs::write(&file_path, &response.content)
-                                if tokio::fs::write(&file_path, &response.content)
-                                    .await
-                                    .is_ok()
-                                {
-                                    tracing::info!("File saved to: {:?}", file_path);
-                                    if let Some(sender) = sender_opt {
-                                        let _ = sender.send(Ok(()));
-                                    }
-                                } else {
-                                    tracing::error!("Failed to save file to: {:?}", file_path);
-                                    if let Some(sender) = sender_opt {
-                                        let _ = sender.send(Err(format!(
-                                            "Failed to save file: {}",
-                                            response.file_name
-                                        )));
-                                    }
+                            let written = download.write_chunk(request_id, &response).await;
+                            if let (Ok(_), Some(progress)) = (&written, download.progress()) {
+                                Self::report(responder, progress);
+                            }
+                            match written {
+                                Ok(true) => {
+                                    tracing::info!(
+                                        "Received file from {:?}: {}",
+                                        peer,
+                                        response.file_name
+                                    );
+                                    // verify against the sender's digest before committing
+                                    let events = transfers.events();
+                                    tokio::spawn(async move {
+                                        let result = download.commit().await;
+                                        let event = TransferEvent::Completed { key, result };
+                                        let _ = events.send(event).await;
+                                    });
+                                    return Ok(());
                                 }
-                            } else {
-                                if let Some(sender) = sender_opt {
-                                    let _ = sender.send(Err(format!(
-                                        "Invalid target path: {}",
-                                        response.tgt_path
-                                    )));
+                                Ok(false) => None,
+                                Err(e) => {
+                                    tracing::error!(
+                                        "Failed to save file to: {:?}: {:?}",
+                                        download.path(),
+                                        e
+                                    );
+                                    let message =
+                                        format!("Failed to save file: {}", response.file_name);
+                                    Some(Err(FileError::new(ErrorCode::WriteError, message)))
                                 }
                             }
+                        };
+                        match result {
+                            Some(result) => transfers.resolve(&key, result),
+                            // pull the next chunk only once this one is on disk
+                            None => Self::pull(swarm, transfers, throttle, download),
                         }
                     }
                 },
@@ -1051,17 +2388,71 @@ impl P2PTransport {
                         peer=%peer, request_id=?request_id,
                         "Outbound failure occurred: {:?}", error
                     );
+                    // a relayed connection closed for a direct one, the peer is still there
+                    let resend = transfers.sent.remove(&request_id).filter(|_| {
+                        matches!(error, OutboundFailure::ConnectionClosed)
+                            && swarm.is_connected(&peer)
+                    });
+                    if let Some((peer, request)) = resend {
+                        let resent = Self::send_once(swarm, transfers, peer, request);
+                        transfers.resent(request_id, resent);
+                        return Ok(());
+                    }
+                    // a peer without a common version is reported as such, not as a network error
+                    let failure = |what: &str| match error {
+                        OutboundFailure::UnsupportedProtocols => {
+                            FileError::new(ErrorCode::Incompatible, versions.incompatible(&peer))
+                        }
+                        _ => FileError::new(ErrorCode::Network, format!("{}: {}", what, error)),
+                    };
+                    if let Some(sender) = transfers.listings.remove(&request_id) {
+                        let _ = sender.send(Err(failure("Folder request failed")));
+                    }
+                    if let Some(sender) = transfers.probes.remove(&request_id) {
+                        let _ = sender.send(Err(failure("Probe failed")));
+                    }
+                    if let Some(sender) = transfers.skips.remove(&request_id) {
+                        let _ = sender.send(Err(failure("Skip notice failed")));
+                    }
+                    if let Some(sender) = transfers.deltas.remove(&request_id) {
+                        let _ = sender.send(Err(failure("Delta request failed")));
+                    }
+                    let download = transfers
+                        .find_download(request_id)
+                        .and_then(|key| transfers.downloads.remove(&key));
+                    if let Some(mut download) = download {
+                        // a relayed connection closed for a direct one, the peer is still there
+                        if matches!(error, OutboundFailure::ConnectionClosed)
+                            && swarm.is_connected(&peer)
+                        {
+                            download.resend(request_id);
+                            Self::pull(swarm, transfers, throttle, download);
+                        } else if download.drop_source(peer) {
+                            tracing::warn!(
+                                "Falling back from {:?} for {}",
+                                peer,
+                                download.file_name
+                            );
+                            Self::pull(swarm, transfers, throttle, download);
+                        } else {
+                            let error = failure("File request failed");
+                            transfers.resolve(&download.key(), Err(error));
+                        }
+                    }
                 }
                 request_response::Event::InboundFailure {
                     peer,
                     request_id,
                     error,
-                } => {
-                    tracing::error!(
+                } => match error {
+                    InboundFailure::UnsupportedProtocols => {
+                        tracing::warn!("Incompatible peer: {}", versions.incompatible(&peer))
+                    }
+                    _ => tracing::error!(
                         peer=%peer, request_id=?request_id,
                         "Inbound failure occurred: {:?}", error
-                    );
-                }
+                    ),
+                },
                 request_response::Event::ResponseSent { peer, request_id } => {
                     tracing::info!(peer=%peer, request_id=?request_id, "Response sent successfully");
                 }
@@ -1075,7 +2466,7 @@ impl P2PTransport {
 pub async fn run_cli_command(
     client: &mut P2PTransport,
     cmd: &str,
-    send_rx: &mut Option<oneshot::Receiver<Result<(), String>>>,
+    send_rx: &mut Option<oneshot::Receiver<Result<(), FileError>>>,
 ) -> bool {
     match cmd {
         "exit" => {
@@ -1114,8 +2505,13 @@ pub async fn run_cli_command(
                     P2pStatus::RelayConnected => tracing::info!("Status : Relay connected"),
                     P2pStatus::PeerConnected(peers) => {
                         tracing::info!("Status : Peer connected");
+                        let routes = client.routes(5).await.unwrap_or_default();
                         for peer in peers {
-                            tracing::info!("  - {:?}", peer);
+                            let route = routes
+                                .iter()
+                                .find(|route| route.peer_id == peer)
+                                .map(|route| route.route);
+                            tracing::info!("  - {:?} ({:?})", peer, route);
                         }
                     }
                 },
@@ -1241,7 +2637,7 @@ pub async fn run_cli_command(
                 let target_path = parts[2].to_string();
                 let save_path = parts[3].to_string();
                 tokio::select! {
-                    res = client.recv_file(remote_peer_id, target_path, save_path, 10) => {
+                    res = client.recv_file(remote_peer_id, target_path, save_path, ConflictPolicy::default(), 10) => {
                         match res {
                             Ok(_) => tracing::info!("File received successfully."),
                             Err(e) => tracing::error!("Failed to receive file: {:?}", e)
@@ -1294,7 +2690,7 @@ pub async fn run_cli_command(
                         tracing::info!(">>{:?}", "Aborted");
                         return true;
                     }
-                    res = client.send_file_wait(rx, 30)=> {
+                    res = client.send_file_wait(rx)=> {
                         match res {
                             Ok(_) => tracing::info!("File sent successfully."),
                             Err(e) => tracing::error!("Failed to send file: {}", e),
@@ -0,0 +1,314 @@
+use std::time::{Duration, Instant};
+
+use libp2p::{core::transport::ListenerId, multiaddr::Protocol, Multiaddr, PeerId};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+// Redials wait twice as long after every failure, up to a minute
+const BACKOFF_BASE_SEC: u64 = 1;
+const BACKOFF_MAX_SEC: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RelayStatus {
+    Connecting,
+    Connected,
+    // peers can reach us through the relay
+    Reserved,
+    Disconnected { retry_in_sec: u64 },
+}
+
+// The relay connection and reservation as the swarm reports them, and when to redial.
+// Every method returns the new status when it changed
+pub struct RelayWatch {
+    status: RelayStatus,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Default for RelayWatch {
+    fn default() -> Self {
+        Self {
+            status: RelayStatus::Connecting,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+}
+
+impl RelayWatch {
+    pub fn status(&self) -> RelayStatus {
+        self.status
+    }
+
+    pub fn connected(&mut self) -> Option<RelayStatus> {
+        self.failures = 0;
+        self.retry_at = None;
+        match self.status {
+            RelayStatus::Connected | RelayStatus::Reserved => None,
+            _ => self.set(RelayStatus::Connected),
+        }
+    }
+
+    pub fn reserved(&mut self) -> Option<RelayStatus> {
+        self.set(RelayStatus::Reserved)
+    }
+
+    // The circuit listener closed while the relay itself is still there
+    pub fn unreserved(&mut self) -> Option<RelayStatus> {
+        match self.status {
+            RelayStatus::Reserved => self.set(RelayStatus::Connected),
+            _ => None,
+        }
+    }
+
+    // The connection dropped or a dial failed, a redial is due after the backoff
+    pub fn lost(&mut self, now: Instant) -> Option<RelayStatus> {
+        if self.retry_at.is_some() {
+            return None;
+        }
+        let delay = Self::backoff(self.failures);
+        self.failures = self.failures.saturating_add(1);
+        self.retry_at = Some(now + delay);
+        self.set(RelayStatus::Disconnected {
+            retry_in_sec: delay.as_secs(),
+        })
+    }
+
+    // Whether to redial now
+    pub fn due(&mut self, now: Instant) -> bool {
+        match self.retry_at {
+            Some(retry_at) if retry_at <= now => {
+                self.retry_at = None;
+                self.status = RelayStatus::Connecting;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn backoff(failures: u32) -> Duration {
+        let factor = 1u64.checked_shl(failures).unwrap_or(u64::MAX);
+        Duration::from_secs(BACKOFF_BASE_SEC.saturating_mul(factor).min(BACKOFF_MAX_SEC))
+    }
+
+    fn set(&mut self, status: RelayStatus) -> Option<RelayStatus> {
+        if self.status == status {
+            return None;
+        }
+        self.status = status;
+        Some(status)
+    }
+}
+
+// The relay's own id, the last part of its address
+pub fn peer_id(address: &Multiaddr) -> Option<PeerId> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::P2p(peer_id) => Some(peer_id),
+        _ => None,
+    })
+}
+
+// One relay as the client reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RelayState {
+    pub address: String,
+    pub status: RelayStatus,
+    pub rtt_ms: Option<u64>,
+}
+
+struct Relay {
+    id: PeerId,
+    address: Multiaddr,
+    watch: RelayWatch,
+    rtt: Option<Duration>,
+    // the `/p2p-circuit` listener holding our reservation
+    listener: Option<ListenerId>,
+    // callers waiting for the relay to tell us our observed address
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+}
+
+impl Relay {
+    fn state(&self) -> RelayState {
+        RelayState {
+            address: self.address.to_string(),
+            status: self.watch.status(),
+            rtt_ms: self.rtt.map(|rtt| rtt.as_millis() as u64),
+        }
+    }
+
+    // Reserved relays first, the fastest among them before the rest
+    fn rank(&self) -> (u8, Duration) {
+        let status = match self.watch.status() {
+            RelayStatus::Reserved => 0,
+            RelayStatus::Connected => 1,
+            RelayStatus::Connecting => 2,
+            RelayStatus::Disconnected { .. } => 3,
+        };
+        (status, self.rtt.unwrap_or(Duration::MAX))
+    }
+}
+
+// Every configured relay; we hold a reservation on each so peers can reach us through
+// whichever they use, and dial peers through the fastest one first. The methods taking
+// a peer return the relay's new state when it changed
+pub struct Relays {
+    relays: Vec<Relay>,
+}
+
+impl Relays {
+    // Addresses without a relay id cannot be told apart from peers and are left out
+    pub fn new(addresses: Vec<Multiaddr>) -> Self {
+        let mut relays: Vec<Relay> = Vec::new();
+        for address in addresses {
+            match peer_id(&address) {
+                Some(id) if relays.iter().all(|relay| relay.id != id) => relays.push(Relay {
+                    id,
+                    address,
+                    watch: RelayWatch::default(),
+                    rtt: None,
+                    listener: None,
+                    waiters: Vec::new(),
+                }),
+                Some(_) => tracing::warn!(%address, "Relay listed twice"),
+                None => tracing::warn!(%address, "Relay address without a peer id"),
+            }
+        }
+        Self { relays }
+    }
+
+    pub fn is_relay(&self, peer: &PeerId) -> bool {
+        self.relays.iter().any(|relay| relay.id == *peer)
+    }
+
+    pub fn ids(&self) -> Vec<PeerId> {
+        self.relays.iter().map(|relay| relay.id).collect()
+    }
+
+    pub fn addresses(&self) -> Vec<(PeerId, Multiaddr)> {
+        self.relays
+            .iter()
+            .map(|relay| (relay.id, relay.address.clone()))
+            .collect()
+    }
+
+    // Best first, see `Relay::rank`
+    pub fn by_latency(&self) -> Vec<Multiaddr> {
+        let mut relays: Vec<&Relay> = self.relays.iter().collect();
+        relays.sort_by_key(|relay| relay.rank());
+        relays
+            .into_iter()
+            .map(|relay| relay.address.clone())
+            .collect()
+    }
+
+    pub fn states(&self) -> Vec<RelayState> {
+        self.relays.iter().map(Relay::state).collect()
+    }
+
+    pub fn state(&self, peer: &PeerId) -> Option<RelayState> {
+        self.find(peer).map(Relay::state)
+    }
+
+    pub fn connected(&mut self, peer: &PeerId) -> Option<RelayState> {
+        self.update(peer, |relay| relay.watch.connected())
+    }
+
+    pub fn reserved(&mut self, peer: &PeerId) -> Option<RelayState> {
+        self.update(peer, |relay| relay.watch.reserved())
+    }
+
+    pub fn lost(&mut self, peer: &PeerId, now: Instant) -> Option<RelayState> {
+        self.update(peer, |relay| relay.watch.lost(now))
+    }
+
+    pub fn rtt(&mut self, peer: &PeerId, rtt: Duration) {
+        if let Some(relay) = self.find_mut(peer) {
+            relay.rtt = Some(rtt);
+        }
+    }
+
+    pub fn listening(&mut self, peer: &PeerId, listener: ListenerId) {
+        if let Some(relay) = self.find_mut(peer) {
+            relay.listener = Some(listener);
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.relays.iter().any(|relay| relay.listener.is_some())
+    }
+
+    // The reservation behind `listener` ended, the relay may still be connected
+    pub fn listener_closed(&mut self, listener: ListenerId) -> Option<RelayState> {
+        let relay = self
+            .relays
+            .iter_mut()
+            .find(|relay| relay.listener == Some(listener))?;
+        relay.listener = None;
+        relay.watch.unreserved().map(|_| relay.state())
+    }
+
+    // Relays holding no reservation for us, `connected` leaves out those not there now
+    pub fn not_listening(&self, connected: bool) -> Vec<(PeerId, Multiaddr)> {
+        self.relays
+            .iter()
+            .filter(|relay| relay.listener.is_none())
+            .filter(|relay| !connected || relay.watch.status() == RelayStatus::Connected)
+            .map(|relay| (relay.id, relay.address.clone()))
+            .collect()
+    }
+
+    // `waiter` is answered once the relay is ready or its dial failed
+    pub fn wait(&mut self, peer: &PeerId, waiter: oneshot::Sender<Result<(), String>>) {
+        if let Some(relay) = self.find_mut(peer) {
+            relay.waiters.push(waiter);
+        }
+    }
+
+    // Relays someone is waiting on
+    pub fn waiting(&self) -> Vec<PeerId> {
+        self.relays
+            .iter()
+            .filter(|relay| !relay.waiters.is_empty())
+            .map(|relay| relay.id)
+            .collect()
+    }
+
+    // Answers everyone waiting on the relay
+    pub fn answer(&mut self, peer: &PeerId, result: Result<(), String>) {
+        if let Some(relay) = self.find_mut(peer) {
+            for waiter in relay.waiters.drain(..) {
+                let _ = waiter.send(result.clone());
+            }
+        }
+    }
+
+    // Lost relays whose backoff is over, now being redialed
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, Multiaddr)> {
+        self.relays
+            .iter_mut()
+            .filter_map(|relay| {
+                relay
+                    .watch
+                    .due(now)
+                    .then(|| (relay.id, relay.address.clone()))
+            })
+            .collect()
+    }
+
+    fn find(&self, peer: &PeerId) -> Option<&Relay> {
+        self.relays.iter().find(|relay| relay.id == *peer)
+    }
+
+    fn find_mut(&mut self, peer: &PeerId) -> Option<&mut Relay> {
+        self.relays.iter_mut().find(|relay| relay.id == *peer)
+    }
+
+    fn update(
+        &mut self,
+        peer: &PeerId,
+        change: impl FnOnce(&mut Relay) -> Option<RelayStatus>,
+    ) -> Option<RelayState> {
+        let relay = self.find_mut(peer)?;
+        change(relay).map(|_| relay.state())
+    }
+}
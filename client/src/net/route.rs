@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use libp2p::{swarm::ConnectionId, PeerId};
+use serde::Serialize;
+
+// How a peer is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Route {
+    Direct,
+    Relayed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerRoute {
+    pub peer_id: String,
+    pub route: Route,
+}
+
+// Open connections per peer. Requests are spread over all of them, so once hole punching
+// yields a direct one the relayed ones are closed and transfers move over
+#[derive(Default)]
+pub struct Routes {
+    connections: HashMap<PeerId, HashMap<ConnectionId, Route>>,
+}
+
+impl Routes {
+    pub fn established(&mut self, peer: PeerId, connection: ConnectionId, route: Route) {
+        self.connections
+            .entry(peer)
+            .or_default()
+            .insert(connection, route);
+    }
+
+    pub fn closed(&mut self, peer: &PeerId, connection: ConnectionId) {
+        if let Some(connections) = self.connections.get_mut(peer) {
+            connections.remove(&connection);
+            if connections.is_empty() {
+                self.connections.remove(peer);
+            }
+        }
+    }
+
+    // Direct as soon as any connection is, `None` when not connected
+    pub fn route(&self, peer: &PeerId) -> Option<Route> {
+        let connections = self.connections.get(peer)?;
+        match connections.values().any(|route| *route == Route::Direct) {
+            true => Some(Route::Direct),
+            false => Some(Route::Relayed),
+        }
+    }
+
+    // Relayed connections a direct one has made redundant
+    pub fn superseded(&self, peer: &PeerId) -> Vec<ConnectionId> {
+        if self.route(peer) != Some(Route::Direct) {
+            return Vec::new();
+        }
+        self.connections[peer]
+            .iter()
+            .filter(|(_, route)| **route == Route::Relayed)
+            .map(|(connection, _)| *connection)
+            .collect()
+    }
+
+    // Every peer but the relays
+    pub fn list(&self, relays: &[PeerId]) -> Vec<PeerRoute> {
+        let mut routes: Vec<PeerRoute> = self
+            .connections
+            .keys()
+            .filter(|peer| !relays.contains(peer))
+            .filter_map(|peer| {
+                self.route(peer).map(|route| PeerRoute {
+                    peer_id: peer.to_string(),
+                    route,
+                })
+            })
+            .collect();
+        routes.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        routes
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use super::ku_protocol::{ErrorCode, FileError};
+
+// Resolves a peer-supplied path against the workspace. Absolute paths and `..` are
+// refused outright, and whatever part of the path exists may not link outside.
+pub fn resolve(base_dir: &Path, requested: &str) -> Result<PathBuf, FileError> {
+    let rejected = |reason: &str| {
+        tracing::warn!("Rejected path {:?}: {}", requested, reason);
+        FileError::new(ErrorCode::Rejected, format!("{}: {}", reason, requested))
+    };
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(requested).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(rejected("Path leaves the workspace")),
+            Component::RootDir | Component::Prefix(_) => return Err(rejected("Absolute path")),
+        }
+    }
+
+    let root = base_dir.canonicalize().map_err(|e| {
+        let message = format!("Workspace unavailable: {}", base_dir.display());
+        FileError::read(&e, message)
+    })?;
+    let path = root.join(&relative);
+
+    // the deepest existing ancestor decides where the path really points
+    let mut existing = path.as_path();
+    let resolved = loop {
+        match existing.canonicalize() {
+            Ok(resolved) => break resolved,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // a dangling link would still be followed when the file is created
+                if existing.symlink_metadata().is_ok() {
+                    return Err(rejected("Broken link"));
+                }
+                match existing.parent() {
+                    Some(parent) => existing = parent,
+                    None => return Err(rejected("Path leaves the workspace")),
+                }
+            }
+            Err(e) => {
+                return Err(FileError::read(
+                    &e,
+                    format!("Failed to resolve: {}", requested),
+                ))
+            }
+        }
+    };
+    if !resolved.starts_with(&root) {
+        return Err(rejected("Path leaves the workspace"));
+    }
+
+    let missing = path
+        .strip_prefix(existing)
+        .expect("existing path is an ancestor");
+    // joining an empty path would leave a trailing `/`, which no file opens through
+    if missing.as_os_str().is_empty() {
+        return Ok(resolved);
+    }
+    Ok(resolved.join(missing))
+}
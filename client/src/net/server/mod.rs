@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use kudrive_common::message::client::ClientMessage;
 use kudrive_common::message::server::ServerMessage;
-use kudrive_common::{Client, FileMap, Listener, Transmitter};
+use kudrive_common::{p2p::PeerIdentity, Client, FileMap, Listener, Transmitter};
 use tokio::io::{self, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::Sender;
@@ -45,12 +45,13 @@ impl Server {
         Ok(())
     }
 
-    pub async fn register(&mut self) -> io::Result<()> {
+    pub async fn register(&mut self, identity: PeerIdentity) -> io::Result<()> {
         let client = Client {
             group: get_group_id(),
             id: get_uuid(),
             nickname: get_nickname(),
             files: get_resolved_filemap(),
+            identity,
         };
 
         let message = ClientMessage::Register { client };
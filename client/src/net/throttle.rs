@@ -0,0 +1,65 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::PeerId;
+use tokio::time::Instant;
+
+use crate::config_loader::RateLimits;
+use crate::event::progress::Direction;
+
+// Paces chunks against the configured rate limits. Every chunk books its bytes
+// ahead of time and waits until both the global and the peer's budget reach it.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    limits: RateLimits,
+    // when each budget is free again
+    global: HashMap<Direction, Instant>,
+    peers: HashMap<(PeerId, Direction), Instant>,
+}
+
+impl Throttle {
+    pub fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+        // bookings made under the old limits no longer apply
+        self.global.clear();
+        self.peers.clear();
+    }
+
+    fn rates(&self, direction: Direction) -> (Option<u64>, Option<u64>) {
+        let (global, peer) = match direction {
+            Direction::Send => (self.limits.upload, self.limits.peer_upload),
+            Direction::Receive => (self.limits.download, self.limits.peer_download),
+        };
+        // zero means unlimited, like unset
+        (
+            global.filter(|rate| *rate > 0),
+            peer.filter(|rate| *rate > 0),
+        )
+    }
+
+    // At most a second's worth per chunk, so a throttled chunk never waits for long
+    pub fn chunk_len(&self, direction: Direction, length: u64) -> u64 {
+        let (global, peer) = self.rates(direction);
+        [global, peer].into_iter().flatten().fold(length, u64::min)
+    }
+
+    // Books `bytes` for `peer`, returning how long to wait before moving them
+    pub fn reserve(&mut self, direction: Direction, peer: PeerId, bytes: u64) -> Duration {
+        let (global_rate, peer_rate) = self.rates(direction);
+        if bytes == 0 || (global_rate.is_none() && peer_rate.is_none()) {
+            return Duration::ZERO;
+        }
+
+        let now = Instant::now();
+        let global = self.global.entry(direction).or_insert(now);
+        let peer = self.peers.entry((peer, direction)).or_insert(now);
+        let start = now.max(*global).max(*peer);
+        let cost = |rate: u64| Duration::from_secs_f64(bytes as f64 / rate as f64);
+        if let Some(rate) = global_rate {
+            *global = start + cost(rate);
+        }
+        if let Some(rate) = peer_rate {
+            *peer = start + cost(rate);
+        }
+        start - now
+    }
+}
@@ -0,0 +1,1166 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use libp2p::{
+    request_response::{OutboundRequestId, ResponseChannel},
+    PeerId,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+use super::delta::{self, Delta, Signature};
+use super::ku_protocol::{
+    fits_response, join_remote, ErrorCode, FileError, FileMeta, FileRequest, FileResponse,
+    FolderEntry, CHUNK_SIZE,
+};
+use super::{meta, sandbox};
+use crate::event::progress::{Direction, Meter, Progress};
+
+const TRANSFER_EVENT_BUFF_SIZE: usize = 1024;
+const MAX_CACHED_DIGESTS: usize = 1024;
+const MAX_CACHED_DELTAS: usize = 64;
+// diffs one peer may have running at once, each reads a whole file
+const MAX_PEER_DELTAS: usize = 2;
+// how long a delta request waits on the diff before answering that it is still running
+const DELTA_WAIT_MS: u64 = 1000;
+
+// Work finished off the swarm event loop, handed back to it
+pub enum TransferEvent {
+    Served {
+        channel: ResponseChannel<FileResponse>,
+        response: Box<FileResponse>,
+        result: Option<Result<(), FileError>>,
+    },
+    Completed {
+        key: DownloadKey,
+        result: Result<(), FileError>,
+    },
+    // a throttled source of a download may request its next chunk
+    Pull {
+        key: DownloadKey,
+        segment: usize,
+    },
+}
+
+// A download by the peer it was asked of and the path there, so two peers may serve the same path at once
+pub type DownloadKey = (PeerId, String);
+
+// In-flight transfers owned by the swarm event loop, uploads keyed by the local source path
+pub struct Transfers {
+    pub downloads: HashMap<DownloadKey, Download>,
+    pub uploads: HashMap<String, Upload>,
+    pub receivers: HashMap<DownloadKey, oneshot::Sender<Result<(), FileError>>>,
+    // whoever waits on an upload, keyed like `uploads`
+    pub senders: HashMap<String, oneshot::Sender<Result<(), FileError>>>,
+    pub listings: HashMap<OutboundRequestId, oneshot::Sender<FolderListing>>,
+    pub probes: HashMap<OutboundRequestId, oneshot::Sender<FileProbe>>,
+    pub deltas: HashMap<OutboundRequestId, oneshot::Sender<Result<Option<Delta>, FileError>>>,
+    pub skips: HashMap<OutboundRequestId, oneshot::Sender<Result<(), FileError>>>,
+    // the requests behind the four above, until answered, so they can be sent again when the
+    // connection they went out on is closed for another one
+    pub sent: HashMap<OutboundRequestId, (PeerId, FileRequest)>,
+    // uploads cancelled here, until the receiver's next chunk request is refused or
+    // it could no longer be waiting on one
+    pub cancelled: HashMap<String, Instant>,
+    pub digests: Digests,
+    pub served_deltas: Deltas,
+    // names left out of folder listings, like the file map leaves them out
+    pub ignored: Arc<Vec<Regex>>,
+    events: Sender<TransferEvent>,
+}
+
+// `None` when the listed path turned out to be a regular file
+pub type FolderListing = Result<Option<Vec<FolderEntry>>, FileError>;
+// Size, metadata and, when asked for, the digest of a remote file
+pub type FileProbe = Result<(u64, Option<String>, FileMeta), FileError>;
+
+// Other peers holding a copy of a file with the same size and digest as the named peer's
+#[derive(Debug, Clone, Default)]
+pub struct Mirrors {
+    pub size: u64,
+    pub peers: Vec<PeerId>,
+}
+
+// A partial file already rebuilt from the receiver's old copy, missing only `ranges`
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub size: u64,
+    pub digest: String,
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl Transfers {
+    pub fn new() -> (Self, Receiver<TransferEvent>) {
+        let (events, rx) = mpsc::channel(TRANSFER_EVENT_BUFF_SIZE);
+        let transfers = Self {
+            downloads: HashMap::new(),
+            uploads: HashMap::new(),
+            receivers: HashMap::new(),
+            senders: HashMap::new(),
+            listings: HashMap::new(),
+            probes: HashMap::new(),
+            deltas: HashMap::new(),
+            skips: HashMap::new(),
+            sent: HashMap::new(),
+            cancelled: HashMap::new(),
+            digests: Digests::default(),
+            served_deltas: Deltas::default(),
+            ignored: Arc::default(),
+            events,
+        };
+        (transfers, rx)
+    }
+
+    pub fn events(&self) -> Sender<TransferEvent> {
+        self.events.clone()
+    }
+
+    // Files inside a shared folder belong to the folder's upload
+    pub fn upload_key(&self, path: &str) -> Option<String> {
+        find_ancestor(self.uploads.keys(), path)
+    }
+
+    // Hands whoever waits on `old` over to `new`, the same request sent again
+    pub fn resent(&mut self, old: OutboundRequestId, new: OutboundRequestId) {
+        if let Some(sender) = self.listings.remove(&old) {
+            self.listings.insert(new, sender);
+        }
+        if let Some(sender) = self.probes.remove(&old) {
+            self.probes.insert(new, sender);
+        }
+        if let Some(sender) = self.deltas.remove(&old) {
+            self.deltas.insert(new, sender);
+        }
+        if let Some(sender) = self.skips.remove(&old) {
+            self.skips.insert(new, sender);
+        }
+    }
+
+    pub fn set_ignored(&mut self, patterns: &[String]) {
+        let ignored = patterns
+            .iter()
+            .filter_map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    tracing::warn!("Invalid ignore pattern {:?}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        self.ignored = Arc::new(ignored);
+    }
+
+    // Whether `path` belongs to a cancelled upload, which is forgotten once refused
+    pub fn take_cancelled(&mut self, path: &str) -> bool {
+        let key = find_ancestor(self.cancelled.keys(), path);
+        key.is_some_and(|key| self.cancelled.remove(&key).is_some())
+    }
+
+    // Resolves whoever waits on the download under `key`
+    pub fn resolve(&mut self, key: &DownloadKey, result: Result<(), FileError>) {
+        if let Some(sender) = self.receivers.remove(key) {
+            let _ = sender.send(result);
+        }
+    }
+
+    pub fn find_download(&self, request_id: OutboundRequestId) -> Option<DownloadKey> {
+        self.downloads
+            .iter()
+            .find(|(_, download)| download.owns(request_id))
+            .map(|(key, _)| key.clone())
+    }
+}
+
+type PendingDigest = Shared<BoxFuture<'static, Result<String, String>>>;
+// Keyed by path, valid for the size and mtime it was started for
+type DigestCache = HashMap<PathBuf, (u64, SystemTime, PendingDigest)>;
+
+// Digests of served files, hashed once per size and mtime in the background from the first
+// chunk on, so the last chunk does not wait for a whole-file hash
+#[derive(Clone, Default)]
+pub struct Digests {
+    digests: Arc<Mutex<DigestCache>>,
+}
+
+impl Digests {
+    // Starts hashing `path` unless its current content is already hashed or being hashed
+    pub async fn start(&self, path: &Path) -> io::Result<PendingDigest> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        let mut digests = self.digests.lock().expect("Digest cache poisoned");
+        if let Some((_, _, digest)) = digests
+            .get(path)
+            .filter(|(len, mtime, _)| *len == size && *mtime == modified)
+        {
+            return Ok(digest.clone());
+        }
+        if digests.len() >= MAX_CACHED_DIGESTS {
+            digests.retain(|_, (_, _, digest)| digest.peek().is_none());
+        }
+        let source = path.to_path_buf();
+        let hashing = tokio::task::spawn_blocking(move || hash_file(&source));
+        let digest = async move {
+            match hashing.await {
+                Ok(digest) => digest.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        .boxed()
+        .shared();
+        digests.insert(path.to_path_buf(), (size, modified, digest.clone()));
+        Ok(digest)
+    }
+
+    pub async fn of(&self, path: &Path) -> io::Result<String> {
+        self.start(path).await?.await.map_err(io::Error::other)
+    }
+}
+
+type PendingDelta = Shared<BoxFuture<'static, Result<Delta, String>>>;
+
+// Deltas being computed for receivers, which ask again until theirs is done, so no single
+// request waits for a diff of the whole file
+#[derive(Clone, Default)]
+pub struct Deltas {
+    deltas: Arc<Mutex<HashMap<(PeerId, PathBuf), PendingDelta>>>,
+}
+
+impl Deltas {
+    // Starts diffing `path` against a receiver's signature, replacing its earlier one,
+    // unless the receiver already has `MAX_PEER_DELTAS` others running
+    pub fn start(&self, peer: PeerId, path: &Path, signature: Signature) -> Result<(), String> {
+        let key = (peer, path.to_path_buf());
+        let running = self
+            .deltas
+            .lock()
+            .expect("Delta cache poisoned")
+            .iter()
+            .filter(|((owner, _), delta)| *owner == peer && delta.peek().is_none())
+            .filter(|(other, _)| **other != key)
+            .count();
+        if running >= MAX_PEER_DELTAS {
+            return Err(format!("{} diffs already running", running));
+        }
+        let source = path.to_path_buf();
+        let diffing = tokio::task::spawn_blocking(move || delta::diff(&source, &signature));
+        let delta = async move {
+            match diffing.await {
+                Ok(delta) => delta.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        .boxed()
+        .shared();
+        let mut deltas = self.deltas.lock().expect("Delta cache poisoned");
+        // finished deltas nobody came back for
+        if deltas.len() >= MAX_CACHED_DELTAS {
+            deltas.retain(|_, delta| delta.peek().is_none());
+        }
+        deltas.insert(key, delta);
+        Ok(())
+    }
+
+    // The receiver's delta once it is done, handed out only once
+    pub async fn poll(&self, peer: PeerId, path: &Path) -> Result<Option<Delta>, String> {
+        let key = (peer, path.to_path_buf());
+        let pending = self
+            .deltas
+            .lock()
+            .expect("Delta cache poisoned")
+            .get(&key)
+            .cloned();
+        let Some(pending) = pending else {
+            return Err("No delta in progress".to_string());
+        };
+        let wait = Duration::from_millis(DELTA_WAIT_MS);
+        let Ok(delta) = tokio::time::timeout(wait, pending).await else {
+            return Ok(None);
+        };
+        self.deltas
+            .lock()
+            .expect("Delta cache poisoned")
+            .remove(&key);
+        delta.map(Some)
+    }
+}
+
+// The entry of `keys` that is `path` itself or one of its folders
+fn find_ancestor<'a>(keys: impl Iterator<Item = &'a String> + Clone, path: &str) -> Option<String> {
+    Path::new(path).ancestors().find_map(|ancestor| {
+        let ancestor = ancestor.to_string_lossy();
+        keys.clone()
+            .find(|key| key.trim_end_matches('/') == ancestor.trim_end_matches('/'))
+            .cloned()
+    })
+}
+
+// Partial data and its resume marker live next to the final path until completion
+const PARTIAL_SUFFIX: &str = ".kudrive-part";
+const MARKER_SUFFIX: &str = ".kudrive-resume";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ResumeMarker {
+    peer: String,
+    src_path: String,
+    size: u64,
+    offset: u64,
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn partial_path(path: &Path) -> PathBuf {
+    sibling(path, PARTIAL_SUFFIX)
+}
+
+// Leftovers of a download rather than content of the workspace
+pub fn is_partial(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(PARTIAL_SUFFIX) || name.ends_with(MARKER_SUFFIX)
+}
+
+// An earlier attempt left something to resume from
+pub async fn is_resumable(path: &Path) -> bool {
+    tokio::fs::try_exists(sibling(path, MARKER_SUFFIX))
+        .await
+        .unwrap_or(false)
+}
+
+// A zero-length request, asking what the path is and optionally for its digest
+pub fn probe(src_path: &str, digest: bool) -> FileRequest {
+    let file_name = Path::new(src_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    FileRequest {
+        file_name,
+        target_path: src_path.to_string(),
+        save_path: String::new(),
+        offset: 0,
+        length: 0,
+        cancel: false,
+        digest,
+        delta: false,
+        signature: None,
+        skip: None,
+        legacy: false,
+    }
+}
+
+// One source's share of a download, `next..end` is still to be fetched
+struct Segment {
+    peer: PeerId,
+    start: u64,
+    next: u64,
+    end: u64,
+    request_id: Option<OutboundRequestId>,
+    // held back by the throttle until a `Pull` event
+    waiting: bool,
+}
+
+impl Segment {
+    fn new(peer: PeerId, start: u64, end: u64) -> Self {
+        Self {
+            peer,
+            start,
+            next: start,
+            end,
+            request_id: None,
+            waiting: false,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.request_id.is_none() && !self.waiting && self.next < self.end
+    }
+}
+
+// Receiving side: pulls one chunk at a time per source and writes it straight to disk
+pub struct Download {
+    pub peer: PeerId,
+    pub file_name: String,
+    pub src_path: String,
+    pub tgt_path: String,
+    // the queued transfer it belongs to, which cancels it as a whole
+    pub transfer: Option<u64>,
+    // everything before it is on disk
+    pub offset: u64,
+    path: PathBuf,
+    size: Option<u64>,
+    file: Option<File>,
+    meter: Meter,
+    received: u64,
+    digest: Option<String>,
+    meta: Option<FileMeta>,
+    // the receiver's workspace, links are only restored inside it
+    root: PathBuf,
+    segments: Vec<Segment>,
+    dropped: HashSet<PeerId>,
+    // the partial was rebuilt from a delta and must not be truncated
+    patched: bool,
+}
+
+impl Download {
+    pub fn key(&self) -> DownloadKey {
+        (self.peer, self.src_path.clone())
+    }
+
+    // Picks up where an earlier attempt for the same source and peer stopped
+    pub async fn resume(
+        base_dir: &Path,
+        peer: PeerId,
+        file_name: String,
+        src_path: String,
+        tgt_path: String,
+    ) -> Result<Self, FileError> {
+        let path = sandbox::resolve(base_dir, &tgt_path)?;
+        let mut download = Self {
+            peer,
+            file_name,
+            src_path,
+            tgt_path,
+            transfer: None,
+            offset: 0,
+            path,
+            size: None,
+            file: None,
+            meter: Meter::new(0),
+            received: 0,
+            digest: None,
+            meta: None,
+            root: base_dir.to_path_buf(),
+            segments: Vec::new(),
+            dropped: HashSet::new(),
+            patched: false,
+        };
+
+        let marker = tokio::fs::read(download.marker_path())
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<ResumeMarker>(&bytes).ok());
+        if let Some(marker) = marker {
+            if marker.peer == peer.to_string() && marker.src_path == download.src_path {
+                // only trust bytes that made it to disk
+                let written = tokio::fs::metadata(download.partial_path())
+                    .await
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+                download.offset = marker.offset.min(written);
+                download.size = Some(marker.size);
+                download.meter = Meter::new(download.offset);
+                download.received = download.offset;
+                tracing::info!(
+                    "Resuming {:?} at {} of {} bytes",
+                    download.path,
+                    download.offset,
+                    marker.size
+                );
+            }
+        }
+        // the size is learned from the first chunk
+        download.segments = vec![Segment::new(peer, download.offset, u64::MAX)];
+        Ok(download)
+    }
+
+    // Splits what is left between the mirrors and the named peer, which keeps the
+    // final range so its upload still ends with the last chunk
+    pub fn spread(&mut self, mirrors: Mirrors) {
+        if mirrors.peers.is_empty() {
+            return;
+        }
+        if self.size.is_some_and(|size| size != mirrors.size) {
+            tracing::warn!("Source of {:?} changed, restarting download", self.path);
+            self.file = None;
+            self.offset = 0;
+            self.received = 0;
+            self.meter = Meter::new(0);
+            // the named peer serves it from the start unless it is split below
+            self.segments = vec![Segment::new(self.peer, 0, u64::MAX)];
+        }
+        self.size = Some(mirrors.size);
+
+        // not worth splitting, the named peer serves it alone
+        let chunks = mirrors
+            .size
+            .saturating_sub(self.offset)
+            .div_ceil(CHUNK_SIZE);
+        if chunks < 2 {
+            return;
+        }
+        let sources: Vec<PeerId> = mirrors.peers.into_iter().chain([self.peer]).collect();
+        let parts = (sources.len() as u64).min(chunks);
+        // whole chunks per source, the named peer takes the remainder
+        let share = chunks / parts * CHUNK_SIZE;
+        self.segments = sources[sources.len() - parts as usize..]
+            .iter()
+            .enumerate()
+            .map(|(index, peer)| {
+                let start = self.offset + index as u64 * share;
+                let end = match index as u64 + 1 == parts {
+                    true => mirrors.size,
+                    false => start + share,
+                };
+                Segment::new(*peer, start, end)
+            })
+            .collect();
+        tracing::info!(
+            "Pulling {:?} from {} sources",
+            self.src_path,
+            self.segments.len()
+        );
+    }
+
+    // Takes over a partial rebuilt by `delta::patch`, pulling only what it is missing
+    pub fn fill(&mut self, patch: Patch) {
+        let missing: u64 = patch.ranges.iter().map(|(start, end)| end - start).sum();
+        self.size = Some(patch.size);
+        self.digest = Some(patch.digest);
+        self.received = patch.size - missing;
+        self.meter = Meter::new(self.received);
+        self.segments = patch
+            .ranges
+            .into_iter()
+            .map(|(start, end)| Segment::new(self.peer, start, end))
+            .collect();
+        self.offset = self.contiguous();
+        self.patched = true;
+    }
+
+    // Tells the sender this transfer is dropped, so it can stop waiting for it
+    pub fn cancel_request(&self) -> FileRequest {
+        FileRequest {
+            cancel: true,
+            ..self.request(self.offset, 0)
+        }
+    }
+
+    fn request(&self, offset: u64, length: u64) -> FileRequest {
+        FileRequest {
+            file_name: self.file_name.clone(),
+            target_path: self.src_path.clone(),
+            save_path: self.tgt_path.clone(),
+            offset,
+            length,
+            cancel: false,
+            digest: false,
+            delta: false,
+            signature: None,
+            skip: None,
+            legacy: false,
+        }
+    }
+
+    // Segments ready to request their next chunk, at most one in flight per source
+    pub fn idle(&self) -> Vec<usize> {
+        let mut busy: HashSet<PeerId> = self
+            .segments
+            .iter()
+            .filter(|segment| segment.request_id.is_some() || segment.waiting)
+            .map(|segment| segment.peer)
+            .collect();
+        (0..self.segments.len())
+            .filter(|index| {
+                let segment = &self.segments[*index];
+                segment.is_idle() && busy.insert(segment.peer)
+            })
+            .collect()
+    }
+
+    pub fn next_request(&self, segment: usize, length: u64) -> (PeerId, FileRequest) {
+        let segment = &self.segments[segment];
+        let length = length.min(segment.end - segment.next);
+        (segment.peer, self.request(segment.next, length))
+    }
+
+    pub fn sent(&mut self, segment: usize, request_id: OutboundRequestId) {
+        let segment = &mut self.segments[segment];
+        segment.request_id = Some(request_id);
+        segment.waiting = false;
+    }
+
+    pub fn wait(&mut self, segment: usize) {
+        self.segments[segment].waiting = true;
+    }
+
+    pub fn is_waiting(&self, segment: usize) -> bool {
+        self.segments
+            .get(segment)
+            .is_some_and(|segment| segment.waiting)
+    }
+
+    pub fn owns(&self, request_id: OutboundRequestId) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| segment.request_id == Some(request_id))
+    }
+
+    // Frees the segment `request_id` was for, so its chunk is asked for again
+    pub fn resend(&mut self, request_id: OutboundRequestId) {
+        for segment in self
+            .segments
+            .iter_mut()
+            .filter(|segment| segment.request_id == Some(request_id))
+        {
+            segment.request_id = None;
+        }
+    }
+
+    // Hands what `peer` had left to a remaining source, false if there is none
+    pub fn drop_source(&mut self, peer: PeerId) -> bool {
+        self.dropped.insert(peer);
+        // the named peer is preferred, its upload only ends with the final range
+        let heir = std::iter::once(self.peer)
+            .chain(self.segments.iter().map(|segment| segment.peer))
+            .find(|source| !self.dropped.contains(source));
+        let Some(heir) = heir else {
+            return false;
+        };
+
+        let mut orphaned = Vec::new();
+        for segment in self
+            .segments
+            .iter_mut()
+            .filter(|segment| segment.peer == peer)
+        {
+            segment.request_id = None;
+            segment.waiting = false;
+            if segment.next < segment.end {
+                orphaned.push((segment.next, segment.end));
+                segment.end = segment.next;
+            }
+        }
+        for (start, end) in orphaned {
+            self.segments.push(Segment::new(heir, start, end));
+        }
+        true
+    }
+
+    // The end of the fetched bytes that have no gap before them
+    fn contiguous(&self) -> u64 {
+        let mut ranges: Vec<(u64, u64)> = self
+            .segments
+            .iter()
+            .map(|segment| (segment.start, segment.next))
+            .collect();
+        ranges.sort_unstable();
+        let mut offset = ranges.first().map_or(0, |(start, _)| *start);
+        for (start, next) in ranges {
+            if start > offset {
+                break;
+            }
+            offset = offset.max(next);
+        }
+        offset
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        let size = self.size?;
+        let progress = self
+            .meter
+            .progress(&self.src_path, Direction::Receive, self.received, size);
+        Some(progress)
+    }
+
+    fn partial_path(&self) -> PathBuf {
+        partial_path(&self.path)
+    }
+
+    fn marker_path(&self) -> PathBuf {
+        sibling(&self.path, MARKER_SUFFIX)
+    }
+
+    async fn open_partial(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = if self.patched {
+                OpenOptions::new()
+                    .write(true)
+                    .open(self.partial_path())
+                    .await?
+            } else if self.offset > 0 {
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .open(self.partial_path())
+                    .await?;
+                file.set_len(self.offset).await?;
+                file.seek(SeekFrom::Start(self.offset)).await?;
+                file
+            } else {
+                File::create(self.partial_path()).await?
+            };
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("partial file is open"))
+    }
+
+    async fn save_marker(&self, size: u64) -> io::Result<()> {
+        let marker = ResumeMarker {
+            peer: self.peer.to_string(),
+            src_path: self.src_path.clone(),
+            size,
+            offset: self.offset,
+        };
+        let bytes = serde_json::to_vec(&marker)?;
+        tokio::fs::write(self.marker_path(), bytes).await
+    }
+
+    // Returns true once the whole file has been written, see `commit`
+    pub async fn write_chunk(
+        &mut self,
+        request_id: OutboundRequestId,
+        response: &FileResponse,
+    ) -> io::Result<bool> {
+        let Some(index) = self
+            .segments
+            .iter()
+            .position(|segment| segment.request_id == Some(request_id))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected chunk at {}", response.offset),
+            ));
+        };
+        self.segments[index].request_id = None;
+        let (peer, next) = (self.segments[index].peer, self.segments[index].next);
+        if response.offset != next {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected chunk at {}, got {}", next, response.offset),
+            ));
+        }
+
+        if self.size.is_some_and(|size| size != response.size) {
+            // a mirror is no longer identical, the others carry on without it
+            if self.segments.len() > 1 {
+                tracing::warn!("Copy of {:?} on {} changed, dropping it", self.path, peer);
+                return match self.drop_source(peer) {
+                    true => Ok(false),
+                    false => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Every copy of {} changed", self.file_name),
+                    )),
+                };
+            }
+            // the source changed since the partial was written, start over
+            tracing::warn!("Source of {:?} changed, restarting download", self.path);
+            self.file = None;
+            self.offset = 0;
+            self.size = None;
+            self.meter = Meter::new(0);
+            self.received = 0;
+            self.patched = false;
+            self.segments = vec![Segment::new(self.peer, 0, u64::MAX)];
+            return Ok(false);
+        }
+
+        let file = self.open_partial().await?;
+        file.seek(SeekFrom::Start(response.offset)).await?;
+        file.write_all(&response.content).await?;
+        file.flush().await?;
+        let length = response.content.len() as u64;
+        let segment = &mut self.segments[index];
+        segment.next += length;
+        segment.end = segment.end.min(response.size);
+        let ended = segment.next < segment.end && response.content.is_empty();
+        self.received += length;
+        self.size = Some(response.size);
+        if response.digest.is_some() {
+            self.digest = response.digest.clone();
+            self.meta = response.meta.clone();
+        }
+        self.offset = self.contiguous();
+
+        if self
+            .segments
+            .iter()
+            .all(|segment| segment.next >= segment.end)
+        {
+            if let Some(file) = self.file.take() {
+                file.sync_all().await?;
+            }
+            return Ok(true);
+        }
+        if ended {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("File ended at {} of {} bytes", next, response.size),
+            ));
+        }
+        self.save_marker(response.size).await?;
+        Ok(false)
+    }
+
+    // Moves the partial file into place if it matches the sender's digest
+    pub async fn commit(mut self) -> Result<(), FileError> {
+        let Some(expected) = self.digest.take() else {
+            let message = format!("No digest received for {}", self.file_name);
+            return Err(FileError::new(ErrorCode::Corrupted, message));
+        };
+        let actual = digest_file(&self.partial_path()).await.map_err(|e| {
+            let message = format!("Failed to hash {}: {}", self.file_name, e);
+            FileError::new(ErrorCode::WriteError, message)
+        })?;
+        if actual != expected {
+            tracing::error!(
+                "Digest mismatch for {:?}: expected {}, got {}",
+                self.path,
+                expected,
+                actual
+            );
+            self.discard().await;
+            let message = format!("Digest mismatch for {}", self.file_name);
+            return Err(FileError::new(ErrorCode::Corrupted, message));
+        }
+
+        tokio::fs::rename(self.partial_path(), &self.path)
+            .await
+            .map_err(|e| {
+                let message = format!("Failed to save file {}: {}", self.file_name, e);
+                FileError::new(ErrorCode::WriteError, message)
+            })?;
+        let _ = tokio::fs::remove_file(self.marker_path()).await;
+        // the content is saved either way, so a failure here only loses metadata
+        if let Some(ref file_meta) = self.meta {
+            if let Err(e) = meta::restore(&self.root, &self.path, file_meta) {
+                tracing::warn!("Failed to restore metadata of {:?}: {}", self.path, e);
+            }
+        }
+        Ok(())
+    }
+
+    // Drops the partial file when the source can no longer be resumed from
+    pub async fn discard(&mut self) {
+        self.file.take();
+        let _ = tokio::fs::remove_file(self.partial_path()).await;
+        let _ = tokio::fs::remove_file(self.marker_path()).await;
+    }
+}
+
+// Sending side: only tracks activity, chunks are read on demand
+pub struct Upload {
+    pub last_activity: Instant,
+    files: Option<HashSet<String>>,
+    failed: Vec<FileError>,
+    meters: HashMap<String, Meter>,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Upload {
+    pub fn new() -> Self {
+        Self {
+            last_activity: Instant::now(),
+            files: None,
+            failed: Vec::new(),
+            meters: HashMap::new(),
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    // Files of a folder upload are metered separately, from their first served chunk
+    pub fn progress(&mut self, response: &FileResponse) -> Progress {
+        let bytes = response.offset + response.content.len() as u64;
+        self.meters
+            .entry(response.src_path.clone())
+            .or_insert_with(|| Meter::new(response.offset))
+            .progress(&response.src_path, Direction::Send, bytes, response.size)
+    }
+
+    // A folder upload is done once every listed file has been served
+    pub fn expect_files(&mut self, folder: &str, entries: &[FolderEntry]) {
+        let files = entries
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| join_remote(folder, &entry.path))
+            .collect();
+        self.files = Some(files);
+    }
+
+    // Records a served file, returning the upload's result once nothing is left
+    pub fn finish(
+        &mut self,
+        path: &str,
+        result: Result<(), FileError>,
+    ) -> Option<Result<(), FileError>> {
+        self.meters.remove(path);
+        let Some(ref mut files) = self.files else {
+            return Some(result);
+        };
+        files.remove(path);
+        if let Err(e) = result {
+            let message = format!("{}: {}", path, e.message);
+            self.failed.push(FileError::new(e.code, message));
+        }
+        self.outcome()
+    }
+
+    // A failed folder upload is reported with the code of its first failed file
+    pub fn outcome(&self) -> Option<Result<(), FileError>> {
+        match self.files {
+            Some(ref files) if files.is_empty() => match self.failed.first() {
+                None => Some(Ok(())),
+                Some(first) => {
+                    let messages: Vec<&str> =
+                        self.failed.iter().map(|e| e.message.as_str()).collect();
+                    let message = format!(
+                        "{} files failed: {}",
+                        self.failed.len(),
+                        messages.join("; ")
+                    );
+                    Some(Err(FileError::new(first.code, message)))
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+// Answers a single chunk request, with the result for the sender once it is the last one
+pub async fn serve_chunk(
+    base_dir: &Path,
+    digests: &Digests,
+    deltas: &Deltas,
+    peer: PeerId,
+    ignored: Arc<Vec<Regex>>,
+    mut request: FileRequest,
+) -> (FileResponse, Option<Result<(), FileError>>) {
+    let mut response = respond_to(&request);
+
+    let path = match sandbox::resolve(base_dir, &request.target_path) {
+        Ok(path) => path,
+        Err(error) => return fail(response, error),
+    };
+
+    let is_dir = tokio::fs::metadata(&path)
+        .await
+        .is_ok_and(|meta| meta.is_dir());
+    if is_dir {
+        let root = path.clone();
+        let listing = tokio::task::spawn_blocking(move || list_folder(&root, &ignored)).await;
+        return match listing {
+            Ok(Ok(entries)) => {
+                response.entries = Some(entries);
+                // the listing has to fit in a single response
+                if !fits_response(&response) {
+                    response.entries = None;
+                    let message = format!("Folder too large: {}", request.target_path);
+                    return fail(response, FileError::new(ErrorCode::TooLarge, message));
+                }
+                (response, None)
+            }
+            Ok(Err(e)) => {
+                let message = format!("Failed to list folder: {}", request.target_path);
+                fail(response, FileError::read(&e, message))
+            }
+            Err(_) => {
+                let message = format!("Failed to list folder: {}", request.target_path);
+                fail(response, FileError::new(ErrorCode::ReadError, message))
+            }
+        };
+    }
+
+    // the receiver found the content locally or declined it, which ends the file like its
+    // last chunk
+    if let Some(outcome) = request.skip.take() {
+        tracing::info!("Receiver skipped {}: {:?}", request.target_path, outcome);
+        return (response, Some(outcome));
+    }
+
+    // the receiver falls back to a whole transfer if this fails, so it ends nothing
+    if request.delta {
+        let started = match request.signature.take() {
+            Some(signature) => deltas.start(peer, &path, signature),
+            None => Ok(()),
+        };
+        let delta = match started {
+            Ok(()) => deltas.poll(peer, &path).await,
+            Err(e) => Err(e),
+        };
+        match delta {
+            Ok(Some(delta)) => {
+                response.size = delta.size;
+                response.delta = Some(delta);
+                if !fits_response(&response) {
+                    response.delta = None;
+                    let message = format!("Delta too large: {}", request.target_path);
+                    response.status = Err(FileError::new(ErrorCode::TooLarge, message));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                let message = format!("Failed to diff file: {}", request.target_path);
+                tracing::error!("{}: {}", message, e);
+                response.status = Err(FileError::new(ErrorCode::ReadError, message));
+            }
+        }
+        return (response, None);
+    }
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            let message = format!("Failed to open file: {}", request.target_path);
+            return fail(response, FileError::read(&e, message));
+        }
+    };
+
+    // a zero-length request only probes the target
+    let probe = request.length == 0;
+    if !probe {
+        if let Err(e) = digests.start(&path).await {
+            tracing::warn!("Failed to start hashing {:?}: {}", path, e);
+        }
+    }
+    let chunk = match read_chunk(file, request.offset, request.length).await {
+        Ok((size, content)) if probe && request.digest => digests
+            .of(&path)
+            .await
+            .map(|digest| (size, content, Some(digest))),
+        Ok((size, content)) if probe => Ok((size, content, None)),
+        Ok((size, content)) if request.offset + content.len() as u64 >= size => digests
+            .of(&path)
+            .await
+            .map(|digest| (size, content, Some(digest))),
+        Ok((size, content)) => Ok((size, content, None)),
+        Err(e) => Err(e),
+    };
+
+    match chunk {
+        Ok((size, content, digest)) => {
+            let done = digest.is_some() && !probe;
+            response.size = size;
+            response.content = content;
+            if probe || digest.is_some() {
+                let literal = base_dir.join(&request.target_path);
+                response.meta = meta::read(&literal, &path).ok();
+            }
+            response.digest = digest;
+            (response, done.then_some(Ok(())))
+        }
+        Err(e) => {
+            let message = format!("Failed to read file after open: {}", request.target_path);
+            fail(response, FileError::read(&e, message))
+        }
+    }
+}
+
+// Refuses a request outright, before anything is read
+pub fn deny(request: &FileRequest, error: FileError) -> FileResponse {
+    let (response, _) = fail(respond_to(request), error);
+    response
+}
+
+fn respond_to(request: &FileRequest) -> FileResponse {
+    FileResponse {
+        status: Ok(()),
+        file_name: request.file_name.clone(),
+        src_path: request.target_path.clone(),
+        tgt_path: request.save_path.clone(),
+        offset: request.offset,
+        size: 0,
+        content: Vec::new(),
+        digest: None,
+        entries: None,
+        delta: None,
+        meta: None,
+    }
+}
+
+// Turns the response into an error reply, which also ends the sender's transfer
+fn fail(
+    mut response: FileResponse,
+    error: FileError,
+) -> (FileResponse, Option<Result<(), FileError>>) {
+    tracing::error!("Failed to serve {}: {}", response.file_name, error);
+    let result = Err(error.clone());
+    response.status = Err(error);
+    (response, Some(result))
+}
+
+// Walks a folder depth first, so every folder is listed before its contents; partial
+// downloads and ignored names are not part of it
+fn list_folder(root: &Path, ignored: &[Regex]) -> io::Result<Vec<FolderEntry>> {
+    let mut entries = Vec::new();
+    let mut folders = vec![PathBuf::new()];
+
+    while let Some(folder) = folders.pop() {
+        for entry in std::fs::read_dir(root.join(&folder))? {
+            let entry = entry?;
+            let relative = folder.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            // linked folders are skipped so a link cannot loop the walk
+            if file_type.is_symlink() && entry.path().is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_partial(&entry.path()) || ignored.iter().any(|regex| regex.is_match(&name)) {
+                continue;
+            }
+
+            let path = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            entries.push(FolderEntry {
+                path,
+                is_dir: file_type.is_dir(),
+            });
+            if file_type.is_dir() {
+                folders.push(relative);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+pub async fn digest_file(path: &Path) -> io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path)).await?
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// Reads at most one chunk starting at `offset`, returning the file size with it
+pub async fn read_chunk(mut file: File, offset: u64, length: u64) -> io::Result<(u64, Vec<u8>)> {
+    let size = file.metadata().await?.len();
+    let length = length.min(CHUNK_SIZE).min(size.saturating_sub(offset));
+
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut content = Vec::with_capacity(length as usize);
+    file.take(length).read_to_end(&mut content).await?;
+
+    Ok((size, content))
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use libp2p::{PeerId, StreamProtocol};
+
+use super::ku_protocol::{ErrorCode, FileError};
+
+const PROTOCOL_PREFIX: &str = "/ku-file-transfer/";
+
+// File transfer wire versions. The dialing side proposes them newest first, so a connection
+// settles on the newest one both peers speak. 1.0.0 sends a whole file in one response, so
+// with its peers only files of up to a chunk are transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProtocolVersion {
+    // Newest first
+    pub const SUPPORTED: [Self; 2] = [Self::V2, Self::V1];
+
+    pub fn protocol(self) -> StreamProtocol {
+        match self {
+            Self::V1 => StreamProtocol::new("/ku-file-transfer/1.0.0"),
+            Self::V2 => StreamProtocol::new("/ku-file-transfer/2.0.0"),
+        }
+    }
+
+    pub fn of(protocol: &StreamProtocol) -> Option<Self> {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|version| version.protocol() == *protocol)
+    }
+
+    // The newest version among what a peer offers
+    pub fn negotiate(offered: &[StreamProtocol]) -> Option<Self> {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|version| offered.contains(&version.protocol()))
+    }
+}
+
+// File transfer versions peers announced through identify
+#[derive(Default)]
+pub struct PeerVersions {
+    offered: HashMap<PeerId, (String, Vec<String>)>,
+}
+
+impl PeerVersions {
+    // Records what a peer speaks, `None` when it does not transfer files at all, like a relay
+    pub fn identify(
+        &mut self,
+        peer: PeerId,
+        agent: &str,
+        protocols: &[StreamProtocol],
+    ) -> Option<Result<ProtocolVersion, String>> {
+        let offered: Vec<String> = protocols
+            .iter()
+            .map(|protocol| protocol.to_string())
+            .filter(|protocol| protocol.starts_with(PROTOCOL_PREFIX))
+            .collect();
+        if offered.is_empty() {
+            return None;
+        }
+        self.offered.insert(peer, (agent.to_string(), offered));
+        Some(ProtocolVersion::negotiate(protocols).ok_or_else(|| self.incompatible(&peer)))
+    }
+
+    // Refuses requests to a peer known to speak none of our versions, before they are sent
+    pub fn check(&self, peer: &PeerId) -> Result<(), FileError> {
+        let Some((_, offered)) = self.offered.get(peer) else {
+            return Ok(());
+        };
+        let common = ProtocolVersion::SUPPORTED
+            .iter()
+            .any(|version| offered.contains(&version.protocol().to_string()));
+        if common {
+            return Ok(());
+        }
+        Err(FileError::new(
+            ErrorCode::Incompatible,
+            self.incompatible(peer),
+        ))
+    }
+
+    // Why no request to `peer` can be understood, with what it offered if it said
+    pub fn incompatible(&self, peer: &PeerId) -> String {
+        let ours: Vec<String> = ProtocolVersion::SUPPORTED
+            .iter()
+            .map(|version| version.protocol().to_string())
+            .collect();
+        match self.offered.get(peer) {
+            Some((agent, offered)) => format!(
+                "Peer {} ({}) speaks {}, this client speaks {}",
+                peer,
+                agent,
+                offered.join(", "),
+                ours.join(", ")
+            ),
+            None => format!("Peer {} speaks none of {}", peer, ours.join(", ")),
+        }
+    }
+}
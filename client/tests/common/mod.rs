@@ -0,0 +1,50 @@
+#![allow(dead_code)]
+
+use rand::{distributions::Alphanumeric, Rng};
+use std::path::{Path, PathBuf};
+
+// A scratch directory of its own per test, removed once the test is done
+pub struct Workspace {
+    pub root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(prefix: &str) -> Self {
+        let name: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let root = std::env::temp_dir().join(format!("kudrive_{}_{}", prefix, name));
+        std::fs::create_dir_all(&root).expect("Failed to create workspace");
+        Workspace { root }
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn base_dir(&self) -> &str {
+        self.root.to_str().expect("Workspace path should be UTF-8")
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+// A workspace for the test `name` with `dirs` created in it, and its canonical root to
+// compare resolved paths against
+pub fn workspace(name: &str, dirs: &[&str]) -> (Workspace, PathBuf) {
+    let workspace = Workspace::new(name);
+    for dir in dirs {
+        std::fs::create_dir_all(workspace.join(dir)).expect("Failed to create test directory");
+    }
+    let root = workspace
+        .root
+        .canonicalize()
+        .expect("Failed to resolve workspace");
+    (workspace, root)
+}
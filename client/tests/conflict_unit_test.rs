@@ -0,0 +1,62 @@
+mod common;
+
+use common::workspace;
+use kudrive_client::net::conflict;
+use kudrive_client::net::ku_protocol::ErrorCode;
+use kudrive_common::ConflictPolicy;
+
+#[test]
+fn test_numbered() {
+    assert_eq!(
+        conflict::numbered("./docs/report.pdf", 1),
+        "./docs/report (1).pdf"
+    );
+    assert_eq!(
+        conflict::numbered("archive.tar.gz", 2),
+        "archive.tar (2).gz"
+    );
+    assert_eq!(conflict::numbered("docs/Makefile", 3), "docs/Makefile (3)");
+    // a leading dot names the file rather than starting an extension
+    assert_eq!(conflict::numbered("./.env", 1), "./.env (1)");
+}
+
+#[test]
+fn test_policies() {
+    let (_workspace, dir) = workspace("conflict_policies", &["docs"]);
+    std::fs::write(dir.join("docs/report.pdf"), b"old").unwrap();
+    std::fs::write(dir.join("docs/report (1).pdf"), b"older").unwrap();
+    let resolve = |policy| conflict::resolve(&dir, "./docs/report.pdf", policy);
+
+    assert_eq!(
+        resolve(ConflictPolicy::Overwrite),
+        Ok(Some("./docs/report.pdf".to_string()))
+    );
+    assert_eq!(
+        resolve(ConflictPolicy::KeepBoth),
+        Ok(Some("./docs/report (2).pdf".to_string()))
+    );
+    assert_eq!(resolve(ConflictPolicy::Skip), Ok(None));
+    let asked = resolve(ConflictPolicy::Ask).expect_err("Asking should report the conflict");
+    assert_eq!(asked.code, ErrorCode::Conflict);
+
+    // nothing in the way, so every policy saves as requested
+    for policy in [ConflictPolicy::Skip, ConflictPolicy::Ask] {
+        assert_eq!(
+            conflict::resolve(&dir, "./docs/new.pdf", policy),
+            Ok(Some("./docs/new.pdf".to_string()))
+        );
+    }
+}
+
+#[test]
+fn test_case_collision() {
+    let (_workspace, dir) = workspace("conflict_case", &["docs"]);
+    std::fs::write(dir.join("docs/README.md"), b"upper").unwrap();
+
+    assert!(conflict::is_taken(&dir.join("docs/readme.md")));
+    assert!(!conflict::is_taken(&dir.join("docs/readme.txt")));
+    assert_eq!(
+        conflict::resolve(&dir, "docs/readme.md", ConflictPolicy::KeepBoth),
+        Ok(Some("docs/readme (1).md".to_string()))
+    );
+}
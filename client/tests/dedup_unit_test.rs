@@ -0,0 +1,71 @@
+mod common;
+
+use common::workspace;
+use kudrive_client::net::dedup::{self, ContentIndex};
+use kudrive_client::net::ku_protocol::FileMeta;
+use kudrive_client::net::transfer;
+
+#[test]
+fn test_find_by_content() {
+    let (_workspace, dir) = workspace("dedup_find", &["nested"]);
+    std::fs::write(dir.join("other.txt"), b"same size, other data").unwrap();
+    std::fs::write(dir.join("nested/copy.txt"), b"identical file content").unwrap();
+    let digest = blake3::hash(b"identical file content").to_hex().to_string();
+
+    let index = ContentIndex::default();
+    let found = index.find(&dir, 22, &digest);
+    assert_eq!(found, Some(dir.join("nested/copy.txt")));
+    // another size is never hashed, let alone matched
+    assert_eq!(index.find(&dir, 23, &digest), None);
+    assert!(index.has_size(&dir, 22));
+    assert!(!index.has_size(&dir, 23));
+}
+
+#[test]
+fn test_partials_ignored() {
+    let (_workspace, dir) = workspace("dedup_partials", &["nested"]);
+    let content = b"half-received download";
+    let partial = transfer::partial_path(&dir.join("file.bin"));
+    std::fs::write(partial, content).unwrap();
+    let digest = blake3::hash(content).to_hex().to_string();
+
+    let index = ContentIndex::default();
+    assert_eq!(index.find(&dir, content.len() as u64, &digest), None);
+}
+
+#[test]
+fn test_changed_file_rehashed() {
+    let (_workspace, dir) = workspace("dedup_changed", &["nested"]);
+    let path = dir.join("file.txt");
+    std::fs::write(&path, b"first").unwrap();
+    let first = blake3::hash(b"first").to_hex().to_string();
+    let index = ContentIndex::default();
+    assert_eq!(index.find(&dir, 5, &first), Some(path.clone()));
+
+    // same size, new mtime: the cached digest no longer applies
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(&path, b"other").unwrap();
+    let other = blake3::hash(b"other").to_hex().to_string();
+    assert_eq!(index.find(&dir, 5, &first), None);
+    assert_eq!(index.find(&dir, 5, &other), Some(path));
+}
+
+#[test]
+fn test_place() {
+    let (_workspace, dir) = workspace("dedup_place", &["nested"]);
+    let source = dir.join("source.txt");
+    let target = dir.join("nested/deeper/target.txt");
+    std::fs::write(&source, b"content").unwrap();
+
+    dedup::place(&dir, &source, &target, &FileMeta::default()).expect("Failed to place copy");
+    assert_eq!(std::fs::read(&target).unwrap(), b"content");
+    assert!(!transfer::partial_path(&target).exists());
+
+    // already in place is left alone
+    dedup::place(&dir, &target, &target, &FileMeta::default()).expect("Failed to place copy");
+    assert_eq!(std::fs::read(&target).unwrap(), b"content");
+
+    // a copy, so editing one file leaves the other alone
+    std::fs::write(&target, b"edited").unwrap();
+    assert_eq!(std::fs::read(&source).unwrap(), b"content");
+}
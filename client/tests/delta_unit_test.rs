@@ -0,0 +1,112 @@
+mod common;
+
+use common::Workspace;
+use kudrive_client::net::delta::{self, DeltaOp, Signature};
+use std::path::Path;
+
+const KB: usize = 1024;
+
+fn content(length: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+// Runs both sides of a delta transfer, returning the rebuilt file and the bytes pulled
+fn transfer(dir: &Path, old: &[u8], new: &[u8]) -> (Vec<u8>, u64) {
+    let (old_path, new_path, partial) = (dir.join("old"), dir.join("new"), dir.join("partial"));
+    std::fs::write(&old_path, old).unwrap();
+    std::fs::write(&new_path, new).unwrap();
+
+    let signature = delta::signature(&old_path).expect("Failed to sign");
+    let delta = delta::diff(&new_path, &signature).expect("Failed to diff");
+    assert_eq!(delta.size, new.len() as u64);
+    assert_eq!(delta.digest, blake3::hash(new).to_hex().to_string());
+
+    let ranges =
+        delta::patch(&old_path, &partial, signature.block_size, &delta).expect("Failed to patch");
+    let mut rebuilt = std::fs::read(&partial).unwrap();
+    let mut pulled = 0;
+    for (start, end) in ranges {
+        let (start, end) = (start as usize, end as usize);
+        rebuilt[start..end].copy_from_slice(&new[start..end]);
+        pulled += (end - start) as u64;
+    }
+    (rebuilt, pulled)
+}
+
+#[test]
+fn test_identical() {
+    let dir = Workspace::new("delta_identical");
+    let old = content(256 * KB + 100, 1);
+    let block_size = delta::block_size(old.len() as u64);
+
+    let (rebuilt, pulled) = transfer(&dir.root, &old, &old);
+    assert!(rebuilt == old);
+    // only the tail is pulled, which ends the sender's upload
+    assert!(pulled <= block_size + 100, "Pulled {} bytes", pulled);
+}
+
+#[test]
+fn test_shifted_edit() {
+    let dir = Workspace::new("delta_shifted");
+    let old = content(512 * KB, 2);
+    let mut new = old.clone();
+    // an insert shifts everything after it off block boundaries
+    new.splice(100 * KB..100 * KB, b"inserted".iter().copied());
+    new[300 * KB] ^= 0xff;
+
+    let (rebuilt, pulled) = transfer(&dir.root, &old, &new);
+    assert!(rebuilt == new);
+    assert!(pulled < 64 * KB as u64, "Pulled {} bytes", pulled);
+}
+
+#[test]
+fn test_unrelated() {
+    let dir = Workspace::new("delta_unrelated");
+    let old = content(64 * KB, 3);
+    let new = content(80 * KB, 4);
+
+    let (rebuilt, pulled) = transfer(&dir.root, &old, &new);
+    assert!(rebuilt == new);
+    assert_eq!(pulled, new.len() as u64);
+}
+
+#[test]
+fn test_copies_merge() {
+    let dir = Workspace::new("delta_merge");
+    let old = content(64 * KB, 5);
+    std::fs::write(dir.join("old"), &old).unwrap();
+    std::fs::write(dir.join("new"), &old).unwrap();
+
+    let signature = delta::signature(&dir.join("old")).unwrap();
+    let delta = delta::diff(&dir.join("new"), &signature).unwrap();
+    // consecutive blocks come out as a single copy
+    assert_eq!(
+        delta.ops,
+        vec![DeltaOp::Copy {
+            block: 0,
+            count: signature.blocks.len() as u64
+        }]
+    );
+}
+
+#[test]
+fn test_block_size_out_of_range() {
+    let dir = Workspace::new("delta_block_size");
+    std::fs::write(dir.join("new"), content(64 * KB, 6)).unwrap();
+
+    // a block as large as the peer likes would be held in memory whole
+    for block_size in [0, 1, 1 << 40] {
+        let signature = Signature {
+            block_size,
+            blocks: Vec::new(),
+        };
+        let error = delta::diff(&dir.join("new"), &signature).expect_err("Block size refused");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
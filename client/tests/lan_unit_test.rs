@@ -0,0 +1,38 @@
+use kudrive_client::net::lan::LocalPeers;
+use libp2p::{Multiaddr, PeerId};
+
+fn address(port: u16) -> Multiaddr {
+    format!("/ip4/192.168.0.10/tcp/{}", port).parse().unwrap()
+}
+
+#[test]
+fn test_discovery() {
+    let mut lan = LocalPeers::default();
+    let peer = PeerId::random();
+    assert!(lan.discovered(peer, address(4001)));
+    // announced again on every query
+    assert!(!lan.discovered(peer, address(4001)));
+    assert!(lan.discovered(peer, address(4002)));
+    assert_eq!(lan.addresses(&peer), vec![address(4001), address(4002)]);
+
+    lan.expired(&peer, &address(4001));
+    assert_eq!(lan.addresses(&peer), vec![address(4002)]);
+    lan.expired(&peer, &address(4002));
+    assert!(lan.addresses(&peer).is_empty());
+}
+
+#[test]
+fn test_relay_fallback() {
+    let mut lan = LocalPeers::default();
+    let (direct, relayed) = (PeerId::random(), PeerId::random());
+    lan.dialing(direct);
+
+    // only a failed direct dial is retried through the relay, and only once
+    assert!(!lan.dial_failed(&relayed));
+    assert!(lan.dial_failed(&direct));
+    assert!(!lan.dial_failed(&direct));
+
+    lan.dialing(direct);
+    lan.connected(&direct);
+    assert!(!lan.dial_failed(&direct));
+}
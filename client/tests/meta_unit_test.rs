@@ -0,0 +1,76 @@
+mod common;
+
+use common::workspace;
+use kudrive_client::net::meta;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_restore_times_and_permissions() {
+    let (_workspace, dir) = workspace("meta_restore", &[]);
+    let (source, saved) = (dir.join("source.sh"), dir.join("saved.sh"));
+    std::fs::write(&source, b"#!/bin/sh").unwrap();
+    std::fs::write(&saved, b"#!/bin/sh").unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&source)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o751)).unwrap();
+    }
+
+    let file_meta = meta::read(&source, &source).expect("Failed to read metadata");
+    assert_eq!(file_meta.modified, Some(modified));
+    assert_eq!(file_meta.link, None);
+    assert!(!meta::matches(&saved, &file_meta));
+
+    meta::restore(&dir, &saved, &file_meta).expect("Failed to restore metadata");
+    assert!(meta::matches(&saved, &file_meta));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&saved).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_restore_links() {
+    let (_sent, sent) = workspace("meta_sent", &[]);
+    let (_received, received) = workspace("meta_received", &[]);
+    std::fs::write(sent.join("real.txt"), b"content").unwrap();
+    std::os::unix::fs::symlink("real.txt", sent.join("link.txt")).unwrap();
+    let file_meta = meta::read(&sent.join("link.txt"), &sent.join("real.txt")).unwrap();
+    assert_eq!(file_meta.link.as_deref(), Some("real.txt"));
+
+    // the target is missing on this side, so the content stays a regular file
+    let saved = received.join("link.txt");
+    std::fs::write(&saved, b"content").unwrap();
+    meta::restore(&received, &saved, &file_meta).unwrap();
+    assert!(!saved.symlink_metadata().unwrap().file_type().is_symlink());
+
+    std::fs::write(received.join("real.txt"), b"content").unwrap();
+    meta::restore(&received, &saved, &file_meta).unwrap();
+    assert_eq!(
+        std::fs::read_link(&saved).unwrap(),
+        PathBuf::from("real.txt")
+    );
+    assert_eq!(std::fs::read(&saved).unwrap(), b"content");
+
+    // nor is a link followed out of the workspace
+    let outside = received.join("outside.txt");
+    std::fs::write(&outside, b"content").unwrap();
+    let mut escaping = file_meta.clone();
+    escaping.link = Some(format!(
+        "../{}/real.txt",
+        sent.file_name().unwrap().to_string_lossy()
+    ));
+    meta::restore(&received, &outside, &escaping).unwrap();
+    assert!(!outside.symlink_metadata().unwrap().file_type().is_symlink());
+}
@@ -1,6 +1,14 @@
+mod common;
+
+use common::Workspace;
 use core::panic;
-use kudrive_client::event::ClientEvent;
+use kudrive_client::event::{ClientEvent, Direction};
+use kudrive_client::net::ku_protocol::{ErrorCode, FileError, CHUNK_SIZE};
+use kudrive_client::net::transfer::Mirrors;
 use kudrive_client::p2p::{P2PTransport, P2pCommand, P2pStatus};
+use kudrive_common::fs::{File, FileMap, Folder, OS};
+use kudrive_common::p2p::{generate_ed25519, Transports};
+use kudrive_common::ConflictPolicy;
 use libp2p::PeerId;
 use rand::{distributions::Alphanumeric, Rng};
 use std::path::PathBuf;
@@ -15,6 +23,7 @@ const MAX_DIAL_RETRY: u32 = 5;
 const LOCAL_RELAY_ADDR: &str =
     "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWA768LzHMatxkjD1f9DrYW375GZJr6MHPCNEdDtHeTNRt";
 
+const TEST_RELAY_KEY_PATH: &str = "./test_relay.key";
 const SERVER_WARMUP_TIME: u64 = 5;
 const WARMUP_TIME: u64 = 10;
 const TEST_TIMEOUT: u64 = 10;
@@ -26,6 +35,19 @@ const DUMMY_FILE_PATH: &str = "./dummy_file.txt";
 const DUMMY_RECV_FILE_PATH: &str = "./test_dummy/dummy_file.txt";
 const INT_DUMMY_FILE_PATH: &str = "./dummy_file1.txt";
 const INT_DUMMY_RECV_FILE_PATH: &str = "./test_dummy1/dummy_file1.txt";
+const LARGE_DUMMY_FILE_PATH: &str = "./dummy_file2.bin";
+const LARGE_DUMMY_RECV_FILE_PATH: &str = "./test_dummy2/dummy_file2.bin";
+const DUMMY_FOLDER_PATH: &str = "./dummy_folder";
+const DUMMY_RECV_FOLDER_PATH: &str = "./test_dummy3/dummy_folder";
+const MISSING_FILE_PATH: &str = "./no_such_file.txt";
+const UNSHARED_FILE_PATH: &str = "./dummy_file4.txt";
+const CANCEL_DUMMY_FILE_PATH: &str = "./dummy_file5.bin";
+const CANCEL_DUMMY_RECV_FILE_PATH: &str = "./test_dummy5/dummy_file5.bin";
+const CANCEL_TRANSFER_ID: u64 = 1;
+const MIRROR_DUMMY_FILE_PATH: &str = "./dummy_file6.bin";
+const DELTA_DUMMY_FILE_PATH: &str = "./dummy_file7.bin";
+const DEDUP_DUMMY_FILE_PATH: &str = "./dummy_file8.bin";
+const DEDUP_DUMMY_RECV_FILE_PATH: &str = "./test_dummy8/dummy_file8.bin";
 
 static SERVER_INSTANCE: OnceCell<TestServer> = OnceCell::const_new();
 
@@ -40,12 +62,17 @@ impl Drop for TestServer {
 }
 
 async fn start_test_server() -> TestServer {
+    // the relay id in LOCAL_RELAY_ADDR belongs to this key
+    let key = generate_ed25519("0").to_protobuf_encoding().unwrap();
+    std::fs::write(TEST_RELAY_KEY_PATH, key).expect("Failed to write relay key");
     let process = Command::new("cargo")
         .arg("run")
         .arg("--manifest-path")
         .arg("../server/Cargo.toml")
         .arg("--")
         .arg("--test-p2p")
+        .arg("--identity")
+        .arg(TEST_RELAY_KEY_PATH)
         .spawn()
         .expect("Failed to start server");
 
@@ -65,7 +92,8 @@ async fn wait_test_server() -> &'static TestServer {
 async fn test_get_local_peer_id() {
     let _server = wait_test_server().await;
 
-    let client = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("peer_id");
+    let (client, _events) = setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
 
     let _ = client.warm_up_with_delay(WARMUP_TIME).await;
 
@@ -92,7 +120,8 @@ async fn test_get_local_peer_id() {
 async fn test_get_status() {
     let _server = wait_test_server().await;
 
-    let client = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("status");
+    let (client, _events) = setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
 
     let _ = client.warm_up_with_delay(WARMUP_TIME).await;
 
@@ -122,7 +151,8 @@ async fn test_get_status() {
 async fn test_connect_to_relay() {
     let _server = wait_test_server().await;
 
-    let client = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("relay");
+    let (client, _events) = setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
     let _ = client.warm_up_with_delay(WARMUP_TIME).await;
 
     let (tx, rx) = channel();
@@ -149,7 +179,8 @@ async fn test_connect_to_relay() {
 async fn test_listening() {
     let _server = wait_test_server().await;
 
-    let client = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("listening");
+    let (client, _events) = setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
     let _ = client.warm_up_with_delay(WARMUP_TIME).await;
 
     let listen_result = client.listen_on_peer(10).await;
@@ -175,10 +206,11 @@ async fn test_listening() {
 async fn test_list_pending_requests() {
     let _server = wait_test_server().await;
 
-    let client = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("pending");
+    let (client, _events) = setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
     let _ = client.warm_up_with_delay(WARMUP_TIME).await;
 
-    tokio::fs::write(DUMMY_FILE_PATH, DUMMY_CONTENT)
+    tokio::fs::write(workspace.join(DUMMY_FILE_PATH), DUMMY_CONTENT)
         .await
         .expect("Failed to create dummy file");
 
@@ -230,18 +262,17 @@ async fn test_list_pending_requests() {
             panic!("Test timed out while waiting for pending requests");
         }
     }
-
-    tokio::fs::remove_file(DUMMY_FILE_PATH)
-        .await
-        .expect("Failed to delete dummy file on sender");
 }
 
 #[tokio::test]
 async fn test_dial_peer() {
     let _server = wait_test_server().await;
 
-    let client_a = setup_mock_client(&generate_rand_id()).await;
-    let client_b = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("dial");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
 
     let _ = tokio::join!(
         client_a.warm_up_with_delay(WARMUP_TIME),
@@ -317,23 +348,27 @@ async fn test_dial_peer() {
 async fn test_receive_file() {
     let _server = wait_test_server().await;
 
-    let client_a = setup_mock_client(&generate_rand_id()).await;
-    let client_b = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("receive");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
 
     let _ = tokio::join!(
         client_a.warm_up_with_delay(WARMUP_TIME),
         client_b.warm_up_with_delay(WARMUP_TIME)
     );
 
-    tokio::fs::write(DUMMY_FILE_PATH, DUMMY_CONTENT)
+    tokio::fs::write(workspace.join(DUMMY_FILE_PATH), DUMMY_CONTENT)
         .await
         .expect("Failed to create dummy file");
 
-    tokio::fs::create_dir_all("./test_dummy")
+    tokio::fs::create_dir_all(workspace.join("test_dummy"))
         .await
         .expect("Failed to create directory for received file");
 
     let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[DUMMY_FILE_PATH], &[]).await;
     let _ = client_a.connect_peer(sender_peer_id.clone(), 10).await;
 
     // tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -344,6 +379,9 @@ async fn test_receive_file() {
         remote_peer_id: sender_peer_id.clone(),
         src_path: DUMMY_FILE_PATH.to_string(),
         tgt_path: DUMMY_RECV_FILE_PATH.to_string(),
+        mirrors: Mirrors::default(),
+        patch: None,
+        transfer: None,
         response_tx: recv_tx,
     };
     client_a
@@ -365,7 +403,7 @@ async fn test_receive_file() {
     }
 
     // Verify file size on the receiver's side
-    let received_metadata = tokio::fs::metadata(DUMMY_RECV_FILE_PATH)
+    let received_metadata = tokio::fs::metadata(workspace.join(DUMMY_RECV_FILE_PATH))
         .await
         .expect("Failed to get metadata of the received file");
     let expected_size = DUMMY_CONTENT.len() as u64;
@@ -374,45 +412,570 @@ async fn test_receive_file() {
         expected_size,
         "Received file size should match the expected size"
     );
+}
+
+#[tokio::test]
+async fn test_receive_large_file() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("large");
+    let (client_a, mut events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // Spans several chunks and ends on a partial one
+    let content: Vec<u8> = (0..(CHUNK_SIZE * 2 + 123))
+        .map(|i| (i % 251) as u8)
+        .collect();
+    tokio::fs::write(workspace.join(LARGE_DUMMY_FILE_PATH), &content)
+        .await
+        .expect("Failed to create dummy file");
+
+    tokio::fs::create_dir_all(workspace.join("test_dummy2"))
+        .await
+        .expect("Failed to create directory for received file");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[LARGE_DUMMY_FILE_PATH], &[]).await;
+    let _ = client_a.connect_peer(sender_peer_id.clone(), 10).await;
+
+    // Recv file A <- B
+    let (recv_tx, recv_rx) = channel();
+    let receive_command = P2pCommand::RecvFile {
+        remote_peer_id: sender_peer_id.clone(),
+        src_path: LARGE_DUMMY_FILE_PATH.to_string(),
+        tgt_path: LARGE_DUMMY_RECV_FILE_PATH.to_string(),
+        mirrors: Mirrors::default(),
+        patch: None,
+        transfer: None,
+        response_tx: recv_tx,
+    };
+    client_a
+        .command_tx
+        .send(receive_command)
+        .await
+        .expect("Failed to send receive command");
+
+    tokio::select! {
+        result = recv_rx => {
+            let transfer_result = result.expect("Failed to receive file transfer status");
+            assert!(transfer_result.is_ok(), "File transfer should succeed");
+        }
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while receiving file");
+        }
+    }
+
+    // Verify content on the receiver's side
+    let received = tokio::fs::read(workspace.join(LARGE_DUMMY_RECV_FILE_PATH))
+        .await
+        .expect("Failed to read the received file");
+    assert!(
+        received == content,
+        "Received file content should match the sent file"
+    );
+
+    // Progress was reported per chunk, up to the full size
+    let mut reported = Vec::new();
+    while let Ok(event) = events_a.try_recv() {
+        if let ClientEvent::Progress { progress } = event {
+            reported.push(progress.bytes);
+            assert_eq!(progress.total, content.len() as u64);
+        }
+    }
+    assert_eq!(reported.len(), 3, "Every chunk should report progress");
+    assert_eq!(reported.last(), Some(&(content.len() as u64)));
+}
+
+#[tokio::test]
+async fn test_receive_folder() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("folder");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // Nested files plus an empty folder
+    let files = ["a.txt", "sub/b.txt", "sub/deeper/c.txt"];
+    for file in files {
+        let path = workspace.join(DUMMY_FOLDER_PATH).join(file);
+        tokio::fs::create_dir_all(path.parent().unwrap())
+            .await
+            .expect("Failed to create dummy folder");
+        tokio::fs::write(&path, DUMMY_CONTENT)
+            .await
+            .expect("Failed to create dummy file");
+    }
+    tokio::fs::create_dir_all(workspace.join(DUMMY_FOLDER_PATH).join("empty"))
+        .await
+        .expect("Failed to create empty folder");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    let shared_files: Vec<String> = files
+        .iter()
+        .map(|file| format!("{}/{}", DUMMY_FOLDER_PATH, file))
+        .collect();
+    let shared_folders: Vec<String> = ["", "/sub", "/sub/deeper", "/empty"]
+        .iter()
+        .map(|folder| format!("{}{}", DUMMY_FOLDER_PATH, folder))
+        .collect();
+    share_with(
+        &client_b,
+        &client_a,
+        &shared_files.iter().map(String::as_str).collect::<Vec<_>>(),
+        &shared_folders
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    )
+    .await;
+
+    // Recv folder A <- B
+    let results = tokio::select! {
+        results = client_a.recv_path(
+            sender_peer_id,
+            DUMMY_FOLDER_PATH.to_string(),
+            DUMMY_RECV_FOLDER_PATH.to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ) => results.expect("Folder transfer should succeed"),
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while receiving folder");
+        }
+    };
+    assert_eq!(results.len(), files.len(), "Every file should be reported");
+    assert!(
+        results.iter().all(|file| file.result.is_ok()),
+        "Every file should be received"
+    );
+
+    // Verify the tree on the receiver's side
+    for file in files {
+        let received = tokio::fs::read(workspace.join(DUMMY_RECV_FOLDER_PATH).join(file))
+            .await
+            .expect("Failed to read the received file");
+        assert_eq!(
+            received, DUMMY_CONTENT,
+            "Received file content should match"
+        );
+    }
+    assert!(
+        workspace
+            .join(DUMMY_RECV_FOLDER_PATH)
+            .join("empty")
+            .is_dir(),
+        "Empty folders should be recreated"
+    );
+}
+
+#[tokio::test]
+async fn test_receive_missing_file() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("missing");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[MISSING_FILE_PATH], &[]).await;
+
+    // The sender reports a typed error instead of a message to parse
+    let error = tokio::select! {
+        results = client_a.recv_path(
+            sender_peer_id,
+            MISSING_FILE_PATH.to_string(),
+            MISSING_FILE_PATH.to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ) => results.expect_err("Receiving a missing file should fail"),
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while receiving file");
+        }
+    };
+    assert_eq!(error.code, ErrorCode::NotFound, "Error should be NotFound");
+}
+
+#[tokio::test]
+async fn test_receive_unshared_file() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("unshared");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // Present on disk but missing from the published file map
+    tokio::fs::write(workspace.join(UNSHARED_FILE_PATH), DUMMY_CONTENT)
+        .await
+        .expect("Failed to create dummy file");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[], &[]).await;
+
+    let error = tokio::select! {
+        results = client_a.recv_path(
+            sender_peer_id,
+            UNSHARED_FILE_PATH.to_string(),
+            "./dummy_file4_recv.txt".to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ) => results.expect_err("Receiving an unshared file should fail"),
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while receiving file");
+        }
+    };
+    assert_eq!(error.code, ErrorCode::Rejected, "Error should be Rejected");
+}
+
+#[tokio::test]
+async fn test_cancel_receive() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("cancel");
+    let (client_a, mut events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
 
-    // Cleanup
-    tokio::fs::remove_file(DUMMY_FILE_PATH)
+    // Large enough to still be in flight after the first chunk
+    let content = vec![7u8; (CHUNK_SIZE * 8) as usize];
+    tokio::fs::write(workspace.join(CANCEL_DUMMY_FILE_PATH), &content)
         .await
-        .expect("Failed to delete dummy file on sender");
-    tokio::fs::remove_file(DUMMY_RECV_FILE_PATH)
+        .expect("Failed to create dummy file");
+
+    tokio::fs::create_dir_all(workspace.join("test_dummy5"))
         .await
-        .expect("Failed to delete received file on receiver");
-    tokio::fs::remove_dir_all("./test_dummy")
+        .expect("Failed to create directory for received file");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[CANCEL_DUMMY_FILE_PATH], &[]).await;
+    let _ = client_a.connect_peer(sender_peer_id.clone(), 10).await;
+
+    // Recv file A <- B
+    let (recv_tx, recv_rx) = channel();
+    let receive_command = P2pCommand::RecvFile {
+        remote_peer_id: sender_peer_id.clone(),
+        src_path: CANCEL_DUMMY_FILE_PATH.to_string(),
+        tgt_path: CANCEL_DUMMY_RECV_FILE_PATH.to_string(),
+        mirrors: Mirrors::default(),
+        patch: None,
+        transfer: Some(CANCEL_TRANSFER_ID),
+        response_tx: recv_tx,
+    };
+    client_a
+        .command_tx
+        .send(receive_command)
         .await
-        .expect("Failed to delete test directory");
+        .expect("Failed to send receive command");
+
+    // Cancel once the first chunk is on disk
+    tokio::select! {
+        _ = async {
+            while let Some(event) = events_a.recv().await {
+                if let ClientEvent::Progress { .. } = event {
+                    break;
+                }
+            }
+        } => {}
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while waiting for progress");
+        }
+    }
+    let cancel_command = P2pCommand::CancelTransfer {
+        direction: Direction::Receive,
+        transfer: CANCEL_TRANSFER_ID,
+        path: CANCEL_DUMMY_FILE_PATH.to_string(),
+    };
+    client_a
+        .command_tx
+        .send(cancel_command)
+        .await
+        .expect("Failed to send cancel command");
+
+    tokio::select! {
+        result = recv_rx => {
+            let error = result
+                .expect("Failed to receive file transfer status")
+                .expect_err("Cancelled transfer should fail");
+            assert_eq!(error.code, ErrorCode::Cancelled, "Error should be Cancelled");
+        }
+        _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT * 3)) => {
+            panic!("Test timed out while cancelling file");
+        }
+    }
+
+    // Neither the file nor its partial is left behind
+    let mut entries = tokio::fs::read_dir(workspace.join("test_dummy5"))
+        .await
+        .expect("Failed to read test directory");
+    assert!(
+        entries.next_entry().await.unwrap().is_none(),
+        "Cancelled download should leave no files"
+    );
+}
+
+#[tokio::test]
+async fn test_receive_from_mirrors() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("mirrors");
+    let received = Workspace::new("mirrors_received");
+    let (client_a, mut events_a) =
+        setup_mock_client_in(&generate_rand_id(), received.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_c, _events_c) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME),
+        client_c.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // B and C share the same workspace, so they hold identical copies
+    let content: Vec<u8> = (0..(CHUNK_SIZE * 4 + 77))
+        .map(|i| (i % 241) as u8)
+        .collect();
+    tokio::fs::write(workspace.join(MIRROR_DUMMY_FILE_PATH), &content)
+        .await
+        .expect("Failed to create dummy file");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    let mirror_peer_id = get_peer_id(&client_c).await;
+    share_with(&client_b, &client_a, &[MIRROR_DUMMY_FILE_PATH], &[]).await;
+    share_with(&client_c, &client_a, &[MIRROR_DUMMY_FILE_PATH], &[]).await;
+    let file_maps = [&sender_peer_id, &mirror_peer_id]
+        .into_iter()
+        .map(|peer_id| {
+            let peer_id = PeerId::from_str(peer_id).expect("Invalid PeerId");
+            (peer_id, file_map(&[MIRROR_DUMMY_FILE_PATH], &[]))
+        })
+        .collect();
+    client_a
+        .update_holders(file_maps)
+        .await
+        .expect("Failed to update holders");
+
+    // Recv file A <- B, with C as a mirror
+    let results = tokio::time::timeout(
+        Duration::from_secs(TEST_TIMEOUT * 3),
+        client_a.recv_path(
+            sender_peer_id,
+            MIRROR_DUMMY_FILE_PATH.to_string(),
+            // relative to A's own workspace
+            MIRROR_DUMMY_FILE_PATH.to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ),
+    )
+    .await
+    .expect("Test timed out while receiving file")
+    .expect("Receive should start");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].result.is_ok(), "File transfer should succeed");
+
+    let pulled = tokio::fs::read(received.join(MIRROR_DUMMY_FILE_PATH))
+        .await
+        .expect("Failed to read the received file");
+    assert!(
+        pulled == content,
+        "Received file content should match the sent file"
+    );
+
+    // Progress counts the chunks of every source together
+    let mut last = 0;
+    while let Ok(event) = events_a.try_recv() {
+        if let ClientEvent::Progress { progress } = event {
+            last = progress.bytes;
+        }
+    }
+    assert_eq!(last, content.len() as u64);
+}
+
+#[tokio::test]
+async fn test_receive_delta() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("delta");
+    let received = Workspace::new("delta_received");
+    let (client_a, mut events_a) =
+        setup_mock_client_in(&generate_rand_id(), received.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // The receiver holds an older copy with a small edit
+    let content: Vec<u8> = (0..(CHUNK_SIZE * 3)).map(|i| (i % 239) as u8).collect();
+    let mut outdated = content.clone();
+    outdated[CHUNK_SIZE as usize] ^= 0xff;
+    tokio::fs::write(workspace.join(DELTA_DUMMY_FILE_PATH), &content)
+        .await
+        .expect("Failed to create dummy file");
+    tokio::fs::write(received.join(DELTA_DUMMY_FILE_PATH), &outdated)
+        .await
+        .expect("Failed to create outdated copy");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[DELTA_DUMMY_FILE_PATH], &[]).await;
+
+    let results = tokio::time::timeout(
+        Duration::from_secs(TEST_TIMEOUT * 3),
+        client_a.recv_path(
+            sender_peer_id,
+            DELTA_DUMMY_FILE_PATH.to_string(),
+            // relative to A's own workspace
+            DELTA_DUMMY_FILE_PATH.to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ),
+    )
+    .await
+    .expect("Test timed out while receiving file")
+    .expect("Receive should start");
+    assert!(results[0].result.is_ok(), "File transfer should succeed");
+
+    let updated = tokio::fs::read(received.join(DELTA_DUMMY_FILE_PATH))
+        .await
+        .expect("Failed to read the received file");
+    assert!(updated == content, "Received file should be updated");
+
+    // The file is whole blocks with no tail, so one pull rebuilds it
+    let mut reported = 0;
+    while let Ok(event) = events_a.try_recv() {
+        if let ClientEvent::Progress { progress } = event {
+            assert_eq!(progress.total, content.len() as u64);
+            reported += 1;
+        }
+    }
+    assert_eq!(reported, 1, "Only changed blocks should be pulled");
+}
+
+#[tokio::test]
+async fn test_receive_deduplicated() {
+    let _server = wait_test_server().await;
+
+    let workspace = Workspace::new("dedup");
+    let (client_a, mut events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+
+    let _ = tokio::join!(
+        client_a.warm_up_with_delay(WARMUP_TIME),
+        client_b.warm_up_with_delay(WARMUP_TIME)
+    );
+
+    // Both peers share a workspace, so the content is already on the receiver
+    let content: Vec<u8> = (0..(CHUNK_SIZE * 2)).map(|i| (i % 233) as u8).collect();
+    tokio::fs::write(workspace.join(DEDUP_DUMMY_FILE_PATH), &content)
+        .await
+        .expect("Failed to create dummy file");
+
+    let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[DEDUP_DUMMY_FILE_PATH], &[]).await;
+
+    let results = tokio::time::timeout(
+        Duration::from_secs(TEST_TIMEOUT * 3),
+        client_a.recv_path(
+            sender_peer_id,
+            DEDUP_DUMMY_FILE_PATH.to_string(),
+            DEDUP_DUMMY_RECV_FILE_PATH.to_string(),
+            ConflictPolicy::Overwrite,
+            TEST_TIMEOUT,
+            None,
+        ),
+    )
+    .await
+    .expect("Test timed out while receiving file")
+    .expect("Receive should start");
+    assert!(results[0].result.is_ok(), "File transfer should succeed");
+    assert!(results[0].deduplicated, "Transfer should be deduplicated");
+
+    let received = tokio::fs::read(workspace.join(DEDUP_DUMMY_RECV_FILE_PATH))
+        .await
+        .expect("Failed to read the received file");
+    assert!(received == content, "Received file content should match");
+
+    // No chunk crossed the network
+    while let Ok(event) = events_a.try_recv() {
+        assert!(
+            !matches!(event, ClientEvent::Progress { .. }),
+            "Deduplicated transfer should pull nothing"
+        );
+    }
 }
 
 #[tokio::test]
 async fn test_integrated_file_transfer() {
     let _server = wait_test_server().await;
 
-    let client_a = setup_mock_client(&generate_rand_id()).await;
-    let client_b = setup_mock_client(&generate_rand_id()).await;
+    let workspace = Workspace::new("integrated");
+    let (client_a, _events_a) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
+    let (client_b, _events_b) =
+        setup_mock_client_in(&generate_rand_id(), workspace.base_dir()).await;
 
     let _ = tokio::join!(
         client_a.warm_up_with_delay(WARMUP_TIME),
         client_b.warm_up_with_delay(WARMUP_TIME)
     );
 
-    tokio::fs::write(INT_DUMMY_FILE_PATH, DUMMY_CONTENT)
+    tokio::fs::write(workspace.join(INT_DUMMY_FILE_PATH), DUMMY_CONTENT)
         .await
         .expect("Failed to create dummy file");
 
-    tokio::fs::create_dir_all("./test_dummy1")
+    tokio::fs::create_dir_all(workspace.join("test_dummy1"))
         .await
         .expect("Failed to create directory for received file");
 
     let sender_peer_id = get_peer_id(&client_b).await;
+    share_with(&client_b, &client_a, &[INT_DUMMY_FILE_PATH], &[]).await;
 
     let _ = client_a.connect_peer(sender_peer_id.clone(), 5).await;
 
     // Start sender
-    let mut send_rx: Option<tokio::sync::oneshot::Receiver<Result<(), String>>> = None;
+    let mut send_rx: Option<tokio::sync::oneshot::Receiver<Result<(), FileError>>> = None;
     let sender_future = {
         async move {
             let src_path = INT_DUMMY_FILE_PATH.to_string();
@@ -433,10 +996,10 @@ async fn test_integrated_file_transfer() {
 
             if let Some(mut rx) = send_rx {
                 tokio::select! {
-                    res = client_b.send_file_wait(&mut rx, TEST_WAIT_TIMEOUT) => {
+                    res = client_b.send_file_wait(&mut rx) => {
                         assert!(res.is_ok(), "File sending should complete successfully");
                     }
-                    _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_TIMEOUT)) => {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(TEST_WAIT_TIMEOUT)) => {
                         panic!("Test timed out while waiting for file send completion");
                     }
                 }
@@ -457,6 +1020,9 @@ async fn test_integrated_file_transfer() {
                 remote_peer_id: sender_peer_id.clone(),
                 src_path: INT_DUMMY_FILE_PATH.to_string(),
                 tgt_path: INT_DUMMY_RECV_FILE_PATH.to_string(),
+                mirrors: Mirrors::default(),
+                patch: None,
+                transfer: None,
                 response_tx: recv_tx,
             };
 
@@ -482,7 +1048,7 @@ async fn test_integrated_file_transfer() {
     tokio::join!(sender_future, receiver_future);
 
     // Verify file size
-    let received_metadata = tokio::fs::metadata(INT_DUMMY_RECV_FILE_PATH)
+    let received_metadata = tokio::fs::metadata(workspace.join(INT_DUMMY_RECV_FILE_PATH))
         .await
         .expect("Failed to get metadata of the received file");
     let expected_size = DUMMY_CONTENT.len() as u64;
@@ -491,26 +1057,57 @@ async fn test_integrated_file_transfer() {
         expected_size,
         "Received file size should match the expected size"
     );
+}
 
-    // Cleanup
-    tokio::fs::remove_file(INT_DUMMY_FILE_PATH)
-        .await
-        .expect("Failed to delete dummy file on sender");
-    tokio::fs::remove_file(INT_DUMMY_RECV_FILE_PATH)
+// A client serving and saving in `base_dir`, clients sharing it hold the same files
+async fn setup_mock_client_in(
+    client_name: &str,
+    base_dir: &str,
+) -> (P2PTransport, tokio::sync::mpsc::Receiver<ClientEvent>) {
+    let (tx, rx) = tokio::sync::mpsc::channel::<ClientEvent>(1024);
+    let client = P2PTransport::new(
+        &[LOCAL_RELAY_ADDR.to_string()],
+        generate_ed25519(client_name),
+        Transports::default(),
+        tx,
+        PathBuf::from(base_dir),
+    )
+    .expect("Failed to create client");
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    (client, rx)
+}
+
+// Publishes the given paths on `sender` and admits `receiver` to its group
+async fn share_with(
+    sender: &P2PTransport,
+    receiver: &P2PTransport,
+    files: &[&str],
+    folders: &[&str],
+) {
+    let file_map = file_map(files, folders);
+    let receiver_id = PeerId::from_str(&get_peer_id(receiver).await).expect("Invalid PeerId");
+    sender
+        .update_shares(file_map)
         .await
-        .expect("Failed to delete received file on receiver");
-    tokio::fs::remove_dir_all("./test_dummy1")
+        .expect("Failed to update shares");
+    sender
+        .update_group(vec![(receiver_id, Vec::new())])
         .await
-        .expect("Failed to delete test directory");
+        .expect("Failed to update group");
 }
 
-async fn setup_mock_client(client_name: &str) -> P2PTransport {
-    let relay_address = LOCAL_RELAY_ADDR;
-    let (tx, rx) = tokio::sync::mpsc::channel::<ClientEvent>(1024);
-    let res = P2PTransport::new(&relay_address, client_name, tx, PathBuf::from("./"))
-        .expect("Failed to create client");
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-    res
+fn file_map(files: &[&str], folders: &[&str]) -> FileMap {
+    let home = |path: &str| format!("home/{}", path.trim_start_matches("./"));
+    FileMap {
+        os: OS {
+            name: std::env::consts::OS.to_string(),
+        },
+        files: files.iter().map(|path| File { name: home(path) }).collect(),
+        folders: folders
+            .iter()
+            .map(|path| Folder { name: home(path) })
+            .collect(),
+    }
 }
 
 async fn get_peer_id(client: &P2PTransport) -> String {
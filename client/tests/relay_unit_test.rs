@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+
+use kudrive_client::net::relay::{RelayStatus, RelayWatch, Relays};
+use libp2p::{core::transport::ListenerId, Multiaddr, PeerId};
+use tokio::sync::oneshot;
+
+#[test]
+fn test_backoff() {
+    assert_eq!(RelayWatch::backoff(0), Duration::from_secs(1));
+    assert_eq!(RelayWatch::backoff(3), Duration::from_secs(8));
+    assert_eq!(RelayWatch::backoff(10), Duration::from_secs(60));
+    assert_eq!(RelayWatch::backoff(u32::MAX), Duration::from_secs(60));
+}
+
+#[test]
+fn test_reconnect() {
+    let mut relay = RelayWatch::default();
+    let now = Instant::now();
+    assert_eq!(relay.connected(), Some(RelayStatus::Connected));
+    assert_eq!(relay.reserved(), Some(RelayStatus::Reserved));
+
+    // the relay restarted
+    let lost = Some(RelayStatus::Disconnected { retry_in_sec: 1 });
+    assert_eq!(relay.lost(now), lost);
+    // closing the connection and its listener report once
+    assert_eq!(relay.lost(now), None);
+    assert_eq!(relay.unreserved(), None);
+    assert!(!relay.due(now));
+    assert!(relay.due(now + Duration::from_secs(1)));
+    assert_eq!(relay.status(), RelayStatus::Connecting);
+
+    // still down, so the next redial waits longer
+    let lost = Some(RelayStatus::Disconnected { retry_in_sec: 2 });
+    assert_eq!(relay.lost(now), lost);
+    assert!(!relay.due(now + Duration::from_secs(1)));
+    assert!(relay.due(now + Duration::from_secs(2)));
+
+    // back up, a later loss starts over
+    assert_eq!(relay.connected(), Some(RelayStatus::Connected));
+    let lost = Some(RelayStatus::Disconnected { retry_in_sec: 1 });
+    assert_eq!(relay.lost(now), lost);
+}
+
+fn relay(host: &str) -> (PeerId, Multiaddr) {
+    let id = PeerId::random();
+    let address = format!("/ip4/{}/tcp/4001/p2p/{}", host, id)
+        .parse()
+        .unwrap();
+    (id, address)
+}
+
+#[test]
+fn test_fastest_reserved_first() {
+    let (seoul, seoul_address) = relay("10.0.0.1");
+    let (tokyo, tokyo_address) = relay("10.0.0.2");
+    let mut relays = Relays::new(vec![seoul_address.clone(), tokyo_address.clone()]);
+    // before anything is known the configured order holds
+    assert_eq!(
+        relays.by_latency(),
+        vec![seoul_address.clone(), tokyo_address.clone()]
+    );
+
+    for id in [seoul, tokyo] {
+        relays.connected(&id);
+        relays.reserved(&id);
+    }
+    relays.rtt(&seoul, Duration::from_millis(80));
+    relays.rtt(&tokyo, Duration::from_millis(20));
+    assert_eq!(
+        relays.by_latency(),
+        vec![tokyo_address.clone(), seoul_address.clone()]
+    );
+
+    // the faster one went away
+    relays.lost(&tokyo, Instant::now());
+    assert_eq!(relays.by_latency(), vec![seoul_address, tokyo_address]);
+}
+
+#[test]
+fn test_reservation_per_relay() {
+    let (seoul, seoul_address) = relay("10.0.0.1");
+    let (tokyo, tokyo_address) = relay("10.0.0.2");
+    let mut relays = Relays::new(vec![seoul_address.clone(), tokyo_address.clone()]);
+    assert!(!relays.is_relay(&PeerId::random()));
+    assert_eq!(relays.not_listening(true), vec![]);
+
+    relays.connected(&seoul);
+    relays.connected(&tokyo);
+    let listener = ListenerId::next();
+    relays.listening(&seoul, listener);
+    assert!(relays.is_listening());
+    assert_eq!(relays.not_listening(true), vec![(tokyo, tokyo_address)]);
+
+    relays.reserved(&seoul);
+    let state = relays.listener_closed(listener).unwrap();
+    assert_eq!(state.address, seoul_address.to_string());
+    assert_eq!(state.status, RelayStatus::Connected);
+    assert!(!relays.is_listening());
+}
+
+#[test]
+fn test_waiters_per_relay() {
+    let (seoul, seoul_address) = relay("10.0.0.1");
+    let (tokyo, tokyo_address) = relay("10.0.0.2");
+    let mut relays = Relays::new(vec![seoul_address, tokyo_address]);
+
+    // callers asking at the same time all wait on the one dial
+    let (first_tx, mut first) = oneshot::channel();
+    let (second_tx, mut second) = oneshot::channel();
+    let (tokyo_tx, mut on_tokyo) = oneshot::channel();
+    relays.wait(&seoul, first_tx);
+    relays.wait(&seoul, second_tx);
+    relays.wait(&tokyo, tokyo_tx);
+    assert_eq!(relays.waiting(), vec![seoul, tokyo]);
+
+    relays.answer(&seoul, Ok(()));
+    assert_eq!(first.try_recv(), Ok(Ok(())));
+    assert_eq!(second.try_recv(), Ok(Ok(())));
+    assert!(on_tokyo.try_recv().is_err());
+    assert_eq!(relays.waiting(), vec![tokyo]);
+
+    relays.answer(&tokyo, Err("Connection refused".into()));
+    assert_eq!(
+        on_tokyo.try_recv(),
+        Ok(Err("Connection refused".to_string()))
+    );
+    assert!(relays.waiting().is_empty());
+}
+
+#[test]
+fn test_addresses_without_id() {
+    let (_, address) = relay("10.0.0.1");
+    let relays = Relays::new(vec![
+        "/ip4/10.0.0.3/tcp/4001".parse().unwrap(),
+        address.clone(),
+        address,
+    ]);
+    assert_eq!(relays.addresses().len(), 1);
+}
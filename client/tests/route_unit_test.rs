@@ -0,0 +1,42 @@
+use kudrive_client::net::route::{PeerRoute, Route, Routes};
+use libp2p::{swarm::ConnectionId, PeerId};
+
+#[test]
+fn test_direct_supersedes_relayed() {
+    let mut routes = Routes::default();
+    let peer = PeerId::random();
+    let (relayed, direct) = (
+        ConnectionId::new_unchecked(1),
+        ConnectionId::new_unchecked(2),
+    );
+    assert_eq!(routes.route(&peer), None);
+
+    routes.established(peer, relayed, Route::Relayed);
+    assert_eq!(routes.route(&peer), Some(Route::Relayed));
+    assert!(routes.superseded(&peer).is_empty());
+
+    // hole punching succeeded
+    routes.established(peer, direct, Route::Direct);
+    assert_eq!(routes.route(&peer), Some(Route::Direct));
+    assert_eq!(routes.superseded(&peer), vec![relayed]);
+
+    routes.closed(&peer, relayed);
+    assert!(routes.superseded(&peer).is_empty());
+    routes.closed(&peer, direct);
+    assert_eq!(routes.route(&peer), None);
+}
+
+#[test]
+fn test_list_leaves_out_relay() {
+    let mut routes = Routes::default();
+    let (relay, peer) = (PeerId::random(), PeerId::random());
+    routes.established(relay, ConnectionId::new_unchecked(1), Route::Direct);
+    routes.established(peer, ConnectionId::new_unchecked(2), Route::Relayed);
+    assert_eq!(
+        routes.list(&[relay]),
+        vec![PeerRoute {
+            peer_id: peer.to_string(),
+            route: Route::Relayed
+        }]
+    );
+}
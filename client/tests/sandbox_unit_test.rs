@@ -0,0 +1,100 @@
+mod common;
+
+use common::Workspace;
+use kudrive_client::net::ku_protocol::ErrorCode;
+use kudrive_client::net::sandbox::resolve;
+
+// A workspace with one shared file, next to a secret outside of it
+fn setup_workspace() -> Workspace {
+    let workspace = Workspace::new("sandbox");
+    let root = &workspace.root;
+    std::fs::create_dir_all(root.join("workspace/docs")).expect("Failed to create workspace");
+    std::fs::write(root.join("workspace/docs/shared.txt"), b"shared")
+        .expect("Failed to create shared file");
+    std::fs::write(root.join("secret.txt"), b"secret").expect("Failed to create secret file");
+    workspace
+}
+
+fn assert_rejected(workspace: &Workspace, path: &str) {
+    let error = resolve(&workspace.root.join("workspace"), path)
+        .expect_err("Path outside the workspace should be rejected");
+    assert_eq!(
+        error.code,
+        ErrorCode::Rejected,
+        "{:?} should be rejected",
+        path
+    );
+}
+
+#[test]
+fn test_resolve_inside_workspace() {
+    let workspace = setup_workspace();
+    let base = workspace.root.join("workspace");
+    let canonical = base.canonicalize().unwrap();
+
+    let existing = resolve(&base, "./docs/shared.txt").expect("Shared file should resolve");
+    // `PathBuf` equality ignores a trailing `/`, the string does not
+    assert_eq!(
+        existing.to_str(),
+        canonical.join("docs/shared.txt").to_str()
+    );
+    assert!(std::fs::File::open(&existing).is_ok());
+
+    // save targets do not exist yet
+    let new = resolve(&base, "./docs/new/file.txt").expect("New file should resolve");
+    assert_eq!(new, canonical.join("docs/new/file.txt"));
+}
+
+#[test]
+fn test_resolve_rejects_absolute_path() {
+    let workspace = setup_workspace();
+    let secret = workspace.root.join("secret.txt");
+    assert_rejected(&workspace, secret.to_str().unwrap());
+    assert_rejected(&workspace, "/etc/passwd");
+}
+
+#[test]
+fn test_resolve_rejects_parent_traversal() {
+    let workspace = setup_workspace();
+    assert_rejected(&workspace, "../secret.txt");
+    assert_rejected(&workspace, "./docs/../../secret.txt");
+    // even when it would end up back inside
+    assert_rejected(&workspace, "./docs/../docs/shared.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolve_rejects_symlink_escape() {
+    let workspace = setup_workspace();
+    let base = workspace.root.join("workspace");
+    std::os::unix::fs::symlink(workspace.root.join("secret.txt"), base.join("link.txt"))
+        .expect("Failed to create file link");
+    std::os::unix::fs::symlink(&workspace.root, base.join("outside"))
+        .expect("Failed to create folder link");
+    std::os::unix::fs::symlink(
+        workspace.root.join("missing.txt"),
+        base.join("dangling.txt"),
+    )
+    .expect("Failed to create dangling link");
+
+    assert_rejected(&workspace, "./link.txt");
+    assert_rejected(&workspace, "./outside/secret.txt");
+    // saving below a linked folder would write outside too
+    assert_rejected(&workspace, "./outside/new/file.txt");
+    assert_rejected(&workspace, "./dangling.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolve_allows_symlink_inside() {
+    let workspace = setup_workspace();
+    let base = workspace.root.join("workspace");
+    std::os::unix::fs::symlink(base.join("docs"), base.join("alias"))
+        .expect("Failed to create folder link");
+
+    let resolved = resolve(&base, "./alias/shared.txt").expect("Link inside should resolve");
+    assert_eq!(
+        resolved,
+        base.canonicalize().unwrap().join("docs/shared.txt")
+    );
+}
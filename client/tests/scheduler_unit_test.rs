@@ -0,0 +1,142 @@
+use kudrive_client::client::scheduler::{Scheduler, TransferState};
+use kudrive_client::event::Direction;
+use kudrive_common::{ConflictPolicy, Peer};
+use std::time::Duration;
+use uuid::Uuid;
+
+fn peer(id: Uuid, source: &str) -> Peer {
+    Peer {
+        id,
+        source: source.to_string(),
+        target: format!("./recv/{}", source),
+        conflict: ConflictPolicy::default(),
+    }
+}
+
+// Drains everything the scheduler lets start right now
+fn start_all(scheduler: &mut Scheduler) -> Vec<u64> {
+    std::iter::from_fn(|| scheduler.start_next().map(|(id, _, _)| id)).collect()
+}
+
+#[test]
+fn test_global_limit() {
+    let mut scheduler = Scheduler::new(2, 2);
+    for id in 0..4 {
+        scheduler.push(id, Direction::Receive, peer(Uuid::new_v4(), "a.txt"));
+    }
+
+    assert_eq!(start_all(&mut scheduler), vec![0, 1]);
+
+    // a finished transfer frees its slot for the next in line
+    scheduler.remove(0).expect("Transfer should be known");
+    assert_eq!(start_all(&mut scheduler), vec![2]);
+}
+
+#[test]
+fn test_peer_limit() {
+    let mut scheduler = Scheduler::new(4, 1);
+    let (busy, idle) = (Uuid::new_v4(), Uuid::new_v4());
+    scheduler.push(0, Direction::Receive, peer(busy, "a.txt"));
+    scheduler.push(1, Direction::Receive, peer(busy, "b.txt"));
+    scheduler.push(2, Direction::Send, peer(idle, "c.txt"));
+
+    // the second transfer of a busy peer does not hold up the others
+    assert_eq!(start_all(&mut scheduler), vec![0, 2]);
+
+    scheduler.remove(0).expect("Transfer should be known");
+    assert_eq!(start_all(&mut scheduler), vec![1]);
+}
+
+#[test]
+fn test_bump() {
+    let mut scheduler = Scheduler::new(1, 1);
+    let id = Uuid::new_v4();
+    for transfer in 0..3 {
+        scheduler.push(transfer, Direction::Receive, peer(id, "a.txt"));
+    }
+    assert_eq!(start_all(&mut scheduler), vec![0]);
+
+    assert!(scheduler.bump(2), "Queued transfer should be bumped");
+    assert!(!scheduler.bump(0), "Running transfer cannot be bumped");
+
+    scheduler.remove(0).expect("Transfer should be known");
+    assert_eq!(start_all(&mut scheduler), vec![2]);
+    scheduler.remove(2).expect("Transfer should be known");
+    assert_eq!(start_all(&mut scheduler), vec![1]);
+}
+
+#[test]
+fn test_pause_resume() {
+    let mut scheduler = Scheduler::new(2, 2);
+    scheduler.push(0, Direction::Receive, peer(Uuid::new_v4(), "a.txt"));
+    assert_eq!(start_all(&mut scheduler), vec![0]);
+
+    scheduler.pause();
+    scheduler.push(1, Direction::Receive, peer(Uuid::new_v4(), "b.txt"));
+    assert!(start_all(&mut scheduler).is_empty());
+
+    // running transfers are unaffected by the pause
+    let states: Vec<_> = scheduler
+        .list()
+        .iter()
+        .map(|transfer| (transfer.id, transfer.state))
+        .collect();
+    assert_eq!(
+        states,
+        vec![(0, TransferState::Running), (1, TransferState::Queued)]
+    );
+
+    scheduler.resume();
+    assert_eq!(start_all(&mut scheduler), vec![1]);
+}
+
+#[test]
+fn test_remove_queued() {
+    let mut scheduler = Scheduler::new(1, 1);
+    scheduler.push(0, Direction::Receive, peer(Uuid::new_v4(), "a.txt"));
+    scheduler.push(1, Direction::Receive, peer(Uuid::new_v4(), "b.txt"));
+    assert_eq!(start_all(&mut scheduler), vec![0]);
+
+    let (_, _, state) = scheduler.remove(1).expect("Transfer should be known");
+    assert_eq!(state, TransferState::Queued);
+    assert!(!scheduler.contains(1));
+
+    scheduler.remove(0).expect("Transfer should be known");
+    assert!(start_all(&mut scheduler).is_empty());
+}
+
+#[test]
+fn test_unanswered_claim() {
+    let mut scheduler = Scheduler::new(1, 1);
+    let id = Uuid::new_v4();
+    scheduler.push(0, Direction::Receive, peer(id, "a.txt"));
+    scheduler.push(1, Direction::Receive, peer(id, "b.txt"));
+    assert_eq!(start_all(&mut scheduler), vec![0]);
+
+    // still within the deadline
+    assert!(scheduler.expire_claims(Duration::from_secs(60)).is_empty());
+
+    // the remote never answers, which frees the slot for the next in line
+    let expired: Vec<u64> = scheduler
+        .expire_claims(Duration::ZERO)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    assert_eq!(expired, vec![0]);
+    assert!(!scheduler.contains(0));
+    assert_eq!(start_all(&mut scheduler), vec![1]);
+
+    // an answered claim runs for as long as it takes
+    scheduler.answered(1);
+    assert!(scheduler.expire_claims(Duration::ZERO).is_empty());
+    assert!(scheduler.contains(1));
+}
+
+#[test]
+fn test_send_has_no_claim() {
+    let mut scheduler = Scheduler::new(1, 1);
+    scheduler.push(0, Direction::Send, peer(Uuid::new_v4(), "a.txt"));
+    assert_eq!(start_all(&mut scheduler), vec![0]);
+
+    assert!(scheduler.expire_claims(Duration::ZERO).is_empty());
+}
@@ -0,0 +1,99 @@
+use kudrive_client::config_loader::RateLimits;
+use kudrive_client::event::Direction;
+use kudrive_client::net::ku_protocol::CHUNK_SIZE;
+use kudrive_client::net::throttle::Throttle;
+use libp2p::PeerId;
+use std::time::Duration;
+
+const KB: u64 = 1024;
+
+fn throttle(limits: RateLimits) -> Throttle {
+    let mut throttle = Throttle::default();
+    throttle.set_limits(limits);
+    throttle
+}
+
+fn assert_about(delay: Duration, secs: f64) {
+    let diff = (delay.as_secs_f64() - secs).abs();
+    assert!(diff < 0.05, "Expected about {}s, waited {:?}", secs, delay);
+}
+
+#[test]
+fn test_unlimited() {
+    let mut throttle = throttle(RateLimits::default());
+    let peer = PeerId::random();
+
+    assert_eq!(throttle.chunk_len(Direction::Send, CHUNK_SIZE), CHUNK_SIZE);
+    for _ in 0..10 {
+        assert_eq!(
+            throttle.reserve(Direction::Send, peer, CHUNK_SIZE),
+            Duration::ZERO
+        );
+    }
+}
+
+#[test]
+fn test_global_limit() {
+    let mut throttle = throttle(RateLimits {
+        download: Some(100 * KB),
+        ..Default::default()
+    });
+    let (a, b) = (PeerId::random(), PeerId::random());
+
+    // chunks shrink to a second's worth
+    assert_eq!(throttle.chunk_len(Direction::Receive, CHUNK_SIZE), 100 * KB);
+
+    assert_eq!(
+        throttle.reserve(Direction::Receive, a, 100 * KB),
+        Duration::ZERO
+    );
+    // every peer draws from the same budget
+    assert_about(throttle.reserve(Direction::Receive, b, 50 * KB), 1.0);
+    assert_about(throttle.reserve(Direction::Receive, a, 100 * KB), 1.5);
+
+    // uploads are not limited by the download budget
+    assert_eq!(
+        throttle.reserve(Direction::Send, a, 100 * KB),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn test_peer_limit() {
+    let mut throttle = throttle(RateLimits {
+        peer_upload: Some(10 * KB),
+        ..Default::default()
+    });
+    let (a, b) = (PeerId::random(), PeerId::random());
+
+    assert_eq!(
+        throttle.reserve(Direction::Send, a, 10 * KB),
+        Duration::ZERO
+    );
+    assert_about(throttle.reserve(Direction::Send, a, 10 * KB), 1.0);
+    // another peer has a budget of its own
+    assert_eq!(
+        throttle.reserve(Direction::Send, b, 10 * KB),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn test_set_limits() {
+    let mut throttle = throttle(RateLimits {
+        upload: Some(KB),
+        ..Default::default()
+    });
+    let peer = PeerId::random();
+    throttle.reserve(Direction::Send, peer, 100 * KB);
+
+    // lifting the limit drops what was booked under it
+    throttle.set_limits(RateLimits {
+        upload: Some(0),
+        ..Default::default()
+    });
+    assert_eq!(
+        throttle.reserve(Direction::Send, peer, 100 * KB),
+        Duration::ZERO
+    );
+}
@@ -0,0 +1,128 @@
+use kudrive_common::p2p::{
+    generate_ed25519, listen_addresses, load_or_create_keypair, prefer_quic, quic_address,
+    transport, PeerIdentity, Transports,
+};
+use libp2p::Multiaddr;
+use uuid::Uuid;
+
+const RELAY: &str =
+    "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWA768LzHMatxkjD1f9DrYW375GZJr6MHPCNEdDtHeTNRt";
+
+fn address(address: &str) -> Multiaddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn test_quic_address() {
+    assert_eq!(
+        quic_address(&address(RELAY)),
+        Some(address(
+            "/ip4/127.0.0.1/udp/4001/quic-v1/p2p/12D3KooWA768LzHMatxkjD1f9DrYW375GZJr6MHPCNEdDtHeTNRt"
+        ))
+    );
+    assert_eq!(
+        quic_address(&address("/ip4/127.0.0.1/udp/4001/quic-v1")),
+        None
+    );
+}
+
+#[test]
+fn test_listen_addresses() {
+    let both = listen_addresses(Transports::default(), 4001);
+    assert_eq!(
+        both,
+        vec![
+            address("/ip4/0.0.0.0/udp/4001/quic-v1"),
+            address("/ip4/0.0.0.0/tcp/4001")
+        ]
+    );
+    let tcp_only = Transports {
+        tcp: true,
+        quic: false,
+    };
+    assert_eq!(
+        listen_addresses(tcp_only, 0),
+        vec![address("/ip4/0.0.0.0/tcp/0")]
+    );
+}
+
+#[test]
+fn test_prefer_quic() {
+    let tcp = address("/ip4/192.168.0.10/tcp/4002");
+    let quic = address("/ip4/192.168.0.10/udp/4003/quic-v1");
+    let other_tcp = address("/ip4/10.0.0.2/tcp/4002");
+    assert_eq!(
+        prefer_quic([tcp.clone(), quic.clone(), other_tcp.clone()]),
+        vec![quic, tcp, other_tcp]
+    );
+}
+
+#[test]
+fn test_transports() {
+    // older configs without the setting keep both
+    let transports: Transports = serde_json::from_str("{}").unwrap();
+    assert_eq!(transports, Transports::default());
+
+    let keypair = generate_ed25519("transport");
+    let none = Transports {
+        tcp: false,
+        quic: false,
+    };
+    assert!(transport(&keypair, none).is_err());
+}
+
+#[test]
+fn test_identity_persists() {
+    let dir = std::env::temp_dir().join(format!("kudrive-identity-{}", std::process::id()));
+    let path = dir.join("identity.key");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let created = load_or_create_keypair(&path).unwrap();
+    let loaded = load_or_create_keypair(&path).unwrap();
+    assert_eq!(created.public().to_peer_id(), loaded.public().to_peer_id());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // a fresh identity, not one derived from anything a peer knows
+    assert_ne!(
+        created.public().to_peer_id(),
+        generate_ed25519("").public().to_peer_id()
+    );
+    std::fs::write(&path, b"not a key").unwrap();
+    assert!(load_or_create_keypair(&path).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_peer_identity() {
+    let keypair = generate_ed25519("identity");
+    let (group, id) = (Uuid::new_v4(), Uuid::new_v4());
+    let quic = address("/ip4/1.2.3.4/udp/4001/quic-v1");
+    let identity = PeerIdentity::new(&keypair, group, id, vec![quic.clone()]).unwrap();
+
+    assert_eq!(
+        identity.verify(group, id),
+        Ok(keypair.public().to_peer_id())
+    );
+    assert_eq!(identity.addresses(), vec![quic]);
+
+    // signed for another client or group
+    assert!(identity.verify(group, Uuid::new_v4()).is_err());
+    assert!(identity.verify(Uuid::new_v4(), id).is_err());
+
+    // addresses changed after signing
+    let mut moved = identity.clone();
+    moved.addresses.push("/ip4/5.6.7.8/tcp/4001".to_string());
+    assert!(moved.verify(group, id).is_err());
+
+    // someone else's peer id with our key
+    let mut claimed = identity.clone();
+    claimed.peer_id = generate_ed25519("other").public().to_peer_id().to_string();
+    assert!(claimed.verify(group, id).is_err());
+
+    assert!(PeerIdentity::default().verify(group, id).is_err());
+}
@@ -0,0 +1,199 @@
+use futures::io::Cursor;
+use kudrive_client::net::ku_protocol::{
+    ErrorCode, FileError, FileRequest, FileResponse, KuFileTransferCodec,
+};
+use kudrive_client::net::transfer;
+use kudrive_client::net::version::{PeerVersions, ProtocolVersion};
+use libp2p::request_response::Codec;
+use libp2p::{PeerId, StreamProtocol};
+
+const LEGACY_PROTOCOL: &str = "/ku-file-transfer/1.0.0";
+const UNKNOWN_PROTOCOL: &str = "/ku-file-transfer/0.9.0";
+
+#[test]
+fn test_negotiate() {
+    let current = ProtocolVersion::V2.protocol();
+    let legacy = StreamProtocol::new(LEGACY_PROTOCOL);
+    assert_eq!(
+        ProtocolVersion::negotiate(&[legacy.clone(), current.clone()]),
+        Some(ProtocolVersion::V2)
+    );
+    assert_eq!(
+        ProtocolVersion::negotiate(std::slice::from_ref(&legacy)),
+        Some(ProtocolVersion::V1)
+    );
+    assert_eq!(
+        ProtocolVersion::negotiate(&[StreamProtocol::new(UNKNOWN_PROTOCOL)]),
+        None
+    );
+    assert_eq!(ProtocolVersion::of(&current), Some(ProtocolVersion::V2));
+    assert_eq!(ProtocolVersion::of(&legacy), Some(ProtocolVersion::V1));
+}
+
+#[test]
+fn test_peer_versions() {
+    let mut versions = PeerVersions::default();
+    let (relay, old, new) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let unknown = PeerId::random();
+
+    // a relay transfers no files, so it is neither compatible nor not
+    let ping = StreamProtocol::new("/ipfs/ping/1.0.0");
+    assert_eq!(versions.identify(relay, "relay", &[ping]), None);
+
+    let legacy = [StreamProtocol::new(LEGACY_PROTOCOL)];
+    assert_eq!(
+        versions.identify(old, "kudrive-client/0.0.1", &legacy),
+        Some(Ok(ProtocolVersion::V1))
+    );
+    assert!(versions.check(&old).is_ok());
+
+    let other = [StreamProtocol::new(UNKNOWN_PROTOCOL)];
+    let reason = versions
+        .identify(unknown, "kudrive-client/0.0.0", &other)
+        .expect("A file transfer peer")
+        .expect_err("No common version");
+    assert!(reason.contains(UNKNOWN_PROTOCOL), "{}", reason);
+    assert!(reason.contains("kudrive-client/0.0.0"), "{}", reason);
+    // requests to it fail before they are sent
+    let error = versions.check(&unknown).expect_err("Incompatible peer");
+    assert_eq!(error.code, ErrorCode::Incompatible);
+    // a peer not identified yet is tried all the same
+    assert!(versions.check(&PeerId::random()).is_ok());
+
+    let current = [ProtocolVersion::V2.protocol()];
+    assert_eq!(
+        versions.identify(new, "kudrive-client/0.1.0", &current),
+        Some(Ok(ProtocolVersion::V2))
+    );
+}
+
+#[tokio::test]
+async fn test_codec_versions() {
+    let mut codec = KuFileTransferCodec();
+    let protocol = ProtocolVersion::V2.protocol();
+    let request = transfer::probe("./file.txt", true);
+
+    let mut wire = Cursor::new(Vec::new());
+    codec
+        .write_request(&protocol, &mut wire, request.clone())
+        .await
+        .expect("Failed to write request");
+    wire.set_position(0);
+    let read = codec
+        .read_request(&protocol, &mut wire)
+        .await
+        .expect("Failed to read request");
+    assert_eq!(read.target_path, request.target_path);
+    assert!(read.digest);
+    assert!(!read.legacy);
+
+    // an unknown version is refused before anything is decoded
+    let unknown = StreamProtocol::new(UNKNOWN_PROTOCOL);
+    wire.set_position(0);
+    let refused: std::io::Result<FileRequest> = codec.read_request(&unknown, &mut wire).await;
+    assert_eq!(
+        refused.expect_err("Unknown version").kind(),
+        std::io::ErrorKind::Unsupported
+    );
+}
+
+#[tokio::test]
+async fn test_legacy_codec() {
+    let mut codec = KuFileTransferCodec();
+    let protocol = ProtocolVersion::V1.protocol();
+
+    // only a plain read from the start can be put in 1.0.0 terms
+    let mut wire = Cursor::new(Vec::new());
+    let probe = codec
+        .write_request(&protocol, &mut wire, transfer::probe("./file.txt", true))
+        .await;
+    assert_eq!(
+        probe.expect_err("Probes are not in 1.0.0").kind(),
+        std::io::ErrorKind::Unsupported
+    );
+
+    let request = FileRequest {
+        length: 1024,
+        ..transfer::probe("./file.txt", false)
+    };
+    codec
+        .write_request(&protocol, &mut wire, request)
+        .await
+        .expect("Failed to write request");
+    wire.set_position(0);
+    let read = codec
+        .read_request(&protocol, &mut wire)
+        .await
+        .expect("Failed to read request");
+    assert_eq!(read.target_path, "./file.txt");
+    assert_eq!(read.offset, 0);
+    assert!(read.length > 0);
+    // answered with the whole file, however the sender throttles
+    assert!(read.legacy);
+
+    // the response carries the whole file, which gives its size and digest
+    let content = b"legacy content".to_vec();
+    let mut response = FileResponse {
+        status: Ok(()),
+        file_name: read.file_name,
+        src_path: read.target_path,
+        tgt_path: read.save_path,
+        offset: 0,
+        size: content.len() as u64,
+        content: content.clone(),
+        digest: None,
+        entries: None,
+        delta: None,
+        meta: None,
+    };
+    let mut wire = Cursor::new(Vec::new());
+    codec
+        .write_response(&protocol, &mut wire, response.clone())
+        .await
+        .expect("Failed to write response");
+    wire.set_position(0);
+    let read = codec
+        .read_response(&protocol, &mut wire)
+        .await
+        .expect("Failed to read response");
+    assert_eq!(read.content, content);
+    assert_eq!(read.size, content.len() as u64);
+    assert_eq!(
+        read.digest,
+        Some(blake3::hash(&content).to_hex().to_string())
+    );
+
+    // a file larger than the response is refused the way 1.0.0 peers report errors
+    response.size += 1;
+    let read = legacy_round_trip(&mut codec, response.clone()).await;
+    assert!(read.content.is_empty());
+    assert_eq!(
+        read.status.expect_err("Too large").code,
+        ErrorCode::TooLarge
+    );
+
+    // and so are failures, which would otherwise arrive as an empty file
+    response.status = Err(FileError::new(ErrorCode::NotFound, "Missing"));
+    let read = legacy_round_trip(&mut codec, response).await;
+    assert_eq!(
+        read.status.expect_err("Not found").code,
+        ErrorCode::NotFound
+    );
+}
+
+async fn legacy_round_trip(
+    codec: &mut KuFileTransferCodec,
+    response: FileResponse,
+) -> FileResponse {
+    let protocol = ProtocolVersion::V1.protocol();
+    let mut wire = Cursor::new(Vec::new());
+    codec
+        .write_response(&protocol, &mut wire, response)
+        .await
+        .expect("Failed to write response");
+    wire.set_position(0);
+    codec
+        .read_response(&protocol, &mut wire)
+        .await
+        .expect("Failed to read response")
+}
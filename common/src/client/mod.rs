@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::FileMap;
+use crate::{p2p::PeerIdentity, FileMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
@@ -9,6 +9,9 @@ pub struct Client {
     pub id: Uuid,
     pub nickname: String,
     pub files: FileMap,
+    // how peers reach this client, the server only registers it with a valid signature
+    #[serde(default)]
+    pub identity: PeerIdentity,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,4 +19,18 @@ pub struct Peer {
     pub id: Uuid,
     pub source: String,
     pub target: String,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+// What the receiving side does when a file is already at the target path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    // saves the new file under a numbered name, "a (1).txt"
+    KeepBoth,
+    Skip,
+    // leaves the file alone and reports the conflict, so the user can decide and retry
+    Ask,
 }
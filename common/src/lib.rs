@@ -5,7 +5,7 @@ pub mod p2p;
 pub mod tcp;
 pub mod util;
 
-pub use client::{Client, Peer};
+pub use client::{Client, ConflictPolicy, Peer};
 pub use fs::{File, FileMap};
 pub use tcp::{listener::Listener, message, transmitter::Transmitter};
 pub use util::{health, pending};
@@ -1,6 +1,30 @@
-use libp2p::{identity, Multiaddr, PeerId};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    net::Ipv4Addr,
+    path::Path,
+    str::FromStr,
+};
+
+use libp2p::{
+    core::{
+        multiaddr::Protocol,
+        muxing::StreamMuxerBox,
+        transport::{Boxed, OptionalTransport},
+        upgrade,
+    },
+    futures::future::Either,
+    identity, noise, quic, tcp, yamux, Multiaddr, PeerId, Transport,
+};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Identify protocol of the relay and every client, file transfers are versioned separately
+pub const IDENTIFY_PROTOCOL: &str = "/KUDRIVE/0.0.1";
 
+// Derives a key from a seed; anyone knowing the seed holds the key, so it only suits
+// tests and fixed well-known setups. Nodes use `load_or_create_keypair`
 pub fn generate_ed25519(secret_key_seed: &str) -> identity::Keypair {
     let mut bytes = [0u8; 32];
     let seed_bytes = secret_key_seed.as_bytes();
@@ -9,3 +33,200 @@ pub fn generate_ed25519(secret_key_seed: &str) -> identity::Keypair {
 
     identity::Keypair::ed25519_from_bytes(bytes).expect("only errors on wrong length")
 }
+
+// The node's identity kept in `path`, a random one is created there on first run.
+// On unix only the owner may read the file
+pub fn load_or_create_keypair(path: &Path) -> io::Result<identity::Keypair> {
+    let invalid = |e: &dyn Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+    match fs::read(path) {
+        Ok(bytes) => identity::Keypair::from_protobuf_encoding(&bytes).map_err(|e| invalid(&e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = identity::Keypair::generate_ed25519();
+            let bytes = keypair.to_protobuf_encoding().map_err(|e| invalid(&e))?;
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                fs::create_dir_all(parent)?;
+            }
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(path) {
+                Ok(mut file) => {
+                    file.write_all(&bytes)?;
+                    file.sync_all()?;
+                    Ok(keypair)
+                }
+                // another process got there first, its key is the one
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => load_or_create_keypair(path),
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// The libp2p identity a client registers with the server. The signature binds the peer id
+// and addresses to the client and its group, so nobody can claim someone else's peer id
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    pub peer_id: String,
+    // protobuf encoding, the peer id is its hash
+    pub public_key: Vec<u8>,
+    pub addresses: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+impl PeerIdentity {
+    pub fn new(
+        keypair: &identity::Keypair,
+        group: Uuid,
+        id: Uuid,
+        addresses: Vec<Multiaddr>,
+    ) -> Result<Self, String> {
+        let peer_id = keypair.public().to_peer_id().to_string();
+        let addresses: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+        let signature = keypair
+            .sign(&Self::payload(group, id, &peer_id, &addresses))
+            .map_err(|e| format!("Failed to sign the identity: {}", e))?;
+        Ok(Self {
+            peer_id,
+            public_key: keypair.public().encode_protobuf(),
+            addresses,
+            signature,
+        })
+    }
+
+    // The peer id, if its key signed this very registration
+    pub fn verify(&self, group: Uuid, id: Uuid) -> Result<PeerId, String> {
+        let peer_id = self.peer_id()?;
+        let public_key = identity::PublicKey::try_decode_protobuf(&self.public_key)
+            .map_err(|e| format!("Invalid public key: {}", e))?;
+        if public_key.to_peer_id() != peer_id {
+            return Err(format!("Key does not belong to {}", peer_id));
+        }
+        let payload = Self::payload(group, id, &self.peer_id, &self.addresses);
+        if !public_key.verify(&payload, &self.signature) {
+            return Err(format!("Invalid signature for {}", peer_id));
+        }
+        Ok(peer_id)
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId, String> {
+        PeerId::from_str(&self.peer_id).map_err(|e| format!("Invalid peer id: {}", e))
+    }
+
+    // Those that do not parse are left out
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        self.addresses
+            .iter()
+            .filter_map(|address| Multiaddr::from_str(address).ok())
+            .collect()
+    }
+
+    fn payload(group: Uuid, id: Uuid, peer_id: &str, addresses: &[String]) -> Vec<u8> {
+        let mut lines = vec![
+            "kudrive-register".to_string(),
+            group.to_string(),
+            id.to_string(),
+            peer_id.to_string(),
+        ];
+        lines.extend(addresses.iter().cloned());
+        lines.join("\n").into_bytes()
+    }
+}
+
+// Which transports a node listens and dials on, both by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transports {
+    pub tcp: bool,
+    pub quic: bool,
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Self {
+            tcp: true,
+            quic: true,
+        }
+    }
+}
+
+// TCP with noise and yamux alongside QUIC, a transport turned off refuses every address
+pub fn transport(
+    keypair: &identity::Keypair,
+    transports: Transports,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error + Send + Sync>> {
+    if !transports.tcp && !transports.quic {
+        return Err("Both TCP and QUIC are turned off".into());
+    }
+    let tcp = match transports.tcp {
+        true => OptionalTransport::some(
+            tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(noise::Config::new(keypair)?)
+                .multiplex(yamux::Config::default()),
+        ),
+        false => OptionalTransport::none(),
+    };
+    let quic = match transports.quic {
+        true => OptionalTransport::some(quic::tokio::Transport::new(quic::Config::new(keypair))),
+        false => OptionalTransport::none(),
+    };
+    Ok(quic
+        .or_transport(tcp)
+        .map(|output, _| match output {
+            Either::Left((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+            Either::Right((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+        })
+        .boxed())
+}
+
+// Wildcard addresses on `port` for every enabled transport, QUIC on the UDP port of the same number
+pub fn listen_addresses(transports: Transports, port: u16) -> Vec<Multiaddr> {
+    let tcp = Multiaddr::empty()
+        .with(Protocol::from(Ipv4Addr::UNSPECIFIED))
+        .with(Protocol::Tcp(port));
+    let mut addresses = Vec::new();
+    if transports.quic {
+        addresses.push(quic_address(&tcp).expect("a TCP address"));
+    }
+    if transports.tcp {
+        addresses.push(tcp);
+    }
+    addresses
+}
+
+// The QUIC counterpart of a TCP address, "/ip4/1.2.3.4/tcp/4001/p2p/.." becomes
+// "/ip4/1.2.3.4/udp/4001/quic-v1/p2p/.."
+pub fn quic_address(address: &Multiaddr) -> Option<Multiaddr> {
+    if !address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::Tcp(_)))
+    {
+        return None;
+    }
+    Some(
+        address
+            .iter()
+            .fold(Multiaddr::empty(), |quic, protocol| match protocol {
+                Protocol::Tcp(port) => quic.with(Protocol::Udp(port)).with(Protocol::QuicV1),
+                protocol => quic.with(protocol),
+            }),
+    )
+}
+
+pub fn is_quic(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| protocol == Protocol::QuicV1)
+}
+
+// Addresses to try one after another, QUIC first as it holds up better through NATs
+// and does not stall every stream on a lost packet
+pub fn prefer_quic(addresses: impl IntoIterator<Item = Multiaddr>) -> Vec<Multiaddr> {
+    let mut addresses: Vec<Multiaddr> = addresses.into_iter().collect();
+    addresses.sort_by_key(|address| !is_quic(address));
+    addresses
+}
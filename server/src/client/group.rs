@@ -6,22 +6,30 @@ use uuid::Uuid;
 
 use crate::event::ServerEvent;
 
+use super::members::Members;
+
 #[derive(Debug, Clone)]
 pub struct ClientGroup {
     clients: HashMap<Uuid, Client>,
     senders: HashMap<Uuid, Sender<ServerEvent>>,
+    // the relay serves whoever is in here
+    members: Members,
 }
 
 impl ClientGroup {
-    pub fn new() -> Self {
+    pub fn new(members: Members) -> Self {
         Self {
             clients: HashMap::new(),
             senders: HashMap::new(),
+            members,
         }
     }
 
     pub fn insert(&mut self, client: Client, sender: Sender<ServerEvent>) {
-        let Client { id, .. } = client;
+        let Client { id, group, .. } = client;
+        if let Ok(peer) = client.identity.peer_id() {
+            self.members.insert(peer, group);
+        }
         self.clients.insert(id, client);
         self.senders.insert(id, sender);
     }
@@ -32,7 +40,11 @@ impl ClientGroup {
     }
 
     pub fn remove(&mut self, id: Uuid) {
-        self.clients.remove(&id);
+        if let Some(client) = self.clients.remove(&id) {
+            if let Ok(peer) = client.identity.peer_id() {
+                self.members.remove(&peer);
+            }
+        }
         self.senders.remove(&id);
     }
 
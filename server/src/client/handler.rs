@@ -16,12 +16,13 @@ use tokio::{
 
 use crate::event::{MetaEvent, PeerEvent, ServerEvent};
 
-use super::group::ClientGroup;
+use super::{group::ClientGroup, members::Members};
 
 pub struct ClientHandler {
     client: Option<Client>,
     group: Option<Arc<RwLock<ClientGroup>>>,
     meta: Sender<MetaEvent>,
+    members: Members,
     sender: Sender<ServerEvent>,
     receiver: Receiver<ServerEvent>,
     transmitter: Transmitter,
@@ -29,7 +30,7 @@ pub struct ClientHandler {
 }
 
 impl ClientHandler {
-    pub fn new(stream: TcpStream, meta: mpsc::Sender<MetaEvent>) -> Self {
+    pub fn new(stream: TcpStream, meta: mpsc::Sender<MetaEvent>, members: Members) -> Self {
         let stream = Arc::new(Mutex::new(stream));
         let (sender, receiver) = mpsc::channel::<ServerEvent>(1024 * 1024);
 
@@ -46,6 +47,7 @@ impl ClientHandler {
             client: None,
             group: None,
             meta,
+            members,
             sender,
             receiver,
             transmitter,
@@ -57,7 +59,12 @@ impl ClientHandler {
         self.sender.clone()
     }
 
-    async fn register(&mut self, client: Client) {
+    // Only a client whose key signed its registration, with the key it first registered, gets
+    // a group
+    async fn register(&mut self, client: Client) -> Result<(), String> {
+        let peer_id = client.identity.verify(client.group, client.id)?;
+        self.members.pin(client.id, peer_id)?;
+        println!("Client {} is peer {}", client.id, peer_id);
         self.client = Some(client.clone());
 
         let event = MetaEvent::Register {
@@ -68,6 +75,7 @@ impl ClientHandler {
         self.meta.send(event).await.unwrap();
 
         self.health_checker.check().await;
+        Ok(())
     }
 
     async fn update(&mut self, file_map: FileMap) {
@@ -156,7 +164,10 @@ impl ClientHandler {
                 }
                 ClientMessage::Register { client } => {
                     println!("Registering client: {:?}", client);
-                    self.register(client).await;
+                    if let Err(e) = self.register(client).await {
+                        println!("Rejecting client: {}", e);
+                        return Err(TryRecvError::Disconnected);
+                    }
                 }
                 ClientMessage::FileMapUpdate { file_map } => {
                     println!("Updating file map: {:?}", file_map);
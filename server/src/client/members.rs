@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use libp2p::PeerId;
+use uuid::Uuid;
+
+// Peer ids of the registered clients and their groups. The relay checks it from inside
+// the swarm, so it is behind a plain mutex rather than the async locks of the groups
+#[derive(Debug, Clone, Default)]
+pub struct Members {
+    peers: Arc<Mutex<HashMap<PeerId, Uuid>>>,
+    // the peer id each client first registered with
+    pinned: Arc<Mutex<HashMap<Uuid, PeerId>>>,
+    // where the pins are kept across restarts, in memory only without it
+    pins_path: Option<PathBuf>,
+}
+
+impl Members {
+    // Pins saved at `path` by an earlier run, the file is created on the first pin
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut pinned = HashMap::new();
+        if path.exists() {
+            let saved: HashMap<Uuid, String> = serde_json::from_slice(&std::fs::read(path)?)?;
+            for (client, peer) in saved {
+                let peer = PeerId::from_str(&peer)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                pinned.insert(client, peer);
+            }
+        }
+        Ok(Self {
+            pinned: Arc::new(Mutex::new(pinned)),
+            pins_path: Some(path.to_path_buf()),
+            ..Default::default()
+        })
+    }
+
+    pub fn insert(&self, peer: PeerId, group: Uuid) {
+        self.peers().insert(peer, group);
+    }
+
+    pub fn remove(&self, peer: &PeerId) {
+        self.peers().remove(peer);
+    }
+
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.peers().contains_key(peer)
+    }
+
+    // Trust on first use: a client keeps the peer id it first registered with, so its UUID
+    // alone cannot register another key. A client that lost its key file stays locked out
+    // until it is unpinned, see `unpin`
+    pub fn pin(&self, client: Uuid, peer: PeerId) -> Result<(), String> {
+        let mut pinned = self.pinned.lock().expect("Members poisoned");
+        if let Some(known) = pinned.get(&client) {
+            if *known != peer {
+                return Err(format!("Client {} registered as peer {}", client, known));
+            }
+            return Ok(());
+        }
+        pinned.insert(client, peer);
+        self.save(&pinned);
+        Ok(())
+    }
+
+    // Forgets the client's key, the next one it registers with is pinned instead. The
+    // server does this for every `--unpin` it is started with
+    pub fn unpin(&self, client: &Uuid) -> Option<PeerId> {
+        let mut pinned = self.pinned.lock().expect("Members poisoned");
+        let peer = pinned.remove(client)?;
+        self.save(&pinned);
+        Some(peer)
+    }
+
+    pub fn same_group(&self, a: &PeerId, b: &PeerId) -> bool {
+        let peers = self.peers();
+        matches!((peers.get(a), peers.get(b)), (Some(a), Some(b)) if a == b)
+    }
+
+    // A pin that could not be saved still holds until the server restarts
+    fn save(&self, pinned: &HashMap<Uuid, PeerId>) {
+        let Some(path) = &self.pins_path else {
+            return;
+        };
+        let saved: HashMap<&Uuid, String> = pinned
+            .iter()
+            .map(|(client, peer)| (client, peer.to_string()))
+            .collect();
+        let result = serde_json::to_vec_pretty(&saved)
+            .map_err(io::Error::from)
+            .and_then(|json| std::fs::write(path, json));
+        if let Err(e) = result {
+            tracing::warn!(path=%path.display(), "Failed to save client pins: {}", e);
+        }
+    }
+
+    fn peers(&self) -> MutexGuard<'_, HashMap<PeerId, Uuid>> {
+        self.peers.lock().expect("Members poisoned")
+    }
+}
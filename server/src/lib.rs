@@ -1,5 +1,6 @@
 pub mod client;
 pub mod event;
+pub mod relay;
 
 use event::{MetaEvent, PeerEvent, ServerEvent};
 use kudrive_common::Client;
@@ -13,21 +14,35 @@ use tokio::{
 };
 use uuid::Uuid;
 
-pub use client::{group::ClientGroup, handler::ClientHandler};
+pub use client::{group::ClientGroup, handler::ClientHandler, members::Members};
+pub use relay::GroupRelay;
 
 pub struct Server {
     groups: HashMap<Uuid, Arc<RwLock<ClientGroup>>>,
+    members: Members,
 }
 
 impl Server {
     pub async fn new() -> Self {
+        Self::with_members(Members::default()).await
+    }
+
+    // A server whose clients are pinned in `members`, see `Members::load`
+    pub async fn with_members(members: Members) -> Self {
         Self {
             groups: HashMap::new(),
+            members,
         }
     }
 
+    // Registered peers, for the relay to check against
+    pub fn members(&self) -> Members {
+        self.members.clone()
+    }
+
     async fn spawn(&mut self, stream: TcpStream, sender: mpsc::Sender<MetaEvent>) {
-        let handler = Arc::new(Mutex::new(ClientHandler::new(stream, sender)));
+        let members = self.members.clone();
+        let handler = Arc::new(Mutex::new(ClientHandler::new(stream, sender, members)));
 
         tokio::spawn(async move {
             let handler = handler.clone();
@@ -44,10 +59,11 @@ impl Server {
     async fn register(&mut self, client: Client, sender: mpsc::Sender<ServerEvent>) {
         // get target group
         let Client { group, .. } = client;
+        let members = &self.members;
         let group = self
             .groups
             .entry(group)
-            .or_insert_with(|| Arc::new(RwLock::new(ClientGroup::new())));
+            .or_insert_with(|| Arc::new(RwLock::new(ClientGroup::new(members.clone()))));
 
         // send client its group
         let event = ServerEvent::PeerEvent {
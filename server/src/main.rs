@@ -1,26 +1,66 @@
-use kudrive_server::Server;
+use kudrive_common::p2p::{load_or_create_keypair, Transports};
+use kudrive_server::{Members, Server};
 pub mod p2p;
 use clap::Parser;
+use std::path::PathBuf;
+use uuid::Uuid;
 
 #[derive(Debug, Parser)]
 struct Opts {
     #[clap(long)]
     test_p2p: bool,
+    // the relay listens on both transports unless one is turned off
+    #[clap(long, env = "RELAY_NO_TCP")]
+    no_tcp: bool,
+    #[clap(long, env = "RELAY_NO_QUIC")]
+    no_quic: bool,
+    // the relay's libp2p key, created on first run; clients need the peer id it prints
+    #[clap(long, env = "RELAY_IDENTITY", default_value = "relay.key")]
+    identity: PathBuf,
+    // the key each client first registered with, kept across restarts
+    #[clap(long, env = "RELAY_PINS", default_value = "pins.json")]
+    pins: PathBuf,
+    // lets a client that lost its key file register with a new one
+    #[clap(long, value_name = "CLIENT_UUID")]
+    unpin: Vec<Uuid>,
 }
 
 #[tokio::main]
 async fn main() {
     let opts: Opts = Opts::parse();
+    let transports = Transports {
+        tcp: !opts.no_tcp,
+        quic: !opts.no_quic,
+    };
+    let keypair = load_or_create_keypair(&opts.identity).expect("Failed to load the relay key");
+    println!("Relay peer id: {}", keypair.public().to_peer_id());
     if opts.test_p2p {
-        let _ = p2p::P2PTransport::run(4001, false).await;
+        // no control server to register with, so the relay is open
+        let _ = p2p::P2PTransport::run(4001, transports, keypair, None, false).await;
     } else {
-        tokio::task::spawn(async {
+        let pins = Members::load(&opts.pins).expect("Failed to load the client pins");
+        for client in &opts.unpin {
+            match pins.unpin(client) {
+                Some(peer) => println!("Unpinned client {} from peer {}", client, peer),
+                None => println!("Client {} was not pinned", client),
+            }
+        }
+        let mut server = Server::with_members(pins).await;
+        let members = Some(server.members());
+
+        tokio::task::spawn(async move {
             let (tx, exit_rx) = tokio::sync::oneshot::channel();
-            let _ = p2p::P2PTransport::run_with_restart(4001, 60 * 60, exit_rx).await;
+            let _ = p2p::P2PTransport::run_with_restart(
+                4001,
+                transports,
+                keypair,
+                members,
+                60 * 60,
+                exit_rx,
+            )
+            .await;
         });
 
-        let mut server = Server::new().await;
-
         server.start().await.unwrap();
 
         println!("Done!");
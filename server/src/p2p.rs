@@ -1,12 +1,11 @@
 use futures::StreamExt;
-use kudrive_common::p2p::generate_ed25519;
+use kudrive_common::p2p::{listen_addresses, transport, Transports, IDENTIFY_PROTOCOL};
+use kudrive_server::{GroupRelay, Members};
 use libp2p::{
-    core::{multiaddr::Protocol, Multiaddr},
-    dcutr, identify, identity, noise, ping, relay,
+    dcutr, identify, identity, ping, relay,
     swarm::{NetworkBehaviour, Swarm, SwarmEvent},
-    tcp, yamux,
 };
-use std::{error::Error, net::Ipv4Addr, sync::Arc};
+use std::{error::Error, sync::Arc, time::Duration};
 use tokio::{
     io::{self, AsyncBufReadExt as _},
     select,
@@ -14,11 +13,13 @@ use tokio::{
 };
 use tracing_subscriber::EnvFilter;
 
-const RELAY_ID: &str = "0";
+// File transfers stream chunks through circuits, so lift the 128 KiB / 2 min defaults
+const RELAY_MAX_CIRCUIT_BYTES: u64 = 0;
+const RELAY_MAX_CIRCUIT_DURATION: u64 = 60 * 60;
 
 #[derive(NetworkBehaviour)]
 struct Behaviour {
-    relay: relay::Behaviour,
+    relay: GroupRelay,
     ping: ping::Behaviour,
     identify: identify::Behaviour,
     dcutr: dcutr::Behaviour,
@@ -36,11 +37,16 @@ pub struct P2PTransport {
 }
 
 impl P2PTransport {
-    fn new(port: u16) -> Self {
+    fn new(
+        port: u16,
+        transports: Transports,
+        keypair: identity::Keypair,
+        members: Option<Members>,
+    ) -> Self {
         let (command_tx, command_rx) = mpsc::channel(32);
         let port_clone = port;
         tokio::spawn(async move {
-            let _ = Self::start_swarm(command_rx, port_clone).await;
+            let _ = Self::start_swarm(command_rx, port_clone, transports, keypair, members).await;
         });
 
         Self {
@@ -50,15 +56,24 @@ impl P2PTransport {
         }
     }
 
-    pub async fn run(port: u16, use_cli: bool) {
+    // Without `members` the relay serves every peer, which only suits tests
+    pub async fn run(
+        port: u16,
+        transports: Transports,
+        keypair: identity::Keypair,
+        members: Option<Members>,
+        use_cli: bool,
+    ) {
         let _ = tracing_subscriber::fmt()
             .with_env_filter(EnvFilter::from_default_env())
             .try_init();
         if use_cli {
-            let transport: P2PTransport = Self::new(port);
+            let transport: P2PTransport = Self::new(port, transports, keypair, members);
             transport.run_with_cli().await;
         } else {
-            let _ = Self::new(port);
+            // the swarm runs on its own task, which ends with the process
+            let _transport = Self::new(port, transports, keypair, members);
+            std::future::pending::<()>().await;
         }
     }
 
@@ -91,6 +106,9 @@ impl P2PTransport {
 
     pub async fn run_with_restart(
         port: u16,
+        transports: Transports,
+        keypair: identity::Keypair,
+        members: Option<Members>,
         restart_interval: u64,
         mut exit_rx: oneshot::Receiver<()>,
     ) {
@@ -101,7 +119,15 @@ impl P2PTransport {
         loop {
             tracing::info!("Starting swarm on port {}...", port);
             let (command_tx, command_rx) = mpsc::channel(32);
-            let swarm_handle = match Self::start_swarm(command_rx, port).await {
+            let swarm_handle = match Self::start_swarm(
+                command_rx,
+                port,
+                transports,
+                keypair.clone(),
+                members.clone(),
+            )
+            .await
+            {
                 Ok(handle) => handle,
                 Err(e) => {
                     tracing::error!("Failed to start swarm: {}", e);
@@ -136,37 +162,34 @@ impl P2PTransport {
     async fn start_swarm(
         event_rx: mpsc::Receiver<P2PCommand>,
         port: u16,
+        transports: Transports,
+        keypair: identity::Keypair,
+        members: Option<Members>,
     ) -> Result<Arc<Mutex<SwarmHandle>>, Box<dyn Error>> {
-        let local_key: identity::Keypair = generate_ed25519(RELAY_ID);
+        let config = relay::Config {
+            max_circuit_bytes: RELAY_MAX_CIRCUIT_BYTES,
+            max_circuit_duration: Duration::from_secs(RELAY_MAX_CIRCUIT_DURATION),
+            ..Default::default()
+        };
 
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(local_key)
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
             .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )?
+            .with_other_transport(|key| transport(key, transports))?
             .with_behaviour(|key| Behaviour {
-                relay: relay::Behaviour::new(key.public().to_peer_id(), Default::default()),
+                relay: GroupRelay::new(key.public().to_peer_id(), config, members),
                 ping: ping::Behaviour::new(ping::Config::new()),
                 identify: identify::Behaviour::new(identify::Config::new(
-                    "/KUDRIVE/0.0.1".to_string(),
+                    IDENTIFY_PROTOCOL.to_string(),
                     key.public(),
                 )),
                 dcutr: dcutr::Behaviour::new(key.public().to_peer_id()),
             })?
             .build();
 
-        let listen_addr_tcp = Multiaddr::empty()
-            .with(Protocol::from(Ipv4Addr::UNSPECIFIED))
-            .with(Protocol::Tcp(port));
-        swarm.listen_on(listen_addr_tcp.clone())?;
-
-        // let listen_addr_quic = Multiaddr::empty()
-        //     .with(Protocol::from(Ipv4Addr::UNSPECIFIED))
-        //     .with(Protocol::Udp(port))
-        //     .with(Protocol::QuicV1);
-        // swarm.listen_on(listen_addr_quic.clone())?;
+        // clients reach the relay on TCP and on QUIC over the UDP port of the same number
+        for address in listen_addresses(transports, port) {
+            swarm.listen_on(address)?;
+        }
 
         let swarm_handle = Arc::new(Mutex::new(SwarmHandle::new(swarm, event_rx)));
 
@@ -227,6 +250,7 @@ impl SwarmHandle {
                             {
                                 swarm.add_external_address(observed_addr.clone());
                             }
+                            Self::log_relay(&event);
                             // tracing::info!("{:?}", event);
                         }
                         SwarmEvent::NewListenAddr { address, .. } => {
@@ -247,6 +271,25 @@ impl SwarmHandle {
         }
     }
 
+    // Circuits across groups are denied by `GroupRelay` before they are opened
+    fn log_relay(event: &BehaviourEvent) {
+        let BehaviourEvent::Relay(event) = event else {
+            return;
+        };
+        match event {
+            relay::Event::ReservationReqDenied { src_peer_id } => {
+                tracing::info!(peer=%src_peer_id, "Reservation denied");
+            }
+            relay::Event::CircuitReqDenied {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                tracing::info!(src=%src_peer_id, dst=%dst_peer_id, "Circuit denied");
+            }
+            _ => {}
+        }
+    }
+
     pub async fn shutdown(&mut self) {
         self.event_rx.take();
         self.swarm.take();
@@ -0,0 +1,194 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    relay,
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+
+use crate::Members;
+
+// The relay, kept to registered clients and to circuits inside a group. Without `members` it
+// serves every peer, which only suits tests.
+//
+// The rate limiters only see a circuit's source, so its destination is checked once the relay
+// has picked the destination's connection for it: the request is dropped before that
+// connection hears of it, which fails that circuit alone at its source.
+//
+// The relay keeps the slot it set aside for a dropped circuit until the source's or the
+// destination's connection closes. Until then the source may open no other circuit, so each
+// source holds at most one such slot.
+//
+// libp2p-relay 0.18 hands that request on under the source's peer id rather than the
+// destination's, which is how the source is told apart. The server pins that version and
+// `test_deny_cross_group_circuit` fails if it changes.
+pub struct GroupRelay {
+    relay: relay::Behaviour,
+    members: Option<Members>,
+    connections: HashMap<ConnectionId, PeerId>,
+    // sources of a dropped circuit, with the destination's connection it was meant for
+    held: Arc<Mutex<HashSet<(PeerId, ConnectionId)>>>,
+}
+
+impl GroupRelay {
+    pub fn new(local_peer_id: PeerId, mut config: relay::Config, members: Option<Members>) -> Self {
+        let held: Arc<Mutex<HashSet<(PeerId, ConnectionId)>>> = Default::default();
+        // only registered clients may reserve a slot or open a circuit
+        if let Some(members) = &members {
+            let reserving = members.clone();
+            config.reservation_rate_limiters.push(Box::new(
+                move |peer: PeerId, _: &Multiaddr, _: Instant| reserving.contains(&peer),
+            ));
+            let dialing = members.clone();
+            let holding = held.clone();
+            config.circuit_src_rate_limiters.push(Box::new(
+                move |peer: PeerId, _: &Multiaddr, _: Instant| {
+                    dialing.contains(&peer)
+                        && !holding.lock().unwrap().iter().any(|(src, _)| *src == peer)
+                },
+            ));
+        }
+        Self {
+            relay: relay::Behaviour::new(local_peer_id, config),
+            members,
+            connections: HashMap::new(),
+            held,
+        }
+    }
+
+    // The destination of a circuit the relay is about to open, when it leaves its source's
+    // group. Only that request is handed to a connection of another peer than its source.
+    fn crossing(&self, src: PeerId, connection: ConnectionId) -> Option<PeerId> {
+        let members = self.members.as_ref()?;
+        let dst = *self.connections.get(&connection)?;
+        (dst != src && !members.same_group(&src, &dst)).then_some(dst)
+    }
+}
+
+impl NetworkBehaviour for GroupRelay {
+    type ConnectionHandler = THandler<relay::Behaviour>;
+    type ToSwarm = relay::Event;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.relay
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.relay.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.relay.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.relay.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+            port_use,
+        )
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                self.connections
+                    .insert(established.connection_id, established.peer_id);
+            }
+            FromSwarm::ConnectionClosed(closed) => {
+                self.connections.remove(&closed.connection_id);
+                // the relay drops a circuit's slot with either end's connection
+                let gone = closed.remaining_established == 0;
+                self.held.lock().unwrap().retain(|&(src, connection)| {
+                    connection != closed.connection_id && !(gone && src == closed.peer_id)
+                });
+            }
+            _ => {}
+        }
+        self.relay.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.relay
+            .on_connection_handler_event(peer_id, connection_id, event);
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        let (peer_id, connection, event) = match self.relay.poll(cx) {
+            Poll::Ready(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event,
+            }) => (peer_id, connection, event),
+            poll => return poll,
+        };
+        let Some(dst) = self.crossing(peer_id, connection) else {
+            return Poll::Ready(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event,
+            });
+        };
+        // dropping the request closes its stream, which fails the circuit at its source
+        drop(event);
+        self.held.lock().unwrap().insert((peer_id, connection));
+        Poll::Ready(ToSwarm::GenerateEvent(relay::Event::CircuitReqDenied {
+            src_peer_id: peer_id,
+            dst_peer_id: dst,
+        }))
+    }
+}
@@ -0,0 +1,65 @@
+use kudrive_server::Members;
+use libp2p::PeerId;
+use uuid::Uuid;
+
+#[test]
+fn test_pin_first_peer_id() {
+    let members = Members::default();
+    let (client, peer) = (Uuid::new_v4(), PeerId::random());
+    assert!(members.pin(client, peer).is_ok());
+    // registering again with the same key is fine
+    assert!(members.pin(client, peer).is_ok());
+
+    // another key for a known UUID is refused
+    let other = PeerId::random();
+    let error = members
+        .pin(client, other)
+        .expect_err("Another key for the client");
+    assert!(error.contains(&peer.to_string()), "{}", error);
+    assert!(members.pin(Uuid::new_v4(), other).is_ok());
+}
+
+#[test]
+fn test_pins_across_restarts() {
+    let path = std::env::temp_dir().join(format!("kudrive-pins-{}.json", Uuid::new_v4()));
+    let (client, peer) = (Uuid::new_v4(), PeerId::random());
+    let members = Members::load(&path).expect("Failed to load pins");
+    assert!(members.pin(client, peer).is_ok());
+
+    // a restarted server still refuses another key
+    let restarted = Members::load(&path).expect("Failed to load pins");
+    let lost = PeerId::random();
+    assert!(restarted.pin(client, lost).is_err());
+
+    // until the client is unpinned, then the new key is kept
+    assert_eq!(restarted.unpin(&client), Some(peer));
+    assert_eq!(restarted.unpin(&client), None);
+    assert!(restarted.pin(client, lost).is_ok());
+    let restarted = Members::load(&path).expect("Failed to load pins");
+    assert!(restarted.pin(client, peer).is_err());
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_same_group() {
+    let members = Members::default();
+    let (group, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+    members.insert(a, group);
+    members.insert(b, group);
+    members.insert(c, other);
+    assert!(members.contains(&a));
+    assert!(members.same_group(&a, &b));
+    assert!(!members.same_group(&a, &c));
+
+    // an unknown peer shares no group, not even with itself
+    let stranger = PeerId::random();
+    assert!(!members.contains(&stranger));
+    assert!(!members.same_group(&stranger, &stranger));
+
+    // a client that left is no longer a member
+    members.remove(&b);
+    assert!(!members.contains(&b));
+    assert!(!members.same_group(&a, &b));
+}